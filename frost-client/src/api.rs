@@ -3,22 +3,160 @@
 use frost_core::{Ciphersuite, SigningPackage};
 use frost_rerandomized::Randomizer;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 use thiserror::Error;
 pub use uuid::Uuid;
+use xeddsa::{xed25519, Verify as _};
 use zeroize::Zeroize;
 
-/// The maximum size of a message.
+/// The maximum size of a message. Servers may be configured with a lower
+/// limit, see [`InfoOutput`].
 pub const MAX_MSG_SIZE: usize = 65535;
 
+/// The maximum length, in bytes, of the reason given when declining or
+/// aborting a session.
+pub const MAX_REASON_LEN: usize = 1024;
+
+/// The maximum length, in bytes, of each field of [`SessionMetadata`].
+pub const MAX_METADATA_LEN: usize = 1024;
+
+/// The maximum time the server will wait for messages in a `receive` call,
+/// in milliseconds. Longer timeouts requested by clients are capped to it.
+pub const MAX_WAIT_TIMEOUT_MS: u64 = 30_000;
+
+/// The versions of the API supported by this crate, from oldest to newest.
+/// Each version is served under a path prefix with its name (e.g. `/v1/send`).
+pub const API_VERSIONS: &[&str] = &["v1"];
+
+/// Optional features that servers may support, reported in
+/// [`InfoOutput::features`].
+pub mod features {
+    /// `receive` supports long polling with `wait_timeout_ms`.
+    pub const LONG_POLLING: &str = "long_polling";
+    /// Messages have sequence numbers and are kept until acknowledged.
+    pub const ACKNOWLEDGEMENTS: &str = "acknowledgements";
+    /// `send` ignores retried messages with the same `msg_id`.
+    pub const IDEMPOTENT_SEND: &str = "idempotent_send";
+    /// `get_session_info` reports the status of the session and its
+    /// participants.
+    pub const SESSION_STATUS: &str = "session_status";
+    /// Participants can accept and decline sessions.
+    pub const INVITATIONS: &str = "invitations";
+    /// Sessions can be aborted by their members.
+    pub const ABORT: &str = "abort";
+    /// The admin API is available.
+    pub const ADMIN: &str = "admin";
+    /// Members can broadcast a message to the whole session, attested by the
+    /// server.
+    pub const BROADCAST: &str = "broadcast";
+    /// Sessions can carry [`SessionMetadata`](super::SessionMetadata).
+    pub const SESSION_METADATA: &str = "session_metadata";
+    /// Login signatures are over [`login_payload()`](super::login_payload).
+    pub const SERVER_BOUND_LOGIN: &str = "server_bound_login";
+    /// Access tokens can be renewed with `refresh`.
+    pub const REFRESH: &str = "refresh";
+
+    /// All the features supported by this crate.
+    pub const ALL: &[&str] = &[
+        LONG_POLLING,
+        ACKNOWLEDGEMENTS,
+        IDEMPOTENT_SEND,
+        SESSION_STATUS,
+        INVITATIONS,
+        ABORT,
+        ADMIN,
+        BROADCAST,
+        SESSION_METADATA,
+        SERVER_BOUND_LOGIN,
+        REFRESH,
+    ];
+}
+
+/// Information about the server and the limits it enforces, so that clients
+/// can adapt to them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InfoOutput {
+    /// The server version.
+    pub version: String,
+    /// The API versions supported by the server (see [`API_VERSIONS`]).
+    /// Empty if the server only supports the unversioned API.
+    #[serde(default)]
+    pub api_versions: Vec<String>,
+    /// The optional features supported by the server (see [`features`]).
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// The maximum size of a message.
+    pub max_msg_size: usize,
+    /// The maximum wait timeout in `receive` calls, in milliseconds.
+    pub max_wait_timeout_ms: u64,
+    /// How long a session stays open without activity, in seconds.
+    pub session_timeout_secs: u64,
+    /// How long a challenge can be replied to, in seconds.
+    pub challenge_timeout_secs: u64,
+    /// How long an access token lasts, in seconds.
+    pub access_token_timeout_secs: u64,
+    /// The maximum number of open sessions a single user can coordinate.
+    /// 0 means no limit.
+    pub max_sessions_per_coordinator: usize,
+    /// The maximum number of messages queued for a single recipient in a
+    /// session. 0 means no limit.
+    pub max_queued_messages_per_recipient: usize,
+    /// The maximum total size of the messages queued for a single recipient
+    /// in a session. 0 means no limit.
+    pub max_queued_bytes_per_recipient: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChallengeOutput {
     pub challenge: Uuid,
 }
 
+/// The context string of login signatures, which makes them unusable for
+/// other purposes.
+pub const LOGIN_CONTEXT: &[u8] = b"frostd login v1";
+
+/// Return the identity of a server given its URL or `host[:port]`: its host
+/// in lowercase and its port, which defaults to 443 (e.g.
+/// `frost.example.com:443`).
+pub fn server_identity(url: &str) -> String {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host_port)| host_port)
+        .to_ascii_lowercase();
+    // IPv6 addresses are enclosed in brackets, so a colon after the closing
+    // bracket (if any) is the port separator.
+    let has_port = authority.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty() && !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+    });
+    if has_port {
+        authority
+    } else {
+        format!("{authority}:443")
+    }
+}
+
+/// Return the payload users sign to log in to the server with the given
+/// identity (see [`server_identity()`]) using the given challenge.
+///
+/// Including the server identity prevents a malicious server from logging in
+/// to another server as its users, by relaying the other server's challenges
+/// and the signatures of its users.
+pub fn login_payload(server_identity: &str, challenge: &Uuid) -> Vec<u8> {
+    let mut payload = LOGIN_CONTEXT.to_vec();
+    payload.extend_from_slice(&(server_identity.len() as u64).to_be_bytes());
+    payload.extend_from_slice(server_identity.as_bytes());
+    payload.extend_from_slice(challenge.as_bytes());
+    payload
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginArgs {
     pub challenge: Uuid,
     pub pubkey: PublicKey,
+    /// The XEdDSA signature of the [`login_payload()`] (or, for servers that
+    /// do not support [`features::SERVER_BOUND_LOGIN`], of the challenge).
     #[serde(
         serialize_with = "serdect::slice::serialize_hex_lower_or_bin",
         deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
@@ -28,13 +166,107 @@ pub struct LoginArgs {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginOutput {
-    pub access_token: Uuid,
+    /// The access token to use in authenticated calls, unless the server
+    /// issued a `signed_access_token` instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<Uuid>,
+    /// A self-contained access token signed by the server, issued instead of
+    /// `access_token` by servers configured to do so.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_access_token: Option<String>,
+}
+
+impl LoginOutput {
+    /// Return the token to use in authenticated calls. It's empty (and thus
+    /// rejected by the server) if the server issued none.
+    pub fn token(&self) -> String {
+        self.signed_access_token
+            .clone()
+            .or_else(|| self.access_token.map(|token| token.to_string()))
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreateNewSessionArgs {
     pub pubkeys: Vec<PublicKey>,
     pub message_count: u8,
+    /// Information about the session for the participants. Ignored by
+    /// servers that do not support it.
+    #[serde(default)]
+    pub metadata: SessionMetadata,
+}
+
+/// Information about a session, attached by the coordinator when creating
+/// it, so that participants know what it is for before joining.
+///
+/// It is not encrypted, so it is visible to the server; all fields are
+/// optional and can be left out if that is a concern.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// The serialized verifying key of the group the session is for.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_option")]
+    pub group: Option<Vec<u8>>,
+    /// The ID of the ciphersuite being used, e.g. `FROST-ED25519-SHA512-v1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciphersuite: Option<String>,
+    /// What the session is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<SessionKind>,
+    /// A human-readable description of the session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl SessionMetadata {
+    /// Whether no metadata was specified.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a session is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    /// Signing one or more messages.
+    Signing,
+    /// Generating a new group key with distributed key generation.
+    Dkg,
+    /// Refreshing the shares of an existing group.
+    Refresh,
+    /// A kind unknown to this version.
+    #[serde(other)]
+    Other,
+}
+
+impl std::fmt::Display for SessionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SessionKind::Signing => "signing",
+            SessionKind::Dkg => "DKG",
+            SessionKind::Refresh => "refresh",
+            SessionKind::Other => "unknown",
+        })
+    }
+}
+
+/// Serialize an optional byte vector as an optional hex string.
+mod hex_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(v) => s.serialize_some(&hex::encode(v)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|v| hex::decode(v).map_err(serde::de::Error::custom))
+            .transpose()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -57,6 +289,81 @@ pub struct GetSessionInfoOutput {
     pub message_count: u8,
     pub pubkeys: Vec<PublicKey>,
     pub coordinator_pubkey: PublicKey,
+    /// When the session was created, as a UNIX timestamp in seconds. 0 if
+    /// not reported by the server.
+    #[serde(default)]
+    pub created_at: u64,
+    /// When a message was last sent or received in the session, as a UNIX
+    /// timestamp in seconds. 0 if not reported by the server.
+    #[serde(default)]
+    pub last_activity: u64,
+    /// When the session will expire unless there is activity, as a UNIX
+    /// timestamp in seconds. 0 if not reported by the server.
+    #[serde(default)]
+    pub expires_at: u64,
+    /// The status of each participant, in the same order as `pubkeys`. Empty
+    /// if not reported by the server.
+    #[serde(default)]
+    pub participants: Vec<ParticipantStatus>,
+    /// The metadata attached to the session by the coordinator, if any.
+    #[serde(default)]
+    pub metadata: SessionMetadata,
+}
+
+/// The status of a participant in a session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParticipantStatus {
+    pub pubkey: PublicKey,
+    /// When the participant last made a call related to the session, as a
+    /// UNIX timestamp in seconds, or None if they never did.
+    pub last_seen: Option<u64>,
+    /// The number of messages sent by the participant in the session.
+    pub messages_sent: u64,
+    /// The number of messages waiting to be received by the participant.
+    pub messages_pending: usize,
+    /// Whether the participant accepted or declined to take part in the
+    /// session.
+    #[serde(default)]
+    pub invitation: InvitationStatus,
+    /// The reason given by the participant when declining, if any.
+    #[serde(default)]
+    pub decline_reason: Option<String>,
+}
+
+/// The response of a participant to the invitation to a session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvitationStatus {
+    /// The participant did not respond yet.
+    #[default]
+    Pending,
+    /// The participant accepted, either explicitly or by sending a message.
+    Accepted,
+    /// The participant declined, and can no longer take part in the session.
+    Declined,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcceptSessionArgs {
+    pub session_id: Uuid,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeclineSessionArgs {
+    pub session_id: Uuid,
+    /// An optional reason for declining, shown to the coordinator.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AbortSessionArgs {
+    pub session_id: Uuid,
+    pub reason: AbortReason,
+    /// An optional description of why the session is being aborted, shown to
+    /// the other members.
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Zeroize)]
@@ -86,6 +393,14 @@ pub struct SendArgs {
         deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
     )]
     pub msg: Vec<u8>,
+    /// An ID generated by the client (e.g. a random UUID) to make the call
+    /// idempotent: if the user already sent a message with the same ID in
+    /// the session, the call succeeds without sending it again. This allows
+    /// safely retrying calls whose response was lost. Only the latest IDs of
+    /// each user are remembered, so the call must be retried before sending
+    /// many other messages.
+    #[serde(default)]
+    pub msg_id: Option<Uuid>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -96,12 +411,125 @@ pub struct Msg {
         deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
     )]
     pub msg: Vec<u8>,
+    /// The sequence number of the message. It starts at 1 and increases by
+    /// one with each message sent to the same recipient in the session. It is
+    /// 0 if the server does not support sequence numbers.
+    #[serde(default)]
+    pub seq: u64,
+    /// If set, this is an event generated by the server rather than a
+    /// message sent by a user, and `msg` is empty. `sender` is the user who
+    /// caused the event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<SessionEvent>,
+}
+
+/// An event in a session, delivered to its members by the server in the
+/// message queue (see [`Msg::event`]).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// A participant declined to take part in the session. Delivered to the
+    /// coordinator and the other participants.
+    Declined {
+        pubkey: PublicKey,
+        reason: Option<String>,
+    },
+    /// A member aborted the session, which will be closed shortly. Delivered
+    /// to all other members.
+    Aborted {
+        pubkey: PublicKey,
+        /// Whether the session was aborted by its coordinator.
+        by_coordinator: bool,
+        reason: AbortReason,
+        /// An optional description of why the session was aborted.
+        message: Option<String>,
+    },
+    /// An event not supported by this version.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Why a session was aborted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbortReason {
+    /// The user cancelled the session.
+    Cancelled,
+    /// An error happened while running the protocol, e.g. an invalid message
+    /// was received.
+    Error,
+    /// Any other reason, including reasons not supported by this version.
+    #[serde(other)]
+    Other,
+}
+
+impl std::fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AbortReason::Cancelled => write!(f, "cancelled"),
+            AbortReason::Error => write!(f, "error"),
+            AbortReason::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl std::fmt::Display for SessionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionEvent::Declined { pubkey, reason } => {
+                write!(
+                    f,
+                    "participant {} declined the session",
+                    hex::encode(&pubkey.0)
+                )?;
+                if let Some(reason) = reason {
+                    write!(f, ": {reason}")?;
+                }
+                Ok(())
+            }
+            SessionEvent::Aborted {
+                pubkey,
+                by_coordinator,
+                reason,
+                message,
+            } => {
+                if *by_coordinator {
+                    write!(f, "session aborted by coordinator: {reason}")?;
+                } else {
+                    write!(
+                        f,
+                        "session aborted by participant {}: {reason}",
+                        hex::encode(&pubkey.0)
+                    )?;
+                }
+                if let Some(message) = message {
+                    write!(f, " ({message})")?;
+                }
+                Ok(())
+            }
+            SessionEvent::Unknown => write!(f, "unknown session event"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReceiveArgs {
     pub session_id: Uuid,
     pub as_coordinator: bool,
+    /// If set and there are no messages queued, the server waits for up to
+    /// this time (in milliseconds, capped to [`MAX_WAIT_TIMEOUT_MS`]) for new
+    /// messages before returning ("long polling"). If not set, the server
+    /// returns immediately.
+    #[serde(default)]
+    pub wait_timeout_ms: Option<u64>,
+    /// If set, the server acknowledges (removes) the queued messages with
+    /// sequence number up to this value and returns the remaining ones
+    /// without removing them. Clients should pass the sequence number of the
+    /// last message they processed, so that messages are returned again if
+    /// a response is lost. If not set, all queued messages are returned and
+    /// removed.
+    #[serde(default)]
+    pub after_seq: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -109,11 +537,193 @@ pub struct ReceiveOutput {
     pub msgs: Vec<Msg>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AckArgs {
+    pub session_id: Uuid,
+    pub as_coordinator: bool,
+    /// The sequence number of the last message processed. All queued
+    /// messages up to it are removed.
+    pub seq: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BroadcastArgs {
+    pub session_id: Uuid,
+    /// The message to broadcast. It is not encrypted, since every member of
+    /// the session receives the same one.
+    #[serde(
+        serialize_with = "serdect::slice::serialize_hex_lower_or_bin",
+        deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
+    )]
+    pub msg: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBroadcastsArgs {
+    pub session_id: Uuid,
+    /// If set and not all participants have broadcast yet, the server waits
+    /// for up to this time (in milliseconds, capped to
+    /// [`MAX_WAIT_TIMEOUT_MS`]) for them to do so before returning.
+    #[serde(default)]
+    pub wait_timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBroadcastsOutput {
+    /// The messages broadcast in the session so far, at most one per sender,
+    /// sorted by sender.
+    pub msgs: Vec<Msg>,
+    /// The server attestation of the messages, set once every participant
+    /// (except those who declined) has broadcast.
+    pub attestation: Option<BroadcastAttestation>,
+}
+
+/// The hash of the message broadcast by a user.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastHash {
+    pub sender: PublicKey,
+    /// The SHA-256 hash of the message.
+    #[serde(
+        serialize_with = "serdect::slice::serialize_hex_lower_or_bin",
+        deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
+    )]
+    pub hash: Vec<u8>,
+}
+
+impl BroadcastHash {
+    /// Hash the message broadcast by the given sender.
+    pub fn new(sender: PublicKey, msg: &[u8]) -> Self {
+        Self {
+            sender,
+            hash: Sha256::digest(msg).to_vec(),
+        }
+    }
+}
+
+/// A statement signed by the server that the given messages (identified by
+/// their hashes) were broadcast in a session.
+///
+/// The server returns the same messages to every member, but a malicious
+/// server could return different ones to each. Members can detect that by
+/// comparing their [`BroadcastAttestation::digest()`] with each other.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastAttestation {
+    pub session_id: Uuid,
+    /// The hashes of the messages, sorted by sender.
+    pub hashes: Vec<BroadcastHash>,
+    /// The public key the server signed the attestation with. It is
+    /// generated when the server starts.
+    pub server_pubkey: PublicKey,
+    /// The XEdDSA signature of the digest.
+    #[serde(
+        serialize_with = "serdect::slice::serialize_hex_lower_or_bin",
+        deserialize_with = "serdect::slice::deserialize_hex_or_bin_vec"
+    )]
+    pub signature: Vec<u8>,
+}
+
+impl BroadcastAttestation {
+    /// Compute the digest of the session ID and the message hashes, which is
+    /// what the server signs.
+    pub fn digest(session_id: &Uuid, hashes: &[BroadcastHash]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(b"frostd broadcast attestation v1");
+        hasher.update(session_id.as_bytes());
+        for h in hashes {
+            hasher.update((h.sender.0.len() as u32).to_be_bytes());
+            hasher.update(&h.sender.0);
+            hasher.update((h.hash.len() as u32).to_be_bytes());
+            hasher.update(&h.hash);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    /// Check that the attestation is signed by its server key and that it
+    /// attests exactly the given messages.
+    pub fn verify(&self, msgs: &[Msg]) -> bool {
+        let Ok(pubkey) = TryInto::<[u8; 32]>::try_into(self.server_pubkey.0.clone()) else {
+            return false;
+        };
+        let Ok(signature) = TryInto::<[u8; 64]>::try_into(self.signature.clone()) else {
+            return false;
+        };
+        let digest = Self::digest(&self.session_id, &self.hashes);
+        xed25519::PublicKey(pubkey)
+            .verify(&digest, &signature)
+            .is_ok()
+            && msgs.len() == self.hashes.len()
+            && msgs
+                .iter()
+                .zip(&self.hashes)
+                .all(|(m, h)| BroadcastHash::new(m.sender.clone(), &m.msg) == *h)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CloseSessionArgs {
     pub session_id: Uuid,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminAddUserArgs {
+    pub pubkey: PublicKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminRemoveUserArgs {
+    pub pubkey: PublicKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminListUsersOutput {
+    /// The users in the allowlist, or `None` if the allowlist is not enabled
+    /// (i.e. any user can log in).
+    pub pubkeys: Option<Vec<PublicKey>>,
+}
+
+/// Information about a session, as seen by an administrator.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminSessionInfo {
+    pub session_id: Uuid,
+    pub coordinator_pubkey: PublicKey,
+    pub pubkeys: Vec<PublicKey>,
+    pub message_count: u8,
+    /// When the session was created, as a UNIX timestamp in seconds.
+    pub created_at: u64,
+    /// The number of messages waiting to be received, for all recipients.
+    pub queued_messages: usize,
+    /// The total size of the messages waiting to be received.
+    pub queued_bytes: usize,
+    /// When a message was last sent or received in the session, as a UNIX
+    /// timestamp in seconds.
+    pub last_activity: u64,
+    /// When the session will expire unless there is activity, as a UNIX
+    /// timestamp in seconds.
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminListSessionsOutput {
+    pub sessions: Vec<AdminSessionInfo>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminCloseSessionArgs {
+    pub session_id: Uuid,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminRevokeTokensArgs {
+    pub pubkey: PublicKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminRevokeTokensOutput {
+    /// The number of access tokens revoked. Signed access tokens are not
+    /// counted, since the server does not keep track of them.
+    pub revoked: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(bound = "C: Ciphersuite")]
 pub struct SendSigningPackageArgs<C: Ciphersuite> {
@@ -141,6 +751,20 @@ pub enum Error {
     NotCoordinator,
     #[error("user is not part of the given session")]
     NotInSession,
+    #[error("user is not registered in the server")]
+    UnregisteredUser,
+    #[error("user is not an administrator")]
+    NotAdmin,
+    #[error("limit exceeded: {0}")]
+    LimitExceeded(String),
+    #[error("requests from the client IP are not allowed")]
+    IpNotAllowed,
+    #[error("server is shutting down")]
+    ShuttingDown,
+    #[error("session was aborted")]
+    SessionAborted,
+    #[error("too large: {0}")]
+    TooLarge(String),
     #[serde(other)]
     #[error("unknown error")]
     Unknown,
@@ -152,6 +776,13 @@ pub const UNAUTHORIZED: usize = 2;
 pub const SESSION_NOT_FOUND: usize = 3;
 pub const NOT_COORDINATOR: usize = 4;
 pub const NOT_IN_SESSION: usize = 5;
+pub const UNREGISTERED_USER: usize = 6;
+pub const NOT_ADMIN: usize = 7;
+pub const LIMIT_EXCEEDED: usize = 8;
+pub const IP_NOT_ALLOWED: usize = 9;
+pub const SHUTTING_DOWN: usize = 10;
+pub const SESSION_ABORTED: usize = 11;
+pub const TOO_LARGE: usize = 12;
 pub const UNKNOWN: usize = 255;
 
 impl Error {
@@ -162,9 +793,56 @@ impl Error {
             Error::SessionNotFound => SESSION_NOT_FOUND,
             Error::NotCoordinator => NOT_COORDINATOR,
             Error::NotInSession => NOT_IN_SESSION,
+            Error::UnregisteredUser => UNREGISTERED_USER,
+            Error::NotAdmin => NOT_ADMIN,
+            Error::LimitExceeded(_) => LIMIT_EXCEEDED,
+            Error::IpNotAllowed => IP_NOT_ALLOWED,
+            Error::ShuttingDown => SHUTTING_DOWN,
+            Error::SessionAborted => SESSION_ABORTED,
+            Error::TooLarge(_) => TOO_LARGE,
             Error::Unknown => UNKNOWN,
         }
     }
+
+    /// Return the HTTP status code the server returns for the error.
+    ///
+    /// Older servers return 500 for all errors, so clients should rely on
+    /// the error itself rather than on the status code.
+    pub fn status_code(&self) -> u16 {
+        match &self {
+            Error::InvalidArgument(_) => 400,
+            Error::Unauthorized => 401,
+            Error::NotCoordinator
+            | Error::NotInSession
+            | Error::UnregisteredUser
+            | Error::NotAdmin
+            | Error::IpNotAllowed => 403,
+            Error::SessionNotFound => 404,
+            Error::SessionAborted => 410,
+            Error::TooLarge(_) => 413,
+            Error::LimitExceeded(_) => 429,
+            Error::ShuttingDown => 503,
+            Error::Unknown => 500,
+        }
+    }
+
+    /// Return if the call that caused the error may succeed if retried later,
+    /// e.g. after a rate limit window ends or the server restarts. Other
+    /// errors are fatal and will happen again if the call is retried as is.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::LimitExceeded(_) | Error::ShuttingDown)
+    }
+}
+
+/// Structured details about an error, to help clients handle it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ErrorDetails {
+    /// The name of the invalid argument, if the error was caused by one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// How long to wait before retrying the call, in seconds, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -172,6 +850,8 @@ pub struct LowError {
     pub code: usize,
     pub msg: String,
     pub error: Error,
+    #[serde(default)]
+    pub details: ErrorDetails,
 }
 
 impl From<Error> for LowError {
@@ -180,6 +860,7 @@ impl From<Error> for LowError {
             code: err.error_code(),
             msg: err.to_string(),
             error: err,
+            details: Default::default(),
         }
     }
 }
@@ -245,6 +245,8 @@ impl Cipher {
         Ok(Msg {
             sender: msg.sender,
             msg: decrypted,
+            seq: msg.seq,
+            event: msg.event,
         })
     }
 }
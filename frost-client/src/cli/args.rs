@@ -201,6 +201,10 @@ pub enum Command {
         /// human-readable hex-string is printed to stdout.
         #[arg(short = 'o', long, default_value = "")]
         signature: String,
+        /// A description of the session shown to participants, e.g. what is
+        /// being signed. Note that it is visible to the server.
+        #[arg(short, long)]
+        description: Option<String>,
     },
     /// Participate in a FROST signing session.
     Participant {
@@ -214,9 +218,10 @@ pub enum Command {
         #[arg(short, long)]
         server_url: Option<String>,
         /// The group to use, identified by the group public key (use `groups`
-        /// to list)
+        /// to list). If not specified, it will use the group the session was
+        /// created for, in which case `server_url` is required.
         #[arg(short, long)]
-        group: String,
+        group: Option<String>,
         /// The session ID to use (use `sessions` to list). Can be omitted in
         /// case there is a single active session.
         #[arg(short = 'S', long)]
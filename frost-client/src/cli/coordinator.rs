@@ -48,6 +48,7 @@ pub(crate) async fn run_for_ciphersuite<C: RandomizedCiphersuite + 'static>(
         message,
         randomizer,
         signature,
+        description,
     } = (*args).clone()
     else {
         panic!("invalid Command");
@@ -111,6 +112,7 @@ pub(crate) async fn run_for_ciphersuite<C: RandomizedCiphersuite + 'static>(
                 .pubkey
                 .clone(),
         ),
+        description,
     };
 
     cli::cli_for_processed_args(pargs, &mut input, &mut output).await?;
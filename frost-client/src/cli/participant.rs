@@ -12,37 +12,95 @@ use frost_core::Ciphersuite;
 use frost_ed25519::Ed25519Sha512;
 use frost_rerandomized::RandomizedCiphersuite;
 
-use super::{args::Command, config::Config};
+use super::{args::Command, config::Config, session::login};
 
+use crate::api::{self, Uuid};
 use crate::participant::args;
 use crate::participant::cli::cli_for_processed_args;
 
 pub async fn run(args: &Command) -> Result<(), Box<dyn Error>> {
-    let Command::Participant { config, group, .. } = (*args).clone() else {
+    let Command::Participant {
+        config,
+        server_url,
+        group,
+        session,
+    } = (*args).clone()
+    else {
         panic!("invalid Command");
     };
 
     let config = Config::read(config)?;
 
-    let group = config.group.get(&group).ok_or_eyre("Group not found")?;
+    let group_id = match group {
+        Some(group) => group,
+        None => {
+            let server_url = server_url.ok_or_eyre("must specify either server_url or group")?;
+            group_from_session(&config, &server_url, session.as_deref()).await?
+        }
+    };
+    let group = config.group.get(&group_id).ok_or_eyre("Group not found")?;
 
     if group.ciphersuite == Ed25519Sha512::ID {
-        run_for_ciphersuite::<Ed25519Sha512>(args).await
+        run_for_ciphersuite::<Ed25519Sha512>(args, &group_id).await
     } else if group.ciphersuite == PallasBlake2b512::ID {
-        run_for_ciphersuite::<PallasBlake2b512>(args).await
+        run_for_ciphersuite::<PallasBlake2b512>(args, &group_id).await
     } else {
         Err(eyre!("unsupported ciphersuite").into())
     }
 }
 
+/// Find the group of the given session (or of the single active session, if
+/// none is given) from the metadata attached to it by the coordinator.
+async fn group_from_session(
+    config: &Config,
+    server_url: &str,
+    session: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let client = login(config, server_url).await?;
+
+    let session_id = match session {
+        Some(session) => Uuid::parse_str(session).wrap_err("error parsing session ID")?,
+        None => {
+            let r = client.list_sessions().await?;
+            match r.session_ids[..] {
+                [session_id] => session_id,
+                [] => return Err(eyre!("User has no current sessions active").into()),
+                _ => return Err(eyre!("user has more than one FROST session active; use `frost-client sessions` to list them and specify the session ID with `-S`").into()),
+            }
+        }
+    };
+
+    let r = client
+        .get_session_info(&api::GetSessionInfoArgs { session_id })
+        .await?;
+    if r.metadata.kind == Some(api::SessionKind::Dkg) {
+        return Err(
+            eyre!("the session is a DKG session; use `frost-client dkg` to join it").into(),
+        );
+    }
+    let group_id = hex::encode(
+        r.metadata
+            .group
+            .ok_or_eyre("the session does not specify its group; specify it with `-g`")?,
+    );
+    let group = config
+        .group
+        .get(&group_id)
+        .ok_or_else(|| eyre!("the session is for group {group_id}, which is not in the config"))?;
+    eprintln!("Using group \"{}\" ({group_id})", group.description);
+
+    Ok(group_id)
+}
+
 pub(crate) async fn run_for_ciphersuite<C: RandomizedCiphersuite + 'static>(
     args: &Command,
+    group_id: &str,
 ) -> Result<(), Box<dyn Error>> {
     let Command::Participant {
         config,
         server_url,
-        group,
         session,
+        ..
     } = (*args).clone()
     else {
         panic!("invalid Command");
@@ -50,7 +108,7 @@ pub(crate) async fn run_for_ciphersuite<C: RandomizedCiphersuite + 'static>(
 
     let config = Config::read(config)?;
 
-    let group = config.group.get(&group).ok_or_eyre("Group not found")?;
+    let group = config.group.get(group_id).ok_or_eyre("Group not found")?;
 
     let key_package: KeyPackage<C> = postcard::from_bytes(&group.key_package)?;
 
@@ -1,4 +1,7 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use eyre::{eyre, OptionExt as _};
 use rand::thread_rng;
@@ -32,34 +35,7 @@ pub async fn list(args: &Command) -> Result<(), Box<dyn Error>> {
         return Err(eyre!("must specify either server_url or group").into());
     };
 
-    let comm_privkey = config
-        .communication_key
-        .clone()
-        .ok_or_eyre("user not initialized")?
-        .privkey
-        .clone();
-    let comm_pubkey = config
-        .communication_key
-        .clone()
-        .ok_or_eyre("user not initialized")?
-        .pubkey
-        .clone();
-
-    let mut client = Client::new(format!("https://{server_url}"));
-
-    let mut rng = thread_rng();
-
-    let challenge = client.challenge().await?.challenge;
-
-    let signature: [u8; 64] = comm_privkey.sign(challenge.as_bytes(), &mut rng)?;
-
-    client
-        .login(&api::LoginArgs {
-            challenge,
-            pubkey: comm_pubkey.clone(),
-            signature: signature.to_vec(),
-        })
-        .await?;
+    let client = login(&config, &server_url).await?;
 
     // Get session ID from server
     let r = client.list_sessions().await?;
@@ -71,29 +47,82 @@ pub async fn list(args: &Command) -> Result<(), Box<dyn Error>> {
             let r = client
                 .get_session_info(&api::GetSessionInfoArgs { session_id })
                 .await?;
-            let coordinator = config.contact_by_pubkey(&r.coordinator_pubkey);
-            let participants: Vec<_> = r
-                .pubkeys
-                .iter()
-                .map(|pubkey| config.contact_by_pubkey(pubkey))
-                .collect();
-            eprintln!("Session with ID {session_id}");
-            eprintln!(
-                "Coordinator: {}",
-                coordinator
+            let name = |pubkey: &api::PublicKey| {
+                config
+                    .contact_by_pubkey(pubkey)
                     .map(|c| c.name)
                     .unwrap_or("(Unknown contact)".to_string())
-            );
-            eprintln!("Signers: {}", participants.len());
-            for participant in participants {
-                if let Ok(participant) = participant {
+            };
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            eprintln!("Session with ID {session_id}");
+            if let Some(kind) = r.metadata.kind {
+                eprintln!("Kind: {kind}");
+            }
+            if let Some(description) = &r.metadata.description {
+                eprintln!("Description: {description}");
+            }
+            if let Some(group) = &r.metadata.group {
+                let group = hex::encode(group);
+                match config.group.get(&group) {
+                    Some(g) => eprintln!("Group: \"{}\" ({group})", g.description),
+                    None => eprintln!("Group: {group} (not in config)"),
+                }
+            } else if let Some(ciphersuite) = &r.metadata.ciphersuite {
+                eprintln!("Ciphersuite: {ciphersuite}");
+            }
+            eprintln!("Coordinator: {}", name(&r.coordinator_pubkey));
+            if r.created_at > 0 {
+                eprintln!(
+                    "Created {} ago; last activity {} ago; expires in {}",
+                    format_secs(now.saturating_sub(r.created_at)),
+                    format_secs(now.saturating_sub(r.last_activity)),
+                    format_secs(r.expires_at.saturating_sub(now)),
+                );
+            }
+            eprintln!("Signers: {}", r.pubkeys.len());
+            if r.participants.is_empty() {
+                // The server does not report the participant status.
+                for pubkey in &r.pubkeys {
+                    eprintln!("\t{}\t({})", name(pubkey), hex::encode(&pubkey.0));
+                }
+            } else {
+                for status in &r.participants {
+                    let last_seen = match status.last_seen {
+                        Some(t) => format!("seen {} ago", format_secs(now.saturating_sub(t))),
+                        None => "never connected".to_string(),
+                    };
+                    let invitation = match (status.invitation, &status.decline_reason) {
+                        (api::InvitationStatus::Pending, _) => "not accepted yet".to_string(),
+                        (api::InvitationStatus::Accepted, _) => "accepted".to_string(),
+                        (api::InvitationStatus::Declined, None) => "DECLINED".to_string(),
+                        (api::InvitationStatus::Declined, Some(reason)) => {
+                            format!("DECLINED ({reason})")
+                        }
+                    };
                     eprintln!(
-                        "\t{}\t({})",
-                        participant.name,
-                        hex::encode(&participant.pubkey.0)
+                        "\t{}\t({})\t{}, {}, {} sent, {} pending",
+                        name(&status.pubkey),
+                        hex::encode(&status.pubkey.0),
+                        invitation,
+                        last_seen,
+                        status.messages_sent,
+                        status.messages_pending,
                     );
-                } else {
-                    eprintln!("\t(Unknown contact)");
+                }
+                // Participants who never connected or didn't pick up their
+                // messages are holding up the session.
+                let waiting: Vec<_> = r
+                    .participants
+                    .iter()
+                    .filter(|s| s.invitation != api::InvitationStatus::Declined)
+                    .filter(|s| s.last_seen.is_none() || s.messages_pending > 0)
+                    .map(|s| name(&s.pubkey))
+                    .collect();
+                if !waiting.is_empty() {
+                    eprintln!("Waiting for {}", join_names(&waiting));
                 }
             }
             eprintln!();
@@ -108,3 +137,48 @@ pub async fn list(args: &Command) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Log in to the given server with the user's communication key.
+pub(crate) async fn login(config: &Config, server_url: &str) -> Result<Client, Box<dyn Error>> {
+    let comm_key = config
+        .communication_key
+        .clone()
+        .ok_or_eyre("user not initialized")?;
+
+    let mut client = Client::new(format!("https://{server_url}"));
+
+    let mut rng = thread_rng();
+
+    let challenge = client.challenge().await?.challenge;
+    let payload = client.login_payload(&challenge).await?;
+
+    let signature: [u8; 64] = comm_key.privkey.sign(&payload, &mut rng)?;
+
+    client
+        .login(&api::LoginArgs {
+            challenge,
+            pubkey: comm_key.pubkey.clone(),
+            signature: signature.to_vec(),
+        })
+        .await?;
+
+    Ok(client)
+}
+
+/// Format a number of seconds in a human-readable way, e.g. "1h 5m".
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+/// Join names in a list, e.g. "Alice, Bob and Carol".
+fn join_names(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
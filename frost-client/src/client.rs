@@ -1,5 +1,11 @@
 //! Client for the frostd server.
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::{io, time::Duration};
+
+use hyper::body::Bytes;
 use thiserror::Error;
+use tokio::sync::OnceCell;
 use uuid::Uuid;
 
 use crate::api;
@@ -7,75 +13,446 @@ use crate::api;
 /// A Client error.
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("server error: {0}")]
-    ServerError(#[from] api::Error),
+    #[error("server error: {error}")]
+    ServerError {
+        error: api::Error,
+        details: api::ErrorDetails,
+    },
     #[error("connection error: {0}")]
     ConnectionError(#[from] reqwest::Error),
     #[error("JSON parsing error: {0}")]
     JsonError(#[from] serde_json::Error),
+    /// An error connecting or talking to the server over a Unix domain
+    /// socket.
+    #[error("connection error: {0}")]
+    IoError(#[from] io::Error),
+    /// An HTTP error status returned without a server error, over a Unix
+    /// domain socket.
+    #[error("HTTP error: {0}")]
+    HttpError(reqwest::StatusCode),
+}
+
+impl From<api::Error> for Error {
+    fn from(error: api::Error) -> Self {
+        Error::ServerError {
+            error,
+            details: Default::default(),
+        }
+    }
+}
+
+impl Error {
+    /// Return the error returned by the server, if any.
+    pub fn server_error(&self) -> Option<&api::Error> {
+        match self {
+            Error::ServerError { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// Return if the error is transient (e.g. the connection failed or timed
+    /// out, or a rate limit was hit), in which case the call may succeed if
+    /// retried. Other errors are fatal.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::ConnectionError(e) => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || e.is_request()
+                    || e.is_body()
+                    || e.status().is_some_and(is_transient_status)
+            }
+            Error::ServerError { error, .. } => error.is_retryable(),
+            Error::JsonError(_) => false,
+            Error::IoError(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::HttpError(status) => is_transient_status(*status),
+        }
+    }
+
+    /// Return how long the server asked to wait before retrying, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::ServerError { details, .. } => details.retry_after_secs.map(Duration::from_secs),
+            _ => None,
+        }
+    }
+
+    /// Return if the error is caused by the server not having the requested
+    /// endpoint.
+    fn is_not_found(&self) -> bool {
+        match self {
+            Error::ConnectionError(e) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+            Error::HttpError(status) => *status == reqwest::StatusCode::NOT_FOUND,
+            _ => false,
+        }
+    }
+}
+
+/// Return if the given HTTP error status is returned by reverse proxies when
+/// the server is temporarily unavailable.
+fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::BAD_GATEWAY
+        || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+        || status == reqwest::StatusCode::GATEWAY_TIMEOUT
+}
+
+/// Convert an error of a connection to the server to an I/O error, which is
+/// transient (see [`Error::is_transient`]) if the connection was closed
+/// before the response was received, e.g. because the server restarted.
+#[cfg(unix)]
+fn connection_error(e: hyper::Error) -> io::Error {
+    let kind = if e.is_closed() || e.is_incomplete_message() || e.is_canceled() {
+        io::ErrorKind::ConnectionReset
+    } else {
+        io::ErrorKind::Other
+    };
+    io::Error::new(kind, e)
+}
+
+/// Return the server error in the given response body, if it has one.
+fn server_error(body: &[u8]) -> Option<Error> {
+    let err = serde_json::from_slice::<api::LowError>(body).ok()?;
+    Some(Error::ServerError {
+        error: err.error,
+        details: err.details,
+    })
+}
+
+/// How long to wait for a response before giving up on a call. It must be
+/// longer than the maximum long polling time of `receive` calls.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(api::MAX_WAIT_TIMEOUT_MS + 30_000);
+/// How many times idempotent calls are retried after a transient error.
+const MAX_RETRIES: u32 = 5;
+/// How long to wait before the first retry. It doubles on each retry.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The API version negotiated with the server.
+#[derive(Clone, Debug)]
+struct Negotiated {
+    /// The path prefix of the API version (e.g. `v1/`), or empty if the
+    /// server only supports the unversioned API.
+    prefix: String,
+    /// The server information, if the server supports the `info` call.
+    info: Option<api::InfoOutput>,
+}
+
+/// How the client connects to the server.
+enum Transport {
+    /// HTTP(S) with the given client.
+    Http(reqwest::Client),
+    /// Plain HTTP over the Unix domain socket at the given path.
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
 }
 
 /// A frostd Client that allows calling frostd API methods.
+///
+/// The API version is negotiated with the server on the first call, using the
+/// newest version in [`api::API_VERSIONS`] that the server supports, and
+/// falling back to the unversioned API for older servers.
 pub struct Client {
     host_port: String,
-    client: reqwest::Client,
-    access_token: Option<Uuid>,
+    transport: Transport,
+    access_token: Option<String>,
+    negotiated: OnceCell<Negotiated>,
 }
 
 impl Client {
     /// Create a new client that will connect to the given host/port in
     /// `host:port` format.
     pub fn new(host_port: String) -> Self {
+        Self::with_http_client(host_port, reqwest::Client::new())
+    }
+
+    /// Create a new client that will connect to the given host/port using
+    /// the given HTTP client, e.g. to trust additional root certificates.
+    pub fn with_http_client(host_port: String, client: reqwest::Client) -> Self {
         Self {
             host_port,
-            client: reqwest::Client::new(),
+            transport: Transport::Http(client),
             access_token: None,
+            negotiated: OnceCell::new(),
         }
     }
 
+    /// Create a new client that will connect to a server on the same host
+    /// over plain HTTP on the Unix domain socket at the given path (see the
+    /// `unix_socket` option of frostd).
+    ///
+    /// `host_port` is the URL the server is known as, e.g.
+    /// `https://frost.example.com`. It is only used as the host of the
+    /// requests and as the server identity when logging in, so it must be one
+    /// of the server names of the server, if it has any.
+    #[cfg(unix)]
+    pub fn with_unix_socket(host_port: String, socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            host_port,
+            transport: Transport::UnixSocket(socket_path.into()),
+            access_token: None,
+            negotiated: OnceCell::new(),
+        }
+    }
+
+    /// Create a new client that will connect to the given host/port,
+    /// authenticating with a TLS client certificate, for servers that
+    /// require them. `cert_and_key_pem` must contain the certificate (and
+    /// optionally its chain) and its private key, in PEM format.
+    pub fn with_client_cert(host_port: String, cert_and_key_pem: &[u8]) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .identity(reqwest::Identity::from_pem(cert_and_key_pem)?)
+            .build()?;
+        Ok(Self::with_http_client(host_port, client))
+    }
+
+    /// Return the API version used to talk to the server, negotiating it if
+    /// needed. Returns `None` if the server only supports the unversioned API.
+    pub async fn api_version(&self) -> Result<Option<&str>, Error> {
+        let negotiated = self.negotiate().await?;
+        Ok(negotiated
+            .prefix
+            .strip_suffix('/')
+            .filter(|v| !v.is_empty()))
+    }
+
+    /// Return if the server supports the given optional feature (see
+    /// [`api::features`]).
+    pub async fn supports(&self, feature: &str) -> Result<bool, Error> {
+        let negotiated = self.negotiate().await?;
+        Ok(negotiated
+            .info
+            .as_ref()
+            .is_some_and(|info| info.features.iter().any(|f| f == feature)))
+    }
+
+    /// Negotiate the API version with the server, if not done already.
+    async fn negotiate(&self) -> Result<&Negotiated, Error> {
+        self.negotiated
+            .get_or_try_init(|| async {
+                for version in api::API_VERSIONS.iter().rev() {
+                    let prefix = format!("{version}/");
+                    match self.post(&format!("{prefix}info"), &()).await {
+                        Ok(info) => {
+                            return Ok(Negotiated {
+                                prefix,
+                                info: Some(info),
+                            })
+                        }
+                        Err(e) if e.is_not_found() => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                // Older servers do not have a versioned API, and the oldest
+                // ones do not have the `info` call either.
+                let info = match self.post("info", &()).await {
+                    Ok(info) => Some(info),
+                    Err(e) if e.is_not_found() => None,
+                    Err(e) => return Err(e),
+                };
+                Ok(Negotiated {
+                    prefix: String::new(),
+                    info,
+                })
+            })
+            .await
+    }
+
     async fn call<A, O>(&self, name: &str, args: &A) -> Result<O, Error>
     where
         A: serde::Serialize,
         O: serde::de::DeserializeOwned,
     {
-        let req = self
-            .client
-            .post(format!("{}/{}", self.host_port, name))
+        let negotiated = self.negotiate().await?;
+        self.post(&format!("{}{}", negotiated.prefix, name), args)
+            .await
+    }
+
+    /// Call the API method at the given path, relative to the server URL.
+    async fn post<A, O>(&self, path: &str, args: &A) -> Result<O, Error>
+    where
+        A: serde::Serialize,
+        O: serde::de::DeserializeOwned,
+    {
+        let body = match &self.transport {
+            Transport::Http(client) => self.post_http(client, path, args).await?,
+            #[cfg(unix)]
+            Transport::UnixSocket(socket_path) => {
+                self.post_unix_socket(socket_path, path, args).await?
+            }
+        };
+        let json = if body.is_empty() { &b"null"[..] } else { &body };
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Send a request to the API method at the given path over HTTP(S),
+    /// returning the body of the response if it succeeded.
+    async fn post_http<A>(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+        args: &A,
+    ) -> Result<Bytes, Error>
+    where
+        A: serde::Serialize,
+    {
+        let req = client
+            .post(format!("{}/{}", self.host_port, path))
+            .timeout(REQUEST_TIMEOUT)
             .json(args);
         let req = if let Some(token) = &self.access_token {
-            req.bearer_auth(token.to_string())
+            req.bearer_auth(token)
         } else {
             req
         };
         let response = req.send().await?;
         if !response.status().is_success() {
-            if response.status() == reqwest::StatusCode::INTERNAL_SERVER_ERROR {
-                let err = response.json::<api::LowError>().await?;
-                let err: api::Error = err.into();
-                Err(err.into())
-            } else {
-                Err(Error::ConnectionError(
-                    response
-                        .error_for_status()
-                        .expect_err("we know the response is not success"),
-                ))
-            }
+            // Server errors have a JSON body with the error. Older servers
+            // return them with status 500, newer ones with a status code that
+            // depends on the error. Other errors (e.g. from a reverse proxy,
+            // or for unknown endpoints) are returned as connection errors.
+            let status_err = response
+                .error_for_status_ref()
+                .expect_err("we know the response is not success");
+            let body = response.bytes().await?;
+            Err(server_error(&body).unwrap_or(Error::ConnectionError(status_err)))
+        } else {
+            Ok(response.bytes().await?)
+        }
+    }
+
+    /// Send a request to the API method at the given path over the Unix
+    /// domain socket at `socket_path`, returning the body of the response if
+    /// it succeeded. A new connection is used for each request.
+    #[cfg(unix)]
+    async fn post_unix_socket<A>(
+        &self,
+        socket_path: &Path,
+        path: &str,
+        args: &A,
+    ) -> Result<Bytes, Error>
+    where
+        A: serde::Serialize,
+    {
+        use http_body_util::{BodyExt as _, Full};
+        use hyper::{header, Request};
+        use hyper_util::rt::TokioIo;
+
+        // Split e.g. `https://frost.example.com/prefix` into the host and
+        // the path prefix.
+        let url = self
+            .host_port
+            .split_once("://")
+            .map_or(&*self.host_port, |(_, rest)| rest);
+        let (authority, prefix) = url.find('/').map_or((url, ""), |i| url.split_at(i));
+        let req = Request::post(format!("{prefix}/{path}"))
+            .header(header::HOST, authority)
+            .header(header::CONTENT_TYPE, "application/json");
+        let req = if let Some(token) = &self.access_token {
+            req.header(header::AUTHORIZATION, format!("Bearer {token}"))
         } else {
-            let body = response.text().await?;
-            let json_str = if body.is_empty() { "null" } else { &body };
-            Ok(serde_json::from_str(json_str)?)
+            req
+        };
+        let req = req
+            .body(Full::new(Bytes::from(serde_json::to_vec(args)?)))
+            .map_err(io::Error::other)?;
+
+        let (status, body) = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            let stream = tokio::net::UnixStream::connect(socket_path).await?;
+            let (mut sender, connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                    .await
+                    .map_err(connection_error)?;
+            tokio::spawn(connection);
+            let response = sender.send_request(req).await.map_err(connection_error)?;
+            let status = response.status();
+            let body = response
+                .into_body()
+                .collect()
+                .await
+                .map_err(connection_error)?
+                .to_bytes();
+            Ok::<_, io::Error>((status, body))
+        })
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+        if !status.is_success() {
+            Err(server_error(&body).unwrap_or(Error::HttpError(status)))
+        } else {
+            Ok(body)
         }
     }
 
+    /// Call the given method, retrying it with exponential backoff if it
+    /// fails with a transient error. Must only be used for idempotent calls.
+    async fn call_with_retries<A, O>(&self, name: &str, args: &A) -> Result<O, Error>
+    where
+        A: serde::Serialize,
+        O: serde::de::DeserializeOwned,
+    {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            match self.call(name, args).await {
+                Err(e) if e.is_transient() && retries < MAX_RETRIES => {
+                    tokio::time::sleep(e.retry_after().map_or(delay, |d| d.max(delay))).await;
+                    delay *= 2;
+                    retries += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// Get information about the server and the limits it enforces.
+    pub async fn info(&self) -> Result<api::InfoOutput, Error> {
+        // The information is fetched when negotiating the API version.
+        if let Some(info) = &self.negotiate().await?.info {
+            return Ok(info.clone());
+        }
+        self.call_with_retries("info", &()).await
+    }
+
     pub async fn challenge(&self) -> Result<api::ChallengeOutput, Error> {
         self.call("challenge", &()).await
     }
 
+    /// Return the payload to sign to log in with the given challenge (see
+    /// [`api::login_payload()`]), using the URL the client connects to as the
+    /// server identity. For servers that do not support it, the challenge
+    /// itself is signed.
+    pub async fn login_payload(&self, challenge: &Uuid) -> Result<Vec<u8>, Error> {
+        if self.supports(api::features::SERVER_BOUND_LOGIN).await? {
+            let server_identity = api::server_identity(&self.host_port);
+            Ok(api::login_payload(&server_identity, challenge))
+        } else {
+            Ok(challenge.as_bytes().to_vec())
+        }
+    }
+
     /// Login to the server. This will internally set the access token for the
     /// client so that other authenticated methods can be called.
     pub async fn login(&mut self, args: &api::LoginArgs) -> Result<api::LoginOutput, Error> {
         let login_output: api::LoginOutput = self.call("login", args).await?;
-        self.access_token = Some(login_output.access_token);
+        self.access_token = Some(login_output.token());
+        Ok(login_output)
+    }
+
+    /// Get a new access token, revoking the current one, so that the client
+    /// can stay logged in for longer than the access token timeout without
+    /// logging in again. Requires [`api::features::REFRESH`].
+    pub async fn refresh(&mut self) -> Result<api::LoginOutput, Error> {
+        let login_output: api::LoginOutput = self.call("refresh", &()).await?;
+        self.access_token = Some(login_output.token());
         Ok(login_output)
     }
 
@@ -94,25 +471,173 @@ impl Client {
     }
 
     pub async fn list_sessions(&self) -> Result<api::ListSessionsOutput, Error> {
-        self.call("list_sessions", &()).await
+        self.call_with_retries("list_sessions", &()).await
     }
 
     pub async fn get_session_info(
         &self,
         args: &api::GetSessionInfoArgs,
     ) -> Result<api::GetSessionInfoOutput, Error> {
-        self.call("get_session_info", args).await
+        self.call_with_retries("get_session_info", args).await
     }
 
+    /// Send a message in the given session.
+    ///
+    /// If the server ignores retried messages (see
+    /// [`api::features::IDEMPOTENT_SEND`]), the call is retried if it fails
+    /// with a transient error, generating a random `args.msg_id` if not set.
+    /// Otherwise it is not, since the message could be sent twice.
     pub async fn send(&self, args: &api::SendArgs) -> Result<(), Error> {
-        self.call("send", args).await
+        if !self.supports(api::features::IDEMPOTENT_SEND).await? {
+            return self.call("send", args).await;
+        }
+        if args.msg_id.is_some() {
+            return self.call_with_retries("send", args).await;
+        }
+        let args = api::SendArgs {
+            msg_id: Some(Uuid::new_v4()),
+            ..args.clone()
+        };
+        self.call_with_retries("send", &args).await
     }
 
+    /// Receive messages sent to the user in the given session.
+    ///
+    /// If `args.wait_timeout_ms` is set, this will return only after a message
+    /// arrives or the timeout expires, whichever comes first. It may still
+    /// return earlier without messages, e.g. if the server is shutting down.
+    ///
+    /// If `args.after_seq` is set, the call is retried if it fails with a
+    /// transient error, since messages are not removed from the server until
+    /// acknowledged.
     pub async fn receive(&self, args: &api::ReceiveArgs) -> Result<api::ReceiveOutput, Error> {
-        self.call("receive", args).await
+        let start = tokio::time::Instant::now();
+        let output: api::ReceiveOutput = if args.after_seq.is_some() {
+            self.call_with_retries("receive", args).await?
+        } else {
+            self.call("receive", args).await?
+        };
+        // Servers that do not support long polling return immediately. Wait
+        // for the remaining time here in that case, so that callers looping
+        // on `receive()` don't busy loop. Servers that do may also return
+        // early with no messages (e.g. when shutting down), in which case
+        // the caller should call again right away.
+        if let Some(wait_timeout_ms) = args.wait_timeout_ms {
+            if output.msgs.is_empty() && !self.supports(api::features::LONG_POLLING).await? {
+                let wait_timeout =
+                    Duration::from_millis(wait_timeout_ms.min(api::MAX_WAIT_TIMEOUT_MS));
+                tokio::time::sleep_until(start + wait_timeout).await;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Accept the invitation to take part in the given session. This is
+    /// optional, since sending a message to the session also accepts it, but
+    /// lets the coordinator know the user is there.
+    pub async fn accept_session(&self, args: &api::AcceptSessionArgs) -> Result<(), Error> {
+        self.call_with_retries("accept_session", args).await
+    }
+
+    /// Decline to take part in the given session. The coordinator and the
+    /// other participants are notified.
+    pub async fn decline_session(&self, args: &api::DeclineSessionArgs) -> Result<(), Error> {
+        self.call_with_retries("decline_session", args).await
+    }
+
+    /// Abort the given session. The other members are notified, and the
+    /// session is closed shortly after.
+    ///
+    /// `args.message` is truncated if longer than [`api::MAX_REASON_LEN`]
+    /// bytes.
+    pub async fn abort_session(&self, args: &api::AbortSessionArgs) -> Result<(), Error> {
+        let args = api::AbortSessionArgs {
+            message: args.message.as_ref().map(|m| {
+                // Truncate to the maximum length in bytes, at a char
+                // boundary.
+                let mut len = m.len().min(api::MAX_REASON_LEN);
+                while !m.is_char_boundary(len) {
+                    len -= 1;
+                }
+                m[..len].to_string()
+            }),
+            ..args.clone()
+        };
+        self.call_with_retries("abort_session", &args).await
+    }
+
+    /// Acknowledge the messages received in the given session up to the
+    /// given sequence number, removing them from the server.
+    ///
+    /// This is not needed if the next `receive()` call passes `after_seq`,
+    /// which also acknowledges them; it's useful to free up the server
+    /// queue when no further messages are expected.
+    pub async fn ack(&self, args: &api::AckArgs) -> Result<(), Error> {
+        self.call_with_retries("ack", args).await
+    }
+
+    /// Broadcast a message to all members of the given session. Each member
+    /// can broadcast a single message per session.
+    pub async fn broadcast(&self, args: &api::BroadcastArgs) -> Result<(), Error> {
+        self.call_with_retries("broadcast", args).await
+    }
+
+    /// Get the messages broadcast in the given session, and the server
+    /// attestation of them once all participants have broadcast.
+    ///
+    /// If `args.wait_timeout_ms` is set, this will return only after all
+    /// participants have broadcast or the timeout expires, whichever comes
+    /// first.
+    pub async fn get_broadcasts(
+        &self,
+        args: &api::GetBroadcastsArgs,
+    ) -> Result<api::GetBroadcastsOutput, Error> {
+        self.call_with_retries("get_broadcasts", args).await
     }
 
     pub async fn close_session(&self, args: &api::CloseSessionArgs) -> Result<(), Error> {
         self.call("close_session", args).await
     }
+
+    /// Add a user to the server allowlist. Requires being logged in as an
+    /// administrator.
+    pub async fn admin_add_user(&self, args: &api::AdminAddUserArgs) -> Result<(), Error> {
+        self.call("admin/add_user", args).await
+    }
+
+    /// Remove a user from the server allowlist, revoking their access tokens.
+    /// Requires being logged in as an administrator.
+    pub async fn admin_remove_user(&self, args: &api::AdminRemoveUserArgs) -> Result<(), Error> {
+        self.call("admin/remove_user", args).await
+    }
+
+    /// List the users in the server allowlist. Requires being logged in as an
+    /// administrator.
+    pub async fn admin_list_users(&self) -> Result<api::AdminListUsersOutput, Error> {
+        self.call("admin/list_users", &()).await
+    }
+
+    /// List all open sessions in the server. Requires being logged in as an
+    /// administrator.
+    pub async fn admin_list_sessions(&self) -> Result<api::AdminListSessionsOutput, Error> {
+        self.call("admin/list_sessions", &()).await
+    }
+
+    /// Close a session, even if the user is not its coordinator. Requires
+    /// being logged in as an administrator.
+    pub async fn admin_close_session(
+        &self,
+        args: &api::AdminCloseSessionArgs,
+    ) -> Result<(), Error> {
+        self.call("admin/close_session", args).await
+    }
+
+    /// Revoke all access tokens of a user. Requires being logged in as an
+    /// administrator.
+    pub async fn admin_revoke_tokens(
+        &self,
+        args: &api::AdminRevokeTokensArgs,
+    ) -> Result<api::AdminRevokeTokensOutput, Error> {
+        self.call("admin/revoke_tokens", args).await
+    }
 }
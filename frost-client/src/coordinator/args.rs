@@ -118,6 +118,9 @@ pub struct ProcessedArgs<C: Ciphersuite> {
 
     /// The coordinator's communication public key for HTTP mode.
     pub comm_pubkey: Option<PublicKey>,
+
+    /// A description of the session shown to participants, for HTTP mode.
+    pub description: Option<String>,
 }
 
 impl<C: Ciphersuite + 'static> ProcessedArgs<C> {
@@ -168,6 +171,7 @@ impl<C: Ciphersuite + 'static> ProcessedArgs<C> {
             port: args.port,
             comm_privkey: None,
             comm_pubkey: None,
+            description: None,
         })
     }
 }
@@ -42,8 +42,9 @@ pub async fn cli_for_processed_args<C: RandomizedCiphersuite + 'static>(
 
     let r = get_commitments(&pargs, &mut *comms, reader, logger).await;
     let Ok(participants_config) = r else {
-        let _ = comms.cleanup_on_error().await;
-        return Err(r.unwrap_err());
+        let e = r.unwrap_err();
+        let _ = comms.cleanup_on_error(&*e).await;
+        return Err(e);
     };
 
     let signing_package =
@@ -60,7 +61,7 @@ pub async fn cli_for_processed_args<C: RandomizedCiphersuite + 'static>(
     .await;
 
     if let Err(e) = r {
-        let _ = comms.cleanup_on_error().await;
+        let _ = comms.cleanup_on_error(&*e).await;
         return Err(e);
     }
 
@@ -55,7 +55,7 @@ pub trait Comms<C: Ciphersuite> {
     ) -> Result<BTreeMap<Identifier<C>, SignatureShare<C>>, Box<dyn Error>>;
 
     /// Do any cleanups in case an error occurs during the protocol run.
-    async fn cleanup_on_error(&mut self) -> Result<(), Box<dyn Error>> {
+    async fn cleanup_on_error(&mut self, _error: &dyn Error) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
 }
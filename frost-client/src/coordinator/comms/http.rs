@@ -5,7 +5,6 @@ use std::{
     error::Error,
     io::{BufRead, Write},
     marker::PhantomData,
-    time::Duration,
     vec,
 };
 
@@ -34,6 +33,9 @@ pub struct HTTPComms<C: Ciphersuite> {
     state: CoordinatorSessionState<C>,
     pubkeys: HashMap<PublicKey, Identifier<C>>,
     cipher: Option<Cipher>,
+    /// The sequence number of the last message received, used to
+    /// acknowledge it and avoid losing messages if a response is lost.
+    last_seq: u64,
     _phantom: PhantomData<C>,
 }
 
@@ -50,6 +52,7 @@ impl<C: Ciphersuite> HTTPComms<C> {
             ),
             pubkeys: Default::default(),
             cipher: None,
+            last_seq: 0,
             _phantom: Default::default(),
         })
     }
@@ -68,13 +71,14 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
 
         eprintln!("Logging in...");
         let challenge = self.client.challenge().await?.challenge;
+        let payload = self.client.login_payload(&challenge).await?;
 
         let signature: [u8; 64] = self
             .args
             .comm_privkey
             .clone()
             .ok_or_eyre("comm_privkey must be specified")?
-            .sign(challenge.as_bytes(), &mut rng)?;
+            .sign(&payload, &mut rng)?;
 
         self.client
             .login(&api::LoginArgs {
@@ -94,6 +98,12 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
             .create_new_session(&api::CreateNewSessionArgs {
                 pubkeys: self.args.signers.keys().cloned().collect(),
                 message_count: 1,
+                metadata: api::SessionMetadata {
+                    group: Some(self.args.public_key_package.verifying_key().serialize()?),
+                    ciphersuite: Some(C::ID.to_string()),
+                    kind: Some(api::SessionKind::Signing),
+                    description: self.args.description.clone(),
+                },
             })
             .await?;
 
@@ -124,13 +134,19 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                 .receive(&api::ReceiveArgs {
                     session_id: r.session_id,
                     as_coordinator: true,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                    after_seq: Some(self.last_seq),
                 })
                 .await?;
             for msg in r.msgs {
+                if let Some(event) = msg.event {
+                    return Err(eyre!("{event}").into());
+                }
+                let seq = msg.seq;
                 let msg = cipher.decrypt(msg)?;
                 self.state.recv(msg)?;
+                self.last_seq = seq;
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
             eprint!(".");
             if self.state.has_commitments() {
                 break;
@@ -179,6 +195,7 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                     session_id: self.session_id.unwrap(),
                     recipients: vec![recipient.clone()],
                     msg,
+                    msg_id: None,
                 })
                 .await?;
         }
@@ -191,13 +208,19 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                 .receive(&api::ReceiveArgs {
                     session_id: self.session_id.unwrap(),
                     as_coordinator: true,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                    after_seq: Some(self.last_seq),
                 })
                 .await?;
             for msg in r.msgs {
+                if let Some(event) = msg.event {
+                    return Err(eyre!("{event}").into());
+                }
+                let seq = msg.seq;
                 let msg = cipher.decrypt(msg)?;
                 self.state.recv(msg)?;
+                self.last_seq = seq;
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
             eprint!(".");
             if self.state.has_signature_shares() {
                 break;
@@ -220,12 +243,25 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
         Ok(signature_shares[0].clone())
     }
 
-    async fn cleanup_on_error(&mut self) -> Result<(), Box<dyn Error>> {
+    async fn cleanup_on_error(&mut self, error: &dyn Error) -> Result<(), Box<dyn Error>> {
         if let Some(session_id) = self.session_id {
-            let _r = self
+            // Let the other members know why the session is going away.
+            // Older servers don't support aborting, so close the session in
+            // that case.
+            let r = self
                 .client
-                .close_session(&api::CloseSessionArgs { session_id })
-                .await?;
+                .abort_session(&api::AbortSessionArgs {
+                    session_id,
+                    reason: api::AbortReason::Error,
+                    message: Some(error.to_string()),
+                })
+                .await;
+            if r.is_err() {
+                let _r = self
+                    .client
+                    .close_session(&api::CloseSessionArgs { session_id })
+                    .await?;
+            }
         }
         Ok(())
     }
@@ -125,8 +125,8 @@ pub async fn cli_for_processed_args<C: Ciphersuite + 'static + MaybeIntoEvenY>(
     };
 
     let r = doit.await;
-    if r.is_err() {
-        let _ = comms.cleanup_on_error().await;
+    if let Err(e) = &r {
+        let _ = comms.cleanup_on_error(&**e).await;
     }
     r
 }
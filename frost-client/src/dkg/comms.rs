@@ -52,7 +52,7 @@ pub trait Comms<C: Ciphersuite> {
     ) -> Result<HashMap<PublicKey, Identifier<C>>, Box<dyn Error>>;
 
     /// Do any cleanups in case an error occurs during the protocol run.
-    async fn cleanup_on_error(&mut self) -> Result<(), Box<dyn Error>> {
+    async fn cleanup_on_error(&mut self, _error: &dyn Error) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
 }
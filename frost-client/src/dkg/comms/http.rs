@@ -1,11 +1,10 @@
 //! HTTP implementation of the Comms trait.
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
     io::{BufRead, Write},
     marker::PhantomData,
-    time::Duration,
     vec,
 };
 
@@ -19,7 +18,7 @@ use frost_core::{
 use crate::cipher::Cipher;
 use crate::client::Client;
 use crate::{
-    api::{self, PublicKey, Uuid},
+    api::{self, Msg, PublicKey, Uuid},
     session::DKGSessionState,
 };
 use rand::thread_rng;
@@ -35,6 +34,14 @@ pub struct HTTPComms<C: Ciphersuite> {
     identifier: Option<Identifier<C>>,
     pubkeys: HashMap<PublicKey, Identifier<C>>,
     cipher: Option<Cipher>,
+    /// The sequence number of the last message received, used to
+    /// acknowledge it and avoid losing messages if a response is lost.
+    last_seq: u64,
+    /// The digest of the server attestation of the Round 1 Packages, if they
+    /// were broadcast with the server. It's sent along with the Round 2
+    /// Packages so that participants can check that they all got the same
+    /// Round 1 Packages.
+    broadcast_digest: Option<Vec<u8>>,
     _phantom: PhantomData<C>,
 }
 
@@ -48,9 +55,92 @@ impl<C: Ciphersuite> HTTPComms<C> {
             identifier: None,
             pubkeys: Default::default(),
             cipher: None,
+            last_seq: 0,
+            broadcast_digest: None,
             _phantom: Default::default(),
         })
     }
+
+    /// Broadcast this participant's Round 1 Package with the server and get
+    /// the other participants' ones, checking the server attestation of
+    /// them.
+    async fn broadcast_round1_package(
+        &mut self,
+        round1_package: &round1::Package<C>,
+    ) -> Result<(), Box<dyn Error>> {
+        let session_id = self.session_id.expect("set before");
+        let comm_pubkey = self
+            .args
+            .comm_pubkey
+            .clone()
+            .ok_or_eyre("comm_pubkey must be specified")?;
+        // Round 1 Packages are public, so they don't need to be encrypted.
+        let msg = serde_json::to_vec(round1_package)?;
+        self.client
+            .broadcast(&api::BroadcastArgs {
+                session_id,
+                msg: msg.clone(),
+            })
+            .await?;
+
+        eprint!("Waiting for other participants to broadcast their Round 1 Packages...");
+        let (msgs, attestation) = loop {
+            let r = self
+                .client
+                .get_broadcasts(&api::GetBroadcastsArgs {
+                    session_id,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                })
+                .await?;
+            eprint!(".");
+            if let Some(attestation) = r.attestation {
+                break (r.msgs, attestation);
+            }
+        };
+        eprintln!();
+
+        if attestation.session_id != session_id || !attestation.verify(&msgs) {
+            return Err(eyre!("invalid broadcast attestation").into());
+        }
+        let senders = msgs.iter().map(|m| &m.sender).collect::<HashSet<_>>();
+        if msgs.len() != self.pubkeys.len() || !self.pubkeys.keys().all(|p| senders.contains(p)) {
+            return Err(eyre!("not all participants broadcast their Round 1 Packages").into());
+        }
+        if !msgs.iter().any(|m| m.sender == comm_pubkey && m.msg == msg) {
+            return Err(eyre!("own Round 1 Package was not broadcast correctly").into());
+        }
+        for msg in msgs {
+            if msg.sender == comm_pubkey {
+                continue;
+            }
+            self.state
+                .recv(msg, self.identifier.expect("must have been set"))?;
+        }
+        // The attestation digest is checked when receiving the Round 2
+        // Packages, which replaces the echo broadcast.
+        self.state.skip_round1_broadcast()?;
+        self.broadcast_digest = Some(api::BroadcastAttestation::digest(
+            &session_id,
+            &attestation.hashes,
+        ));
+        Ok(())
+    }
+}
+
+/// Check that the sender of a Round 2 Package got the same Round 1 Packages
+/// from the server broadcast as this participant, i.e. that the digest sent
+/// along with the package matches the given one. Returns the message with
+/// just the Round 2 Package.
+fn check_broadcast_digest<C: Ciphersuite>(msg: Msg, digest: &[u8]) -> Result<Msg, Box<dyn Error>> {
+    let (sender_digest, round2_package): (Vec<u8>, round2::Package<C>) =
+        serde_json::from_slice(&msg.msg)?;
+    if sender_digest != digest {
+        return Err(eyre!("broadcast mismatch").into());
+    }
+    Ok(Msg {
+        msg: serde_json::to_vec(&round2_package)?,
+        ..msg
+    })
 }
 
 #[async_trait(?Send)]
@@ -64,13 +154,14 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
 
         eprintln!("Logging in...");
         let challenge = self.client.challenge().await?.challenge;
+        let payload = self.client.login_payload(&challenge).await?;
 
         let signature: [u8; 64] = self
             .args
             .comm_privkey
             .clone()
             .ok_or_eyre("comm_privkey must be specified")?
-            .sign(challenge.as_bytes(), &mut rng)?;
+            .sign(&payload, &mut rng)?;
 
         let comm_pubkey = self
             .args
@@ -93,6 +184,11 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                 .create_new_session(&api::CreateNewSessionArgs {
                     pubkeys: self.args.participants.clone(),
                     message_count: 1,
+                    metadata: api::SessionMetadata {
+                        ciphersuite: Some(C::ID.to_string()),
+                        kind: Some(api::SessionKind::Dkg),
+                        ..Default::default()
+                    },
                 })
                 .await?;
             r.session_id
@@ -178,6 +274,15 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
             )).collect::<Result<_,_>>()?,
         )?;
         self.cipher = Some(cipher);
+
+        // With more than 2 participants, the Round 1 Packages must be
+        // broadcast consistently. Use the server broadcast if available,
+        // which needs far fewer messages than the echo broadcast below.
+        if self.pubkeys.len() > 2 && self.client.supports(api::features::BROADCAST).await? {
+            self.broadcast_round1_package(&round1_package).await?;
+            return self.state.round1_packages();
+        }
+
         let cipher = self.cipher.as_mut().expect("was just set");
 
         // Send Round 1 Package to all other participants
@@ -191,6 +296,7 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                     session_id: self.session_id.expect("set before"),
                     recipients: vec![pubkey.clone()],
                     msg,
+                    msg_id: None,
                 })
                 .await?;
         }
@@ -203,14 +309,20 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                 .receive(&api::ReceiveArgs {
                     session_id: self.session_id.unwrap(),
                     as_coordinator: false,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                    after_seq: Some(self.last_seq),
                 })
                 .await?;
             for msg in r.msgs {
+                if let Some(event) = msg.event {
+                    return Err(eyre!("{event}").into());
+                }
+                let seq = msg.seq;
                 let msg = cipher.decrypt(msg)?;
                 self.state
                     .recv(msg, self.identifier.expect("must have been set"))?;
+                self.last_seq = seq;
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
             eprint!(".");
             if self.state.has_round1_packages() {
                 break;
@@ -241,6 +353,7 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                             session_id: self.session_id.expect("set before"),
                             recipients: vec![recipient_pubkey.clone()],
                             msg,
+                            msg_id: None,
                         })
                         .await?;
                 }
@@ -254,14 +367,20 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                     .receive(&api::ReceiveArgs {
                         session_id: self.session_id.unwrap(),
                         as_coordinator: false,
+                        wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                        after_seq: Some(self.last_seq),
                     })
                     .await?;
                 for msg in r.msgs {
+                    if let Some(event) = msg.event {
+                        return Err(eyre!("{event}").into());
+                    }
+                    let seq = msg.seq;
                     let msg = cipher.decrypt(msg)?;
                     self.state
                         .recv(msg, self.identifier.expect("must have been set"))?;
+                    self.last_seq = seq;
                 }
-                tokio::time::sleep(Duration::from_secs(2)).await;
                 eprint!(".");
                 if self.state.has_round1_broadcast_packages() {
                     break;
@@ -285,19 +404,20 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
             if Some(&pubkey) == self.args.comm_pubkey.as_ref() {
                 continue;
             }
-            let msg = cipher.encrypt(
-                Some(&pubkey),
-                serde_json::to_vec(
-                    &round2_packages
-                        .get(&identifier)
-                        .ok_or_eyre("must have Round 2 Package for the given identifier")?,
-                )?,
-            )?;
+            let round2_package = round2_packages
+                .get(&identifier)
+                .ok_or_eyre("must have Round 2 Package for the given identifier")?;
+            let msg = match &self.broadcast_digest {
+                Some(digest) => serde_json::to_vec(&(digest, round2_package))?,
+                None => serde_json::to_vec(round2_package)?,
+            };
+            let msg = cipher.encrypt(Some(&pubkey), msg)?;
             self.client
                 .send(&api::SendArgs {
                     session_id: self.session_id.expect("set before"),
                     recipients: vec![pubkey.clone()],
                     msg,
+                    msg_id: None,
                 })
                 .await?;
         }
@@ -310,14 +430,24 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                 .receive(&api::ReceiveArgs {
                     session_id: self.session_id.unwrap(),
                     as_coordinator: false,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                    after_seq: Some(self.last_seq),
                 })
                 .await?;
             for msg in r.msgs {
+                if let Some(event) = msg.event {
+                    return Err(eyre!("{event}").into());
+                }
+                let seq = msg.seq;
                 let msg = cipher.decrypt(msg)?;
+                let msg = match &self.broadcast_digest {
+                    Some(digest) => check_broadcast_digest::<C>(msg, digest)?,
+                    None => msg,
+                };
                 self.state
                     .recv(msg, self.identifier.expect("must have been set"))?;
+                self.last_seq = seq;
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
             eprint!(".");
             if self.state.has_round2_packages() {
                 break;
@@ -332,6 +462,16 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
                     session_id: self.session_id.unwrap(),
                 })
                 .await?;
+        } else {
+            // Acknowledge the last messages, since there won't be another
+            // `receive()` call to do it.
+            self.client
+                .ack(&api::AckArgs {
+                    session_id: self.session_id.unwrap(),
+                    as_coordinator: false,
+                    seq: self.last_seq,
+                })
+                .await?;
         }
 
         let _r = self.client.logout().await?;
@@ -348,12 +488,25 @@ impl<C: Ciphersuite + 'static> Comms<C> for HTTPComms<C> {
         }
     }
 
-    async fn cleanup_on_error(&mut self) -> Result<(), Box<dyn Error>> {
+    async fn cleanup_on_error(&mut self, error: &dyn Error) -> Result<(), Box<dyn Error>> {
         if let Some(session_id) = self.session_id {
-            let _r = self
+            // Let the other members know why the session is going away.
+            // Older servers don't support aborting, so close the session in
+            // that case.
+            let r = self
                 .client
-                .close_session(&api::CloseSessionArgs { session_id })
-                .await?;
+                .abort_session(&api::AbortSessionArgs {
+                    session_id,
+                    reason: api::AbortReason::Error,
+                    message: Some(error.to_string()),
+                })
+                .await;
+            if r.is_err() {
+                let _r = self
+                    .client
+                    .close_session(&api::CloseSessionArgs { session_id })
+                    .await?;
+            }
         }
         Ok(())
     }
@@ -71,9 +71,12 @@ pub async fn cli_for_processed_args<C: RandomizedCiphersuite + 'static>(
     )
     .await?;
 
-    comms
-        .confirm_message(input, logger, &round_2_config)
-        .await?;
+    if let Err(e) = comms.confirm_message(input, logger, &round_2_config).await {
+        if let Err(decline_err) = comms.decline(&e.to_string()).await {
+            writeln!(logger, "Error declining the session: {decline_err}")?;
+        }
+        return Err(e);
+    }
 
     let signature = generate_signature(round_2_config, key_package, &nonces)?;
 
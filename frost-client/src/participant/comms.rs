@@ -78,4 +78,11 @@ pub trait Comms<C: Ciphersuite> {
         identifier: Identifier<C>,
         signature_share: SignatureShare<C>,
     ) -> Result<(), Box<dyn Error>>;
+
+    /// Decline to take part in the signing session, e.g. if the user does not
+    /// want to sign the message, so that the coordinator does not keep
+    /// waiting for them.
+    async fn decline(&mut self, _reason: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
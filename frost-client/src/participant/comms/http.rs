@@ -4,7 +4,6 @@ use std::{
     error::Error,
     io::{BufRead, Write},
     marker::PhantomData,
-    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -107,6 +106,9 @@ pub struct HTTPComms<C: Ciphersuite> {
     access_token: Option<String>,
     args: ProcessedArgs<C>,
     cipher: Option<Cipher>,
+    /// The sequence number of the last message received, used to
+    /// acknowledge it and avoid losing messages if a response is lost.
+    last_seq: u64,
     _phantom: PhantomData<C>,
 }
 
@@ -122,6 +124,7 @@ where
             access_token: None,
             args: args.clone(),
             cipher: None,
+            last_seq: 0,
             _phantom: Default::default(),
         })
     }
@@ -144,13 +147,14 @@ where
 
         eprintln!("Logging in...");
         let challenge = self.client.challenge().await?.challenge;
+        let payload = self.client.login_payload(&challenge).await?;
 
         let signature: [u8; 64] = self
             .args
             .comm_privkey
             .clone()
             .ok_or_eyre("comm_privkey must be specified")?
-            .sign(challenge.as_bytes(), &mut rng)?;
+            .sign(&payload, &mut rng)?;
 
         self.access_token = Some(
             self.client
@@ -164,8 +168,7 @@ where
                     signature: signature.to_vec(),
                 })
                 .await?
-                .access_token
-                .to_string(),
+                .token(),
         );
 
         eprintln!("Joining signing session...");
@@ -216,6 +219,7 @@ where
                 // Empty recipients: Coordinator
                 recipients: vec![],
                 msg,
+                msg_id: None,
             })
             .await?;
 
@@ -229,21 +233,64 @@ where
                 .receive(&api::ReceiveArgs {
                     session_id,
                     as_coordinator: false,
+                    wait_timeout_ms: Some(api::MAX_WAIT_TIMEOUT_MS),
+                    after_seq: Some(self.last_seq),
                 })
                 .await?;
-            if r.msgs.is_empty() {
-                tokio::time::sleep(Duration::from_secs(2)).await;
-                eprint!(".");
-            } else {
+            let mut signing_package_msg = None;
+            for msg in r.msgs {
+                match &msg.event {
+                    Some(event @ api::SessionEvent::Aborted { .. }) => {
+                        return Err(eyre!("{event}").into());
+                    }
+                    // Skip other events such as other participants declining;
+                    // it's up to the coordinator to decide what to do about
+                    // them.
+                    Some(_) => self.last_seq = msg.seq,
+                    None => {
+                        signing_package_msg = Some(msg);
+                        break;
+                    }
+                }
+            }
+            if let Some(msg) = signing_package_msg {
                 eprintln!("\nSigning package received");
-                let msg = cipher.decrypt(r.msgs[0].clone())?;
-                break serde_json::from_slice(&msg.msg)?;
+                let seq = msg.seq;
+                let msg = cipher.decrypt(msg)?;
+                let r = serde_json::from_slice(&msg.msg)?;
+                self.last_seq = seq;
+                break r;
+            } else {
+                eprint!(".");
             }
         };
 
+        // Acknowledge the signing package, since there won't be another
+        // `receive()` call to do it.
+        self.client
+            .ack(&api::AckArgs {
+                session_id,
+                as_coordinator: false,
+                seq: self.last_seq,
+            })
+            .await?;
+
         Ok(r)
     }
 
+    async fn decline(&mut self, reason: &str) -> Result<(), Box<dyn Error>> {
+        let Some(session_id) = self.session_id else {
+            return Ok(());
+        };
+        self.client
+            .decline_session(&api::DeclineSessionArgs {
+                session_id,
+                reason: Some(reason.to_string()),
+            })
+            .await?;
+        Ok(())
+    }
+
     async fn send_signature_share(
         &mut self,
         _identifier: Identifier<C>,
@@ -266,6 +313,7 @@ where
                 // Empty recipients: Coordinator
                 recipients: vec![],
                 msg,
+                msg_id: None,
             })
             .await?;
 
@@ -526,6 +526,29 @@ impl<C: Ciphersuite> DKGSessionState<C> {
         }
     }
 
+    /// Skip the echo broadcast round, when the Round 1 Packages were
+    /// broadcast by other means that ensure every participant got the same
+    /// ones (e.g. a server broadcast whose attestation is checked by all
+    /// participants).
+    pub fn skip_round1_broadcast(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            DKGSessionState::WaitingForRound1PackagesBroadcast {
+                pubkeys,
+                round1_packages,
+                ..
+            } => {
+                *self = DKGSessionState::WaitingForRound2Packages {
+                    pubkeys: pubkeys.clone(),
+                    round1_packages: round1_packages.clone(),
+                    round2_packages: Default::default(),
+                };
+                Ok(())
+            }
+            DKGSessionState::WaitingForRound2Packages { .. } => Ok(()),
+            _ => Err(eyre!("wrong state").into()),
+        }
+    }
+
     /// Returns if all participants sent their broadcast Round 1 Packages,
     /// or if the echo broadcast round should be skipped.
     ///
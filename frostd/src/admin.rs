@@ -0,0 +1,157 @@
+//! Handlers for the admin API, which can only be called by administrators.
+
+use axum::{extract::State, Json};
+
+use crate::{
+    functions::{unix_timestamp, IntoResponseError},
+    state::SharedState,
+    user::{pubkey_hash, Admin},
+};
+use frost_client::api::*;
+
+/// Implement the admin/add_user API.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip_all,
+    fields(user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn add_user(
+    State(state): State<SharedState>,
+    admin: Admin,
+    Json(args): Json<AdminAddUserArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    tracing::info!(
+        "admin {} adding user {}",
+        admin.0.pubkey_hash(),
+        pubkey_hash(&args.pubkey)
+    );
+    state.users.add(args.pubkey)?;
+    Ok(Json(()))
+}
+
+/// Implement the admin/remove_user API. This also revokes all access tokens
+/// of the removed user.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip_all,
+    fields(user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn remove_user(
+    State(state): State<SharedState>,
+    admin: Admin,
+    Json(args): Json<AdminRemoveUserArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    tracing::info!(
+        "admin {} removing user {}",
+        admin.0.pubkey_hash(),
+        pubkey_hash(&args.pubkey)
+    );
+    state.users.remove(&args.pubkey)?;
+    state.revoke_access_tokens(&args.pubkey);
+    Ok(Json(()))
+}
+
+/// Implement the admin/list_users API.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip(state, admin),
+    fields(user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn list_users(
+    State(state): State<SharedState>,
+    admin: Admin,
+) -> Result<Json<AdminListUsersOutput>, IntoResponseError> {
+    Ok(Json(AdminListUsersOutput {
+        pubkeys: state.users.list(),
+    }))
+}
+
+/// Implement the admin/list_sessions API, which lists all open sessions.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip(state, admin),
+    fields(user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn list_sessions(
+    State(state): State<SharedState>,
+    admin: Admin,
+) -> Result<Json<AdminListSessionsOutput>, IntoResponseError> {
+    let sessions = state.sessions.sessions.read().unwrap();
+    let sessions = sessions
+        .iter()
+        .map(|(session_id, session)| {
+            let queues = session.queue.values();
+            AdminSessionInfo {
+                session_id: *session_id,
+                coordinator_pubkey: session.coordinator_pubkey.clone(),
+                pubkeys: session.pubkeys.clone(),
+                message_count: session.message_count,
+                created_at: unix_timestamp(session.created_at),
+                queued_messages: queues.clone().map(|q| q.len()).sum(),
+                queued_bytes: queues.flatten().map(|m| m.msg.len()).sum(),
+                last_activity: unix_timestamp(session.last_activity),
+                expires_at: unix_timestamp(session.expires_at),
+            }
+        })
+        .collect();
+    Ok(Json(AdminListSessionsOutput { sessions }))
+}
+
+/// Implement the admin/close_session API, which closes a session regardless
+/// of who its coordinator is.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip(state, admin, args),
+    fields(session_id = %args.session_id, user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn close_session(
+    State(state): State<SharedState>,
+    admin: Admin,
+    Json(args): Json<AdminCloseSessionArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    tracing::info!(
+        "admin {} closing session {}",
+        admin.0.pubkey_hash(),
+        args.session_id
+    );
+    let mut sessions = state.sessions.sessions.write().unwrap();
+    let mut sessions_by_pubkey = state.sessions.sessions_by_pubkey.write().unwrap();
+    state
+        .remove_session(&mut sessions, &mut sessions_by_pubkey, &args.session_id)
+        .ok_or(Error::SessionNotFound)?;
+    state.metrics.sessions_closed.inc();
+    Ok(Json(()))
+}
+
+/// Implement the admin/revoke_tokens API, which revokes all access tokens of
+/// a user, logging them out.
+#[tracing::instrument(
+    level = "info",
+    ret(level = "debug"),
+    err(Debug),
+    skip_all,
+    fields(user = %admin.0.pubkey_hash())
+)]
+pub(crate) async fn revoke_tokens(
+    State(state): State<SharedState>,
+    admin: Admin,
+    Json(args): Json<AdminRevokeTokensArgs>,
+) -> Result<Json<AdminRevokeTokensOutput>, IntoResponseError> {
+    tracing::info!(
+        "admin {} revoking access tokens of {}",
+        admin.0.pubkey_hash(),
+        pubkey_hash(&args.pubkey)
+    );
+    let revoked = state.revoke_access_tokens(&args.pubkey);
+    Ok(Json(AdminRevokeTokensOutput { revoked }))
+}
@@ -1,44 +1,296 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use uuid::Uuid;
 
+use crate::{limits::IpRange, logging::LogFormat, tls::ClientAuth};
+
+/// The command line arguments.
+///
+/// Every argument can also be set with the environment variable shown in the
+/// help, and overrides the corresponding value of the configuration file (see
+/// [`crate::config::Config`]). Defaults are only applied after reading the
+/// configuration file, so they are shown in the descriptions.
 #[derive(Parser, Debug, Default)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// IP to bind to.
+    /// Run a command instead of the server.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to a TOML configuration file.
+    #[arg(long, env = "FROSTD_CONFIG")]
+    pub config: Option<String>,
+
+    /// IP to bind to. [default: 0.0.0.0]
     ///
     /// If `no_tls_very_insecure` is set, it will bind to 127.0.0.1
     /// regardless of the value passed here.
-    #[arg(short, long, default_value = "0.0.0.0")]
-    pub ip: String,
+    #[arg(short, long, env = "FROSTD_IP")]
+    pub ip: Option<String>,
 
-    /// Port to bind to.
-    #[arg(short, long, default_value_t = 2744)]
-    pub port: u16,
+    /// Port to bind to. [default: 2744]
+    #[arg(short, long, env = "FROSTD_PORT")]
+    pub port: Option<u16>,
 
-    /// The path of the certificate to use for HTTPS (PEM format).
+    /// The path of the certificate to use for HTTPS (PEM format). It is
+    /// reloaded when the file changes or SIGHUP is received.
     ///
     /// For production deployments, it's recommended to provide HTTPS using
-    /// a reverse proxy such as nginx. In that case, set `no_tls_very_insecure`
-    /// instead.
-    #[arg(short = 'c', long)]
+    /// a reverse proxy such as nginx. In that case, set `unix_socket` or
+    /// `no_tls_very_insecure` instead.
+    #[arg(short = 'c', long, env = "FROSTD_TLS_CERT")]
     pub tls_cert: Option<String>,
 
     /// The path of the private key to use for HTTPS (PEM format).
-    #[arg(short = 'k', long)]
+    #[arg(short = 'k', long, env = "FROSTD_TLS_KEY")]
     pub tls_key: Option<String>,
 
+    /// Use a self-signed certificate generated on startup, for development.
+    /// Its fingerprint is logged so that clients can check it. Can't be used
+    /// with `tls_cert` and `tls_key`.
+    #[arg(long, default_value_t = false, env = "FROSTD_DEV_TLS")]
+    pub dev_tls: bool,
+
+    /// The path of the CA certificates (PEM format) that issue client
+    /// certificates. If specified, clients must authenticate with a
+    /// certificate issued by one of them (see `tls_client_auth`).
+    #[arg(long, env = "FROSTD_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<String>,
+
+    /// Whether clients must send a certificate when `tls_client_ca` is
+    /// specified. [default: required]
+    #[arg(long, value_enum, env = "FROSTD_TLS_CLIENT_AUTH")]
+    pub tls_client_auth: Option<ClientAuth>,
+
+    /// Only allow users to log in with the communication public key named in
+    /// their client certificate, as its subject common name or as a URI
+    /// subject alternative name (`urn:frost:pubkey:<hex>`). Requires
+    /// `tls_client_ca`.
+    #[arg(long, default_value_t = false, env = "FROSTD_TLS_CLIENT_CERT_BINDING")]
+    pub tls_client_cert_binding: bool,
+
     /// Flag to disable TLS/HTTPS. DO NOT set this flag unless you're providing
     /// TLS/HTTPS on your own (e.g. with nginx or another reverse proxy).
-    #[arg(short, long, default_value_t = false)]
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        env = "FROSTD_NO_TLS_VERY_INSECURE"
+    )]
     pub no_tls_very_insecure: bool,
+
+    /// Path of a Unix domain socket to listen on instead of the IP and port,
+    /// e.g. when running behind a reverse proxy on the same host. Requests are
+    /// served over plain HTTP, so it can't be used with the TLS options. A
+    /// stale socket file left by a previous run is replaced.
+    #[arg(long, env = "FROSTD_UNIX_SOCKET")]
+    pub unix_socket: Option<String>,
+
+    /// The permissions of the Unix domain socket file, in octal. Only
+    /// processes allowed to write to it can connect. [default: 660]
+    #[arg(long, env = "FROSTD_UNIX_SOCKET_MODE")]
+    pub unix_socket_mode: Option<String>,
+
+    /// Directory where sessions, their message queues and access tokens are
+    /// persisted, allowing them to survive server restarts. If not specified,
+    /// all state is kept in memory and lost when the server stops.
+    #[arg(short = 's', long, env = "FROSTD_STORAGE_DIR")]
+    pub storage_dir: Option<String>,
+
+    /// Path to a file with the hex-encoded communication public keys of the
+    /// users allowed to log in, one per line. If specified, only those users
+    /// (and administrators) can log in. The list can be changed with the admin
+    /// API, which will rewrite the file. If not specified, any user can log
+    /// in.
+    #[arg(short, long, env = "FROSTD_ALLOWLIST")]
+    pub allowlist: Option<String>,
+
+    /// The comma-separated hex-encoded communication public keys of the
+    /// server administrators, which can call the admin API.
+    #[arg(long, value_delimiter = ',', env = "FROSTD_ADMIN_PUBKEY")]
+    pub admin_pubkey: Vec<String>,
+
+    /// The comma-separated names (`host[:port]`, with port 443 if omitted)
+    /// clients use to reach the server, e.g. `frost.example.com`. Users sign
+    /// the name when logging in, which prevents other servers from using
+    /// their signatures to log in as them. Required unless `dev_tls` or
+    /// `no_tls_very_insecure` is set, in which case the name in each login
+    /// request is used if not specified, which does not prevent that.
+    #[arg(long, value_delimiter = ',', env = "FROSTD_SERVER_NAME")]
+    pub server_name: Vec<String>,
+
+    /// Accept login signatures of just the challenge, as made by older
+    /// clients. They can be used by other servers to log in as the users.
+    #[arg(long, default_value_t = false, env = "FROSTD_ALLOW_LEGACY_LOGIN")]
+    pub allow_legacy_login: bool,
+
+    /// Path to a file with a hex-encoded secret key of at least 32 bytes
+    /// (e.g. created with `openssl rand -hex 32`). If specified, access
+    /// tokens are signed with it instead of being kept by the server, so they
+    /// survive restarts and are accepted by any server with the same key.
+    /// However, revocations (logging out, refreshing a token, or an admin
+    /// revoking tokens or removing a user) are only known by the server that
+    /// made them, which keeps them in memory and in its storage; other
+    /// servers with the same key keep accepting the revoked tokens until they
+    /// expire. Clients must support signed tokens.
+    #[arg(long, env = "FROSTD_TOKEN_KEY_FILE")]
+    pub token_key_file: Option<String>,
+
+    /// Maximum size in bytes of a single message. [default: 65535, which is
+    /// also the maximum]
+    #[arg(long, env = "FROSTD_MAX_MSG_SIZE")]
+    pub max_msg_size: Option<usize>,
+
+    /// Maximum number of challenges per minute from a single IP. 0 means no
+    /// limit. [default: 60]
+    #[arg(long, env = "FROSTD_MAX_CHALLENGES_PER_IP")]
+    pub max_challenges_per_ip: Option<u32>,
+
+    /// Maximum number of logins per minute from a single IP. 0 means no
+    /// limit. [default: 60]
+    #[arg(long, env = "FROSTD_MAX_LOGINS_PER_IP")]
+    pub max_logins_per_ip: Option<u32>,
+
+    /// Maximum number of logins per minute for a single user. 0 means no
+    /// limit. [default: 20]
+    #[arg(long, env = "FROSTD_MAX_LOGINS_PER_PUBKEY")]
+    pub max_logins_per_pubkey: Option<u32>,
+
+    /// Maximum number of open sessions a single user can coordinate. 0 means
+    /// no limit. [default: 100]
+    #[arg(long, env = "FROSTD_MAX_SESSIONS_PER_COORDINATOR")]
+    pub max_sessions_per_coordinator: Option<usize>,
+
+    /// Maximum number of messages queued for a single recipient in a session.
+    /// 0 means no limit. [default: 1000]
+    #[arg(long, env = "FROSTD_MAX_QUEUED_MESSAGES")]
+    pub max_queued_messages: Option<usize>,
+
+    /// Maximum total size in bytes of the messages queued for a single
+    /// recipient in a session. 0 means no limit. [default: 16777216]
+    #[arg(long, env = "FROSTD_MAX_QUEUED_BYTES")]
+    pub max_queued_bytes: Option<usize>,
+
+    /// Comma-separated IP ranges in CIDR notation (e.g. `10.0.0.0/8`) or IPs
+    /// allowed to access the server. If not specified, all IPs are allowed
+    /// (except those in `ip_denylist`). Note that if the server is behind a
+    /// reverse proxy, this applies to the IP of the proxy.
+    #[arg(long, value_delimiter = ',', env = "FROSTD_IP_ALLOWLIST")]
+    pub ip_allowlist: Vec<IpRange>,
+
+    /// Comma-separated IP ranges in CIDR notation or IPs not allowed to access
+    /// the server.
+    #[arg(long, value_delimiter = ',', env = "FROSTD_IP_DENYLIST")]
+    pub ip_denylist: Vec<IpRange>,
+
+    /// How long, in seconds, a session stays open without activity.
+    /// [default: 86400]
+    #[arg(long, env = "FROSTD_SESSION_TIMEOUT_SECS")]
+    pub session_timeout_secs: Option<u64>,
+
+    /// How long, in seconds, a login challenge can be replied to.
+    /// [default: 10]
+    #[arg(long, env = "FROSTD_CHALLENGE_TIMEOUT_SECS")]
+    pub challenge_timeout_secs: Option<u64>,
+
+    /// How long, in seconds, an access token lasts. [default: 3600]
+    #[arg(long, env = "FROSTD_ACCESS_TOKEN_TIMEOUT_SECS")]
+    pub access_token_timeout_secs: Option<u64>,
+
+    /// How long, in seconds, to wait for in-flight requests to finish when
+    /// shutting down on SIGTERM or Ctrl-C. [default: 10]
+    #[arg(long, env = "FROSTD_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+
+    /// Address (IP and port, e.g. `127.0.0.1:9090`) to serve Prometheus
+    /// metrics at, under `/metrics`. The metrics are served over plain HTTP,
+    /// so this should not be exposed publicly. If not specified, metrics are
+    /// not served.
+    #[arg(long, env = "FROSTD_METRICS_ADDR")]
+    pub metrics_addr: Option<String>,
+
+    /// The default logging filter (e.g. `info` or `frostd=debug`). The
+    /// `RUST_LOG` environment variable takes precedence over it.
+    /// [default: info]
+    #[arg(long, env = "FROSTD_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// The format of the logs. [default: text]
+    #[arg(long, env = "FROSTD_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// The endpoint of an OpenTelemetry collector (e.g.
+    /// `http://localhost:4317`) to export spans to with OTLP over gRPC.
+    /// Requires frostd to be built with the `otlp` feature.
+    #[arg(long, env = "FROSTD_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Call the admin API of a running server. The communication key in the
+    /// frost-client config file is used to log in, and it must be one of the
+    /// administrator keys of the server.
+    Admin {
+        /// The path to the frost-client config file with the administrator
+        /// communication key. If not specified, it uses
+        /// $HOME/.local/frost/credentials.toml
+        #[arg(short, long)]
+        config: Option<String>,
+        /// The server URL (host:port).
+        #[arg(short, long)]
+        server_url: String,
+        #[command(flatten)]
+        connection: AdminConnection,
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Print the OpenAPI description of the API (in JSON) and exit.
+    Openapi,
+}
+
+/// How the `admin` command connects to the server.
+#[derive(ClapArgs, Debug, Clone, Default)]
+pub struct AdminConnection {
+    /// Path to a PEM file with the TLS client certificate to authenticate
+    /// with, for servers that require client certificates.
+    #[arg(long, requires = "tls_client_key")]
+    pub tls_client_cert: Option<String>,
+    /// Path to a PEM file with the private key of the TLS client
+    /// certificate.
+    #[arg(long, requires = "tls_client_cert")]
+    pub tls_client_key: Option<String>,
+    /// Path to the Unix domain socket of the server (see `unix_socket`), to
+    /// connect to a server on the same host through it. The server URL is
+    /// still used as the server identity when logging in.
+    #[arg(long, conflicts_with_all = ["tls_client_cert", "tls_client_key"])]
+    pub unix_socket: Option<String>,
 }
 
-impl Args {
-    /// Get the effective IP to use, considering the arguments passed.
-    pub fn ip(&self) -> String {
-        if self.no_tls_very_insecure {
-            "127.0.0.1".to_string()
-        } else {
-            self.ip.clone()
-        }
-    }
+#[derive(Subcommand, Debug, Clone)]
+pub enum AdminCommand {
+    /// List all open sessions, with their participants, queue sizes and last
+    /// activity.
+    ListSessions,
+    /// Close a session, even if not its coordinator.
+    CloseSession {
+        /// The ID of the session to close.
+        session_id: Uuid,
+    },
+    /// Revoke all access tokens of a user, logging them out.
+    RevokeTokens {
+        /// The hex-encoded communication public key of the user.
+        pubkey: String,
+    },
+    /// List the users in the allowlist.
+    ListUsers,
+    /// Add a user to the allowlist.
+    AddUser {
+        /// The hex-encoded communication public key of the user.
+        pubkey: String,
+    },
+    /// Remove a user from the allowlist, revoking their access tokens.
+    RemoveUser {
+        /// The hex-encoded communication public key of the user.
+        pubkey: String,
+    },
 }
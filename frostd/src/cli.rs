@@ -0,0 +1,148 @@
+//! Implementation of the `frostd admin` command, which calls the admin API of
+//! a running server.
+
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::OptionExt as _;
+use frost_client::{api, cli::config::Config, client::Client};
+use rand::thread_rng;
+
+use crate::{
+    args::{AdminCommand, AdminConnection},
+    registry::parse_pubkey,
+    PublicKey,
+};
+
+/// Log in to the server with the communication key in the given
+/// frost-client config file and run the given admin command.
+pub async fn admin(
+    config: Option<String>,
+    server_url: &str,
+    connection: &AdminConnection,
+    command: &AdminCommand,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::read(config)?;
+    let comm_key = config
+        .communication_key
+        .clone()
+        .ok_or_eyre("user not initialized")?;
+
+    let mut client = connect(server_url, connection)?;
+    let challenge = client.challenge().await?.challenge;
+    let payload = client.login_payload(&challenge).await?;
+    let signature: [u8; 64] = comm_key.privkey.sign(&payload, &mut thread_rng())?;
+    client
+        .login(&api::LoginArgs {
+            challenge,
+            pubkey: comm_key.pubkey.clone(),
+            signature: signature.to_vec(),
+        })
+        .await?;
+
+    // Describe a public key with the contact name, if known.
+    let describe = |pubkey: &PublicKey| match config.contact_by_pubkey(pubkey) {
+        Ok(contact) if !contact.name.is_empty() => {
+            format!("{} ({})", contact.name, hex::encode(&pubkey.0))
+        }
+        _ => hex::encode(&pubkey.0),
+    };
+
+    match command {
+        AdminCommand::ListSessions => {
+            let sessions = client.admin_list_sessions().await?.sessions;
+            if sessions.is_empty() {
+                eprintln!("No active sessions.");
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            for session in sessions {
+                println!("Session with ID {}", session.session_id);
+                println!("Coordinator: {}", describe(&session.coordinator_pubkey));
+                println!("Signers: {}", session.pubkeys.len());
+                for pubkey in &session.pubkeys {
+                    println!("\t{}", describe(pubkey));
+                }
+                println!(
+                    "Queued: {} messages, {} bytes",
+                    session.queued_messages, session.queued_bytes
+                );
+                println!(
+                    "Last activity: {}s ago; expires in {}s",
+                    now.saturating_sub(session.last_activity),
+                    session.expires_at.saturating_sub(now)
+                );
+                println!();
+            }
+        }
+        AdminCommand::CloseSession { session_id } => {
+            client
+                .admin_close_session(&api::AdminCloseSessionArgs {
+                    session_id: *session_id,
+                })
+                .await?;
+            eprintln!("Session {session_id} closed.");
+        }
+        AdminCommand::RevokeTokens { pubkey } => {
+            let r = client
+                .admin_revoke_tokens(&api::AdminRevokeTokensArgs {
+                    pubkey: parse_pubkey(pubkey)?,
+                })
+                .await?;
+            eprintln!("Revoked {} access tokens.", r.revoked);
+        }
+        AdminCommand::ListUsers => match client.admin_list_users().await?.pubkeys {
+            Some(pubkeys) => {
+                for pubkey in pubkeys {
+                    println!("{}", describe(&pubkey));
+                }
+            }
+            None => eprintln!("The allowlist is not enabled; any user can log in."),
+        },
+        AdminCommand::AddUser { pubkey } => {
+            client
+                .admin_add_user(&api::AdminAddUserArgs {
+                    pubkey: parse_pubkey(pubkey)?,
+                })
+                .await?;
+            eprintln!("User added.");
+        }
+        AdminCommand::RemoveUser { pubkey } => {
+            client
+                .admin_remove_user(&api::AdminRemoveUserArgs {
+                    pubkey: parse_pubkey(pubkey)?,
+                })
+                .await?;
+            eprintln!("User removed.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a client for the server, connecting to it as requested.
+fn connect(server_url: &str, connection: &AdminConnection) -> Result<Client, Box<dyn Error>> {
+    let host_port = format!("https://{server_url}");
+    if let Some(unix_socket) = &connection.unix_socket {
+        #[cfg(unix)]
+        return Ok(Client::with_unix_socket(host_port, unix_socket));
+        #[cfg(not(unix))]
+        return Err(
+            eyre::eyre!("unix sockets are not supported on this platform ({unix_socket})").into(),
+        );
+    }
+    match (&connection.tls_client_cert, &connection.tls_client_key) {
+        (Some(cert), Some(key)) => {
+            let mut pem = std::fs::read(cert)?;
+            pem.push(b'\n');
+            pem.extend(std::fs::read(key)?);
+            Ok(Client::with_client_cert(host_port, &pem)?)
+        }
+        (None, None) => Ok(Client::new(host_port)),
+        _ => Err(eyre::eyre!("tls_client_cert and tls_client_key must be set together").into()),
+    }
+}
@@ -0,0 +1,350 @@
+//! Server configuration.
+//!
+//! The configuration is read from an optional TOML file, and then each value
+//! can be overridden with command line arguments or environment variables
+//! (see [`Args`]).
+//!
+//! Example configuration file:
+//!
+//! ```toml
+//! ip = "0.0.0.0"
+//! port = 2744
+//! tls_cert = "/etc/frostd/cert.pem"
+//! tls_key = "/etc/frostd/key.pem"
+//! log_level = "info"
+//! log_format = "json"
+//! server_names = ["frost.example.com"]
+//!
+//! [timeouts]
+//! session_secs = 3600
+//!
+//! [limits]
+//! max_msg_size = 32768
+//! sessions_per_coordinator = 10
+//! ```
+
+use std::{fs, path::Path, time::Duration};
+
+use eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::Args,
+    limits::Limits,
+    logging::LogFormat,
+    state::{ACCESS_TOKEN_TIMEOUT, CHALLENGE_TIMEOUT, SESSION_TIMEOUT, SHUTDOWN_TIMEOUT},
+    tls::ClientAuth,
+    MAX_MSG_SIZE,
+};
+
+/// The server configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// IP to bind to. If `no_tls_very_insecure` is set, 127.0.0.1 is used
+    /// regardless of this value.
+    pub ip: String,
+    /// Port to bind to.
+    pub port: u16,
+    /// The path of the certificate to use for HTTPS (PEM format).
+    pub tls_cert: Option<String>,
+    /// The path of the private key to use for HTTPS (PEM format).
+    pub tls_key: Option<String>,
+    /// Use a self-signed certificate for development. See
+    /// [`Args::dev_tls`].
+    pub dev_tls: bool,
+    /// The path of the CA certificates that issue client certificates (PEM
+    /// format), if clients authenticate with certificates.
+    pub tls_client_ca: Option<String>,
+    /// Whether clients must send a certificate, if `tls_client_ca` is set.
+    pub tls_client_auth: ClientAuth,
+    /// Only allow users to log in with the public key named in their client
+    /// certificate. See [`Args::tls_client_cert_binding`].
+    pub tls_client_cert_binding: bool,
+    /// Disable TLS/HTTPS. See [`Args::no_tls_very_insecure`].
+    pub no_tls_very_insecure: bool,
+    /// Path of a Unix domain socket to listen on instead of the IP and port,
+    /// if any. See [`Args::unix_socket`].
+    pub unix_socket: Option<String>,
+    /// The permissions of the Unix domain socket file, in octal (e.g.
+    /// `"660"`).
+    pub unix_socket_mode: String,
+    /// Directory where the state is persisted, if any.
+    pub storage_dir: Option<String>,
+    /// Path to the allowlist file, if any.
+    pub allowlist: Option<String>,
+    /// The hex-encoded communication public keys of the administrators.
+    pub admin_pubkeys: Vec<String>,
+    /// The names clients use to reach the server. See [`Args::server_name`].
+    pub server_names: Vec<String>,
+    /// Accept login signatures of just the challenge. See
+    /// [`Args::allow_legacy_login`].
+    pub allow_legacy_login: bool,
+    /// Path to the file with the key to sign access tokens with, if any. See
+    /// [`Args::token_key_file`].
+    pub token_key_file: Option<String>,
+    /// Address to serve Prometheus metrics at, if any.
+    pub metrics_addr: Option<String>,
+    /// The default logging filter (e.g. `info` or `frostd=debug`). The
+    /// `RUST_LOG` environment variable takes precedence over it.
+    pub log_level: String,
+    /// The format of the logs.
+    pub log_format: LogFormat,
+    /// The OTLP endpoint to export spans to, if any. Requires the `otlp`
+    /// feature.
+    pub otlp_endpoint: Option<String>,
+    /// Timeouts of sessions, challenges and access tokens.
+    pub timeouts: Timeouts,
+    /// Limits enforced by the server.
+    pub limits: Limits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ip: "0.0.0.0".to_string(),
+            port: 2744,
+            tls_cert: None,
+            tls_key: None,
+            dev_tls: false,
+            tls_client_ca: None,
+            tls_client_auth: ClientAuth::Required,
+            tls_client_cert_binding: false,
+            no_tls_very_insecure: false,
+            unix_socket: None,
+            unix_socket_mode: "660".to_string(),
+            storage_dir: None,
+            allowlist: None,
+            admin_pubkeys: Vec::new(),
+            server_names: Vec::new(),
+            allow_legacy_login: false,
+            token_key_file: None,
+            metrics_addr: None,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+            timeouts: Default::default(),
+            limits: Default::default(),
+        }
+    }
+}
+
+/// Timeouts used by the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// How long a session stays open without activity.
+    #[serde(rename = "session_secs", with = "secs")]
+    pub session: Duration,
+    /// How long a challenge can be replied to.
+    #[serde(rename = "challenge_secs", with = "secs")]
+    pub challenge: Duration,
+    /// How long an access token lasts.
+    #[serde(rename = "access_token_secs", with = "secs")]
+    pub access_token: Duration,
+    /// How long to wait for in-flight requests to finish when shutting down.
+    #[serde(rename = "shutdown_secs", with = "secs")]
+    pub shutdown: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            session: SESSION_TIMEOUT,
+            challenge: CHALLENGE_TIMEOUT,
+            access_token: ACCESS_TOKEN_TIMEOUT,
+            shutdown: SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+/// Serializes a Duration as a number of seconds.
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+    }
+}
+
+impl Config {
+    /// Load the configuration from the file specified in the arguments (if
+    /// any), overriding it with the other arguments.
+    pub fn load(args: &Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read the configuration from a TOML file. Missing values are set to
+    /// their defaults.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| eyre!("error reading {}: {}", path.as_ref().display(), e))?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Override the configuration with the values passed in the arguments.
+    fn apply_args(&mut self, args: &Args) {
+        fn set<T: Clone>(value: &mut T, arg: &Option<T>) {
+            if let Some(arg) = arg {
+                *value = arg.clone();
+            }
+        }
+        fn set_opt<T: Clone>(value: &mut Option<T>, arg: &Option<T>) {
+            if arg.is_some() {
+                *value = arg.clone();
+            }
+        }
+        fn set_vec<T: Clone>(value: &mut Vec<T>, arg: &[T]) {
+            if !arg.is_empty() {
+                *value = arg.to_vec();
+            }
+        }
+
+        set(&mut self.ip, &args.ip);
+        set(&mut self.port, &args.port);
+        set_opt(&mut self.tls_cert, &args.tls_cert);
+        set_opt(&mut self.tls_key, &args.tls_key);
+        self.dev_tls |= args.dev_tls;
+        set_opt(&mut self.tls_client_ca, &args.tls_client_ca);
+        set(&mut self.tls_client_auth, &args.tls_client_auth);
+        self.tls_client_cert_binding |= args.tls_client_cert_binding;
+        self.no_tls_very_insecure |= args.no_tls_very_insecure;
+        set_opt(&mut self.unix_socket, &args.unix_socket);
+        set(&mut self.unix_socket_mode, &args.unix_socket_mode);
+        set_opt(&mut self.storage_dir, &args.storage_dir);
+        set_opt(&mut self.allowlist, &args.allowlist);
+        set_vec(&mut self.admin_pubkeys, &args.admin_pubkey);
+        set_vec(&mut self.server_names, &args.server_name);
+        self.allow_legacy_login |= args.allow_legacy_login;
+        set_opt(&mut self.token_key_file, &args.token_key_file);
+        set_opt(&mut self.metrics_addr, &args.metrics_addr);
+        set(&mut self.log_level, &args.log_level);
+        set(&mut self.log_format, &args.log_format);
+        set_opt(&mut self.otlp_endpoint, &args.otlp_endpoint);
+
+        let timeouts = &mut self.timeouts;
+        set(
+            &mut timeouts.session,
+            &args.session_timeout_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut timeouts.challenge,
+            &args.challenge_timeout_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut timeouts.access_token,
+            &args.access_token_timeout_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut timeouts.shutdown,
+            &args.shutdown_timeout_secs.map(Duration::from_secs),
+        );
+
+        let limits = &mut self.limits;
+        set(&mut limits.max_msg_size, &args.max_msg_size);
+        set(&mut limits.challenges_per_ip, &args.max_challenges_per_ip);
+        set(&mut limits.logins_per_ip, &args.max_logins_per_ip);
+        set(&mut limits.logins_per_pubkey, &args.max_logins_per_pubkey);
+        set(
+            &mut limits.sessions_per_coordinator,
+            &args.max_sessions_per_coordinator,
+        );
+        set(
+            &mut limits.queued_messages_per_recipient,
+            &args.max_queued_messages,
+        );
+        set(
+            &mut limits.queued_bytes_per_recipient,
+            &args.max_queued_bytes,
+        );
+        set_vec(&mut limits.ip_allowlist, &args.ip_allowlist);
+        set_vec(&mut limits.ip_denylist, &args.ip_denylist);
+    }
+
+    /// Check if the configuration is valid.
+    fn validate(&self) -> Result<(), eyre::Report> {
+        let timeouts = &self.timeouts;
+        if timeouts.session.is_zero()
+            || timeouts.challenge.is_zero()
+            || timeouts.access_token.is_zero()
+        {
+            return Err(eyre!("timeouts must be greater than zero"));
+        }
+        if self.limits.max_msg_size == 0 || self.limits.max_msg_size > MAX_MSG_SIZE {
+            return Err(eyre!("max_msg_size must be between 1 and {MAX_MSG_SIZE}"));
+        }
+        if self.dev_tls
+            && (self.tls_cert.is_some() || self.tls_key.is_some() || self.no_tls_very_insecure)
+        {
+            return Err(eyre!(
+                "dev_tls can't be used with tls_cert, tls_key or no_tls_very_insecure"
+            ));
+        }
+        if self.tls_client_ca.is_some() && self.no_tls_very_insecure {
+            return Err(eyre!("tls_client_ca can't be used without TLS"));
+        }
+        if self.tls_client_cert_binding && self.tls_client_ca.is_none() {
+            return Err(eyre!("tls_client_cert_binding requires tls_client_ca"));
+        }
+        if self.unix_socket.is_some()
+            && (self.tls_cert.is_some()
+                || self.tls_key.is_some()
+                || self.dev_tls
+                || self.tls_client_ca.is_some())
+        {
+            return Err(eyre!(
+                "unix_socket can't be used with tls_cert, tls_key, dev_tls or tls_client_ca"
+            ));
+        }
+        self.unix_socket_mode()?;
+        // The name in the login request is controlled by whoever sends it,
+        // including a malicious server relaying a user's signature.
+        if self.server_names.is_empty() && !self.dev_tls && !self.no_tls_very_insecure {
+            return Err(eyre!(
+                "server_names is required (unless dev_tls or no_tls_very_insecure is set), \
+                otherwise users logging in to a malicious server could be impersonated \
+                on this one"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the permissions of the Unix domain socket file.
+    pub fn unix_socket_mode(&self) -> Result<u32, eyre::Report> {
+        u32::from_str_radix(&self.unix_socket_mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| {
+                eyre!(
+                    "invalid unix_socket_mode {}: it must be octal permissions, e.g. 660",
+                    self.unix_socket_mode
+                )
+            })
+    }
+
+    /// Get the effective IP to use, considering the configuration.
+    pub fn ip(&self) -> String {
+        if self.no_tls_very_insecure {
+            "127.0.0.1".to_string()
+        } else {
+            self.ip.clone()
+        }
+    }
+}
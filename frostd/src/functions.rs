@@ -1,44 +1,132 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use axum::{
     extract::State,
+    http::{header, HeaderMap, HeaderValue, Uri},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
+use rand::thread_rng;
 use reqwest::StatusCode;
+use tokio::time::Instant;
 use uuid::Uuid;
 use xeddsa::{xed25519, Verify as _};
 
 use crate::{
-    state::{Session, SessionParticipant, SharedState, SESSION_TIMEOUT},
-    user::User,
+    limits::ClientIp,
+    state::{Session, SessionParticipant, SharedState, ABORT_GRACE_PERIOD},
+    tls::TlsInfo,
+    user::{pubkey_hash, User},
 };
 use frost_client::api::*;
 
 /// An Error which implements IntoResponse.
 /// Required since Error is defined in another crate.
+///
+/// It's returned with the status code given by [`Error::status_code()`], and
+/// optional details to help clients handle it.
 #[derive(Debug)]
-pub(crate) struct IntoResponseError(Error);
+pub(crate) struct IntoResponseError {
+    error: Error,
+    details: ErrorDetails,
+}
+
+impl IntoResponseError {
+    /// Set the name of the argument that caused the error.
+    pub(crate) fn field(mut self, field: &str) -> Self {
+        self.details.field = Some(field.to_string());
+        self
+    }
+
+    /// Set how long the client should wait before retrying the call.
+    pub(crate) fn retry_after(mut self, retry_after: Duration) -> Self {
+        // Round up so that clients don't retry too early.
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        self.details.retry_after_secs = Some(secs);
+        self
+    }
+}
 
 impl IntoResponse for IntoResponseError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(Into::<LowError>::into(self.0)),
-        )
-            .into_response()
+        let status = StatusCode::from_u16(self.error.status_code())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut headers = HeaderMap::new();
+        if let Some(secs) = self.details.retry_after_secs {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        if self.error == Error::Unauthorized {
+            headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        let err = LowError {
+            details: self.details,
+            ..self.error.into()
+        };
+        (status, headers, Json(err)).into_response()
     }
 }
 
 impl From<Error> for IntoResponseError {
-    fn from(err: Error) -> Self {
-        IntoResponseError(err)
+    fn from(error: Error) -> Self {
+        IntoResponseError {
+            error,
+            details: Default::default(),
+        }
     }
 }
 
+/// Implement the healthz API, used by supervisors to check if the server
+/// is alive.
+pub(crate) async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Implement the readyz API, used by supervisors to check if the server can
+/// receive traffic. It fails when the server is shutting down.
+pub(crate) async fn readyz(State(state): State<SharedState>) -> (StatusCode, &'static str) {
+    if state.is_shutting_down() {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+    } else {
+        (StatusCode::OK, "ok")
+    }
+}
+
+/// Implement the info API.
+#[tracing::instrument(level = "debug", ret, err(Debug), skip_all)]
+pub(crate) async fn info(
+    State(state): State<SharedState>,
+) -> Result<Json<InfoOutput>, IntoResponseError> {
+    Ok(Json(InfoOutput {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_versions: API_VERSIONS.iter().map(|v| v.to_string()).collect(),
+        features: features::ALL.iter().map(|f| f.to_string()).collect(),
+        max_msg_size: state.limits.max_msg_size,
+        max_wait_timeout_ms: MAX_WAIT_TIMEOUT_MS,
+        session_timeout_secs: state.timeouts.session.as_secs(),
+        challenge_timeout_secs: state.timeouts.challenge.as_secs(),
+        access_token_timeout_secs: state.timeouts.access_token.as_secs(),
+        max_sessions_per_coordinator: state.limits.sessions_per_coordinator,
+        max_queued_messages_per_recipient: state.limits.queued_messages_per_recipient,
+        max_queued_bytes_per_recipient: state.limits.queued_bytes_per_recipient,
+    }))
+}
+
 /// Implement the challenge API.
 #[tracing::instrument(level = "debug", err(Debug), skip(state))]
 pub(crate) async fn challenge(
     State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
 ) -> Result<Json<ChallengeOutput>, IntoResponseError> {
+    if let Some(ip) = ip {
+        state
+            .rate_limiters
+            .challenges_by_ip
+            .check(&ip, "challenges")?;
+    }
+
     // Create new challenge.
     let challenge = Uuid::new_v4();
 
@@ -49,24 +137,101 @@ pub(crate) async fn challenge(
 }
 
 /// Implement the key_login API.
-#[tracing::instrument(level = "debug", err(Debug), skip(state, args))]
+#[tracing::instrument(
+    level = "debug",
+    err(Debug),
+    skip(state, headers, uri, tls, args),
+    fields(user = %pubkey_hash(&args.pubkey))
+)]
 pub(crate) async fn login(
     State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    uri: Uri,
+    tls: Option<Extension<TlsInfo>>,
     Json(args): Json<LoginArgs>,
 ) -> Result<Json<LoginOutput>, IntoResponseError> {
+    let server_names: Vec<String> = if state.server_names.is_empty() {
+        request_server_identity(&headers, &uri)
+            .into_iter()
+            .collect()
+    } else {
+        state.server_names.clone()
+    };
+    let tls = tls.map(|Extension(tls)| tls).unwrap_or_default();
+    if let Err(e) = check_login(&state, ip, &server_names, &tls, &args) {
+        state.metrics.logins_failed.inc();
+        return Err(e);
+    }
+    state.metrics.logins_succeeded.inc();
+
+    Ok(Json(state.issue_access_token(&args.pubkey)))
+}
+
+/// Return the identity of the server as addressed by a request, from its
+/// `Host` header or (for HTTP/2) its URI.
+fn request_server_identity(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| uri.authority().map(|authority| authority.to_string()))
+        .map(|host| server_identity(&host))
+}
+
+/// Check if the user can log in with the given arguments, consuming the
+/// challenge. The signature must be of the login payload for one of the
+/// given server identities, and the client certificate must name the user if
+/// the server requires it.
+fn check_login(
+    state: &SharedState,
+    ip: Option<IpAddr>,
+    server_names: &[String],
+    tls: &TlsInfo,
+    args: &LoginArgs,
+) -> Result<(), IntoResponseError> {
+    if let Some(ip) = ip {
+        state.rate_limiters.logins_by_ip.check(&ip, "logins")?;
+    }
     // Check if the user sent the credentials
     if args.signature.is_empty() || args.pubkey.0.is_empty() {
-        return Err(Error::InvalidArgument("signature or pubkey".into()).into());
+        let field = if args.signature.is_empty() {
+            "signature"
+        } else {
+            "pubkey"
+        };
+        return Err(
+            IntoResponseError::from(Error::InvalidArgument("signature or pubkey".into()))
+                .field(field),
+        );
     }
 
-    let pubkey = TryInto::<[u8; 32]>::try_into(args.pubkey.0.clone())
-        .map_err(|_| Error::InvalidArgument("pubkey".into()))?;
+    let pubkey = TryInto::<[u8; 32]>::try_into(args.pubkey.0.clone()).map_err(|_| {
+        IntoResponseError::from(Error::InvalidArgument("pubkey".into())).field("pubkey")
+    })?;
     let pubkey = xed25519::PublicKey(pubkey);
-    let signature = TryInto::<[u8; 64]>::try_into(args.signature)
-        .map_err(|_| Error::InvalidArgument("signature".into()))?;
-    pubkey
-        .verify(args.challenge.as_bytes(), &signature)
-        .map_err(|_| Error::Unauthorized)?;
+    let signature = TryInto::<[u8; 64]>::try_into(args.signature.clone()).map_err(|_| {
+        IntoResponseError::from(Error::InvalidArgument("signature".into())).field("signature")
+    })?;
+    let verified = server_names.iter().any(|server_name| {
+        pubkey
+            .verify(&login_payload(server_name, &args.challenge), &signature)
+            .is_ok()
+    }) || (state.allow_legacy_login
+        && pubkey.verify(args.challenge.as_bytes(), &signature).is_ok());
+    if !verified {
+        return Err(Error::Unauthorized.into());
+    }
+    if state.bind_client_cert && !tls.is_bound_to(&args.pubkey) {
+        tracing::info!("client certificate does not name the user");
+        return Err(Error::Unauthorized.into());
+    }
+    // Only checked after verifying the signature, otherwise anyone could
+    // prevent a user from logging in.
+    state
+        .rate_limiters
+        .logins_by_pubkey
+        .check(&args.pubkey, "logins")?;
 
     let mut challenges = state.challenges.write().unwrap();
     if !challenges.remove(&args.challenge) {
@@ -74,47 +239,116 @@ pub(crate) async fn login(
     }
     drop(challenges);
 
-    let access_token = Uuid::new_v4();
-
-    let mut access_tokens = state.access_tokens.write().unwrap();
-    access_tokens.insert(access_token, args.pubkey);
-
-    let token = LoginOutput { access_token };
-
-    Ok(Json(token))
+    if !state.users.is_allowed(&args.pubkey) {
+        return Err(Error::UnregisteredUser.into());
+    }
+    Ok(())
 }
 
 /// Implement the logout API.
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(user = %user.pubkey_hash())
+)]
 pub(crate) async fn logout(
     State(state): State<SharedState>,
     user: User,
 ) -> Result<Json<()>, IntoResponseError> {
-    state
-        .access_tokens
-        .write()
-        .unwrap()
-        .remove(&user.current_token);
+    state.revoke_access_token(&user.current_token, user.signed_token_expires_at);
     Ok(Json(()))
 }
 
+/// Implement the refresh API, which issues a new access token to the user
+/// and revokes the one used in the request.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(user = %user.pubkey_hash())
+)]
+pub(crate) async fn refresh(
+    State(state): State<SharedState>,
+    user: User,
+) -> Result<Json<LoginOutput>, IntoResponseError> {
+    // The user may have been removed from the allowlist since logging in.
+    if !state.users.is_allowed(&user.pubkey) {
+        return Err(Error::UnregisteredUser.into());
+    }
+    let output = state.issue_access_token(&user.pubkey);
+    state.revoke_access_token(&user.current_token, user.signed_token_expires_at);
+    Ok(Json(output))
+}
+
 /// Implement the create_new_session API.
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id, user = %user.pubkey_hash())
+)]
 pub(crate) async fn create_new_session(
     State(state): State<SharedState>,
     user: User,
     Json(args): Json<CreateNewSessionArgs>,
 ) -> Result<Json<CreateNewSessionOutput>, IntoResponseError> {
+    if state.is_shutting_down() {
+        return Err(Error::ShuttingDown.into());
+    }
     if args.message_count == 0 {
-        return Err(Error::InvalidArgument("message_count".into()).into());
+        return Err(
+            IntoResponseError::from(Error::InvalidArgument("message_count".into()))
+                .field("message_count"),
+        );
+    }
+    // Don't allow creating sessions with users that would not be able to
+    // log in to join them.
+    if args.pubkeys.iter().any(|p| !state.users.is_allowed(p)) {
+        return Err(Error::UnregisteredUser.into());
+    }
+    let metadata_lens = [
+        args.metadata.group.as_ref().map(Vec::len),
+        args.metadata.ciphersuite.as_ref().map(String::len),
+        args.metadata.description.as_ref().map(String::len),
+    ];
+    if metadata_lens
+        .into_iter()
+        .flatten()
+        .any(|len| len > MAX_METADATA_LEN)
+    {
+        return Err(
+            IntoResponseError::from(Error::TooLarge("metadata is too long".into()))
+                .field("metadata"),
+        );
     }
 
     // Create new session object.
     let id = Uuid::new_v4();
+    tracing::Span::current().record("session_id", tracing::field::display(id));
 
     let mut sessions = state.sessions.sessions.write().unwrap();
     let mut sessions_by_pubkey = state.sessions.sessions_by_pubkey.write().unwrap();
 
+    let max_sessions = state.limits.sessions_per_coordinator;
+    if max_sessions > 0 {
+        let coordinated_sessions = sessions_by_pubkey
+            .get(&user.pubkey)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| sessions.get(id))
+                    .filter(|s| s.coordinator_pubkey == user.pubkey)
+                    .count()
+            })
+            .unwrap_or_default();
+        if coordinated_sessions >= max_sessions {
+            return Err(Error::LimitExceeded("too many open sessions".into()).into());
+        }
+    }
+
     // Save session ID in global state
     for pubkey in &args.pubkeys {
         sessions_by_pubkey
@@ -128,21 +362,41 @@ pub(crate) async fn create_new_session(
         .or_default()
         .insert(id);
     // Create Session object
-    let session = Session {
+    let now = SystemTime::now();
+    let mut session = Session {
         pubkeys: args.pubkeys.clone(),
-        coordinator_pubkey: user.pubkey,
+        coordinator_pubkey: user.pubkey.clone(),
         message_count: args.message_count,
         queue: Default::default(),
+        last_seq: Default::default(),
+        msg_ids: Default::default(),
+        notify: Default::default(),
+        activity: Default::default(),
+        created_at: now,
+        aborted: false,
+        expires_at: now + state.timeouts.session,
+        last_activity: now,
+        broadcasts: Default::default(),
+        metadata: args.metadata,
     };
+    session.seen(&user.pubkey);
     // Save session into global state.
+    state.persist_session(&id, &session);
     sessions.insert(id, session);
+    state.metrics.sessions_created.inc();
 
     let user = CreateNewSessionOutput { session_id: id };
     Ok(Json(user))
 }
 
 /// Implement the create_new_session API.
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(user = %user.pubkey_hash())
+)]
 pub(crate) async fn list_sessions(
     State(state): State<SharedState>,
     user: User,
@@ -158,13 +412,19 @@ pub(crate) async fn list_sessions(
 }
 
 /// Implement the get_session_info API
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
 pub(crate) async fn get_session_info(
     State(state): State<SharedState>,
     user: User,
     Json(args): Json<GetSessionInfoArgs>,
 ) -> Result<Json<GetSessionInfoOutput>, IntoResponseError> {
-    let sessions = state.sessions.sessions.read().unwrap();
+    let mut sessions = state.sessions.sessions.write().unwrap();
     let sessions_by_pubkey = state.sessions.sessions_by_pubkey.read().unwrap();
 
     let user_sessions = sessions_by_pubkey
@@ -176,25 +436,57 @@ pub(crate) async fn get_session_info(
     }
 
     let session = sessions
-        .get(&args.session_id)
+        .get_mut(&args.session_id)
         .ok_or(Error::SessionNotFound)?;
+    session.seen(&user.pubkey);
+
+    let participants = session
+        .pubkeys
+        .iter()
+        .map(|pubkey| {
+            let activity = session.activity.get(pubkey).cloned().unwrap_or_default();
+            ParticipantStatus {
+                pubkey: pubkey.clone(),
+                last_seen: activity.last_seen.map(unix_timestamp),
+                messages_sent: activity.messages_sent,
+                messages_pending: session
+                    .queue
+                    .get(&SessionParticipant::Participant(pubkey.clone()))
+                    .map(|q| q.len())
+                    .unwrap_or_default(),
+                invitation: activity.invitation,
+                decline_reason: activity.decline_reason,
+            }
+        })
+        .collect();
 
     Ok(Json(GetSessionInfoOutput {
         message_count: session.message_count,
         pubkeys: session.pubkeys.clone(),
         coordinator_pubkey: session.coordinator_pubkey.clone(),
+        created_at: unix_timestamp(session.created_at),
+        last_activity: unix_timestamp(session.last_activity),
+        expires_at: unix_timestamp(session.expires_at),
+        participants,
+        metadata: session.metadata.clone(),
     }))
 }
 
 /// Implement the send API
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
 pub(crate) async fn send(
     State(state): State<SharedState>,
     user: User,
     Json(args): Json<SendArgs>,
 ) -> Result<(), IntoResponseError> {
-    if args.msg.len() > MAX_MSG_SIZE {
-        return Err(Error::InvalidArgument("msg is too big".into()).into());
+    if args.msg.len() > state.limits.max_msg_size {
+        return Err(IntoResponseError::from(Error::TooLarge("msg is too big".into())).field("msg"));
     }
 
     // Get the mutex lock to read and write from the state
@@ -213,77 +505,551 @@ pub(crate) async fn send(
             .collect()
     };
 
+    if session.aborted {
+        return Err(Error::SessionAborted.into());
+    }
+
     // Check if both the sender and the recipients are in the session
-    if (!session.pubkeys.contains(&user.pubkey) && session.coordinator_pubkey != user.pubkey)
+    if !session.is_member(&user.pubkey)
         || recipients.iter().any(|p| match p {
             SessionParticipant::Coordinator => false,
-            SessionParticipant::Participant(public_key) => !session.pubkeys.contains(public_key),
+            SessionParticipant::Participant(public_key) => !session.is_member(public_key),
         })
     {
         return Err(Error::NotInSession.into());
     }
 
+    // If the message was already sent (i.e. this is a retry of a call whose
+    // response was lost), don't send it again.
+    if let Some(msg_id) = args.msg_id {
+        if session.has_msg_id(&user.pubkey, &msg_id) {
+            return Ok(());
+        }
+    }
+
+    // Check the queue limits for all recipients before enqueuing, so that
+    // the message is either delivered to all of them or to none.
+    let max_msgs = state.limits.queued_messages_per_recipient;
+    let max_bytes = state.limits.queued_bytes_per_recipient;
     for recipient in &recipients {
-        session
-            .queue
-            .entry(recipient.clone())
-            .or_default()
-            .push_back(Msg {
+        let Some(queue) = session.queue.get(recipient) else {
+            continue;
+        };
+        if max_msgs > 0 && queue.len() >= max_msgs {
+            return Err(Error::LimitExceeded("too many queued messages".into()).into());
+        }
+        let queued_bytes: usize = queue.iter().map(|m| m.msg.len()).sum();
+        if max_bytes > 0 && queued_bytes + args.msg.len() > max_bytes {
+            return Err(Error::LimitExceeded("too many queued bytes".into()).into());
+        }
+    }
+
+    for recipient in &recipients {
+        session.enqueue(
+            recipient.clone(),
+            Msg {
                 sender: user.pubkey.clone(),
                 msg: args.msg.clone(),
-            });
+                seq: 0,
+                event: None,
+            },
+        );
+    }
+    if let Some(msg_id) = args.msg_id {
+        session.add_msg_id(&user.pubkey, msg_id);
     }
-    sessions.update_timeout(&args.session_id, SESSION_TIMEOUT);
+    session.seen(&user.pubkey);
+    let is_participant = session.pubkeys.contains(&user.pubkey);
+    let activity = session.activity.entry(user.pubkey.clone()).or_default();
+    activity.messages_sent += 1;
+    // Sending a message implicitly accepts the invitation.
+    if is_participant && activity.invitation == InvitationStatus::Pending {
+        activity.invitation = InvitationStatus::Accepted;
+    }
+    state.metrics.messages_sent.add(recipients.len() as u64);
+    // Wake up any long polling `receive` calls.
+    session.notify.notify_waiters();
+    session.touch(state.timeouts.session);
+    // Only persist the queues that changed.
+    state.persist_session_info(&args.session_id, session);
+    for recipient in &recipients {
+        state.persist_queue(&args.session_id, session, recipient);
+    }
+    sessions.update_timeout(&args.session_id, state.timeouts.session);
 
     Ok(())
 }
 
-/// Implement the recv API
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+/// Implement the recv API.
+///
+/// If the client passed a sequence number, the messages up to it are
+/// acknowledged and the remaining ones are returned but kept in the queue
+/// until acknowledged. Otherwise, all messages are returned and removed.
+///
+/// If the client requested a wait timeout and there are no messages to
+/// return, this waits until a message is sent to the user (or the session is
+/// closed) or the timeout expires.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
 pub(crate) async fn receive(
     State(state): State<SharedState>,
     user: User,
     Json(args): Json<ReceiveArgs>,
 ) -> Result<Json<ReceiveOutput>, IntoResponseError> {
-    // Get the mutex lock to read and write from the state
+    let wait_timeout = Duration::from_millis(
+        args.wait_timeout_ms
+            .unwrap_or_default()
+            .min(MAX_WAIT_TIMEOUT_MS),
+    );
+    let deadline = Instant::now() + wait_timeout;
+
+    loop {
+        let notify;
+        let notified;
+        {
+            // Get the mutex lock to read and write from the state
+            let mut sessions = state.sessions.sessions.write().unwrap();
+
+            let session = sessions
+                .get_mut(&args.session_id)
+                .ok_or(Error::SessionNotFound)?;
+
+            let participant = recipient(session, &user, args.as_coordinator)?;
+            session.seen(&user.pubkey);
+
+            let (msgs, acked): (Vec<_>, _) = match args.after_seq {
+                Some(seq) => {
+                    let acked = session.ack(&participant, seq);
+                    let msgs = session
+                        .queue
+                        .get(&participant)
+                        .map(|queue| queue.iter().cloned().collect())
+                        .unwrap_or_default();
+                    (msgs, acked)
+                }
+                None => {
+                    let msgs: Vec<_> = session
+                        .queue
+                        .get_mut(&participant)
+                        .map(|queue| queue.drain(..).collect())
+                        .unwrap_or_default();
+                    let received = msgs.len();
+                    (msgs, received)
+                }
+            };
+            if acked > 0 {
+                state.metrics.messages_received.add(acked as u64);
+            }
+            // If there are no new messages, we don't want to renew the timeout.
+            // Aborted sessions are never renewed.
+            let renew = !msgs.is_empty() && !session.aborted;
+            if renew {
+                session.touch(state.timeouts.session);
+            }
+            if acked > 0 {
+                state.persist_queue(&args.session_id, session, &participant);
+            }
+            if renew {
+                state.persist_session_info(&args.session_id, session);
+            }
+            if renew {
+                sessions.update_timeout(&args.session_id, state.timeouts.session);
+            }
+            if !msgs.is_empty() {
+                return Ok(Json(ReceiveOutput { msgs }));
+            }
+            // Don't hold up the shutdown; the client will call again (probably
+            // on another server instance).
+            if Instant::now() >= deadline || state.is_shutting_down() {
+                return Ok(Json(ReceiveOutput { msgs }));
+            }
+
+            // Create the future while still holding the lock, so that we
+            // don't miss a notification sent right after we release it.
+            notify = session.notify.clone();
+            notified = notify.notified();
+        }
+        // Timing out is fine; the loop will return the (empty) queue.
+        let _ = tokio::time::timeout_at(deadline, notified).await;
+    }
+}
+
+/// Implement the ack API, which removes the messages up to the given
+/// sequence number from the queue of the user.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn ack(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<AckArgs>,
+) -> Result<Json<()>, IntoResponseError> {
     let mut sessions = state.sessions.sessions.write().unwrap();
 
     let session = sessions
         .get_mut(&args.session_id)
         .ok_or(Error::SessionNotFound)?;
 
-    // Check if both the sender and the recipients are in the session
-    if !session.pubkeys.contains(&user.pubkey) && session.coordinator_pubkey != user.pubkey {
+    let participant = recipient(session, &user, args.as_coordinator)?;
+    session.seen(&user.pubkey);
+    let acked = session.ack(&participant, args.seq);
+    if acked > 0 {
+        state.metrics.messages_received.add(acked as u64);
+        state.persist_queue(&args.session_id, session, &participant);
+    }
+    Ok(Json(()))
+}
+
+/// Return the queue of the session the user receives messages from, checking
+/// that they are in the session.
+fn recipient(
+    session: &Session,
+    user: &User,
+    as_coordinator: bool,
+) -> Result<SessionParticipant, Error> {
+    if !session.is_member(&user.pubkey) {
+        return Err(Error::NotInSession);
+    }
+    Ok(
+        if user.pubkey == session.coordinator_pubkey && as_coordinator {
+            SessionParticipant::Coordinator
+        } else {
+            SessionParticipant::Participant(user.pubkey.clone())
+        },
+    )
+}
+
+/// Implement the broadcast API, which stores a message that all members of
+/// the session can get with `get_broadcasts`. Each member can broadcast a
+/// single message; broadcasting it again has no effect.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn broadcast(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<BroadcastArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    if args.msg.len() > state.limits.max_msg_size {
+        return Err(IntoResponseError::from(Error::TooLarge("msg is too big".into())).field("msg"));
+    }
+
+    let mut sessions = state.sessions.sessions.write().unwrap();
+
+    let session = sessions
+        .get_mut(&args.session_id)
+        .ok_or(Error::SessionNotFound)?;
+
+    if session.aborted {
+        return Err(Error::SessionAborted.into());
+    }
+    if !session.is_member(&user.pubkey) {
         return Err(Error::NotInSession.into());
     }
+    session.seen(&user.pubkey);
+    if let Some(msg) = session.broadcasts.get(&user.pubkey) {
+        if *msg == args.msg {
+            return Ok(Json(()));
+        }
+        return Err(IntoResponseError::from(Error::InvalidArgument(
+            "a different message was already broadcast".into(),
+        ))
+        .field("msg"));
+    }
+    session.broadcasts.insert(user.pubkey.clone(), args.msg);
+    let is_participant = session.pubkeys.contains(&user.pubkey);
+    let activity = session.activity.entry(user.pubkey.clone()).or_default();
+    activity.messages_sent += 1;
+    // Broadcasting implicitly accepts the invitation, like sending.
+    if is_participant && activity.invitation == InvitationStatus::Pending {
+        activity.invitation = InvitationStatus::Accepted;
+    }
+    state.metrics.messages_sent.inc();
+    // Wake up any long polling `get_broadcasts` calls.
+    session.notify.notify_waiters();
+    session.touch(state.timeouts.session);
+    state.persist_session_info(&args.session_id, session);
+    sessions.update_timeout(&args.session_id, state.timeouts.session);
 
-    let participant = if user.pubkey == session.coordinator_pubkey && args.as_coordinator {
-        SessionParticipant::Coordinator
-    } else {
-        SessionParticipant::Participant(user.pubkey)
-    };
+    Ok(Json(()))
+}
 
-    let msgs = if session.queue.contains_key(&participant) {
-        let msgs = session
-            .queue
-            .entry(participant)
-            .or_default()
-            .drain(..)
-            .collect();
-        msgs
-    } else {
-        vec![]
-    };
-    // If there are no new messages, we don't want to renew the timeout.
-    if !msgs.is_empty() {
-        sessions.update_timeout(&args.session_id, SESSION_TIMEOUT);
+/// Implement the get_broadcasts API, which returns the messages broadcast in
+/// the session and, once every participant has broadcast, an attestation of
+/// them signed by the server.
+///
+/// If the client requested a wait timeout and not every participant has
+/// broadcast yet, this waits until they do or the timeout expires.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn get_broadcasts(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<GetBroadcastsArgs>,
+) -> Result<Json<GetBroadcastsOutput>, IntoResponseError> {
+    let wait_timeout = Duration::from_millis(
+        args.wait_timeout_ms
+            .unwrap_or_default()
+            .min(MAX_WAIT_TIMEOUT_MS),
+    );
+    let deadline = Instant::now() + wait_timeout;
+
+    loop {
+        let notify;
+        let notified;
+        {
+            let mut sessions = state.sessions.sessions.write().unwrap();
+
+            let session = sessions
+                .get_mut(&args.session_id)
+                .ok_or(Error::SessionNotFound)?;
+
+            if session.aborted {
+                return Err(Error::SessionAborted.into());
+            }
+            if !session.is_member(&user.pubkey) {
+                return Err(Error::NotInSession.into());
+            }
+            session.seen(&user.pubkey);
+
+            let (msgs, complete) = session.broadcasts();
+            if complete {
+                let hashes: Vec<_> = msgs
+                    .iter()
+                    .map(|m| BroadcastHash::new(m.sender.clone(), &m.msg))
+                    .collect();
+                let digest = BroadcastAttestation::digest(&args.session_id, &hashes);
+                let signature = state
+                    .attestation_key
+                    .sign(&digest, thread_rng())
+                    .map_err(|_| Error::Unknown)?;
+                let attestation = BroadcastAttestation {
+                    session_id: args.session_id,
+                    hashes,
+                    server_pubkey: state.attestation_pubkey.clone(),
+                    signature: signature.to_vec(),
+                };
+                return Ok(Json(GetBroadcastsOutput {
+                    msgs,
+                    attestation: Some(attestation),
+                }));
+            }
+            if Instant::now() >= deadline || state.is_shutting_down() {
+                return Ok(Json(GetBroadcastsOutput {
+                    msgs,
+                    attestation: None,
+                }));
+            }
+
+            // Create the future while still holding the lock, so that we
+            // don't miss a notification sent right after we release it.
+            notify = session.notify.clone();
+            notified = notify.notified();
+        }
+        let _ = tokio::time::timeout_at(deadline, notified).await;
     }
+}
 
-    Ok(Json(ReceiveOutput { msgs }))
+/// Implement the accept_session API, which lets a participant confirm that
+/// they will take part in a session.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn accept_session(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<AcceptSessionArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    let mut sessions = state.sessions.sessions.write().unwrap();
+
+    let session = sessions
+        .get_mut(&args.session_id)
+        .ok_or(Error::SessionNotFound)?;
+
+    if !session.pubkeys.contains(&user.pubkey) {
+        return Err(Error::NotInSession.into());
+    }
+    session.seen(&user.pubkey);
+    let activity = session.activity.entry(user.pubkey.clone()).or_default();
+    match activity.invitation {
+        InvitationStatus::Accepted => return Ok(Json(())),
+        InvitationStatus::Declined => {
+            return Err(Error::InvalidArgument("session was already declined".into()).into())
+        }
+        InvitationStatus::Pending => activity.invitation = InvitationStatus::Accepted,
+    }
+    state.persist_session_info(&args.session_id, session);
+    Ok(Json(()))
+}
+
+/// Implement the decline_session API, which lets a participant refuse to
+/// take part in a session. The other members of the session are notified
+/// with a [`SessionEvent::Declined`] event.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn decline_session(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<DeclineSessionArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    if args
+        .reason
+        .as_ref()
+        .is_some_and(|r| r.len() > MAX_REASON_LEN)
+    {
+        return Err(
+            IntoResponseError::from(Error::TooLarge("reason is too long".into())).field("reason"),
+        );
+    }
+
+    let mut sessions = state.sessions.sessions.write().unwrap();
+    let mut sessions_by_pubkey = state.sessions.sessions_by_pubkey.write().unwrap();
+
+    let session = sessions
+        .get_mut(&args.session_id)
+        .ok_or(Error::SessionNotFound)?;
+
+    if !session.pubkeys.contains(&user.pubkey) {
+        return Err(Error::NotInSession.into());
+    }
+    session.seen(&user.pubkey);
+    let activity = session.activity.entry(user.pubkey.clone()).or_default();
+    // Declining twice is fine, e.g. if the first response was lost.
+    if activity.invitation == InvitationStatus::Declined {
+        return Ok(Json(()));
+    }
+    activity.invitation = InvitationStatus::Declined;
+    activity.decline_reason = args.reason.clone();
+
+    // Discard the messages queued for the user and stop listing the session
+    // for them (unless they are also the coordinator).
+    let participant = SessionParticipant::Participant(user.pubkey.clone());
+    session.queue.remove(&participant);
+    state.remove_persisted_queue(&args.session_id, &participant);
+    if session.coordinator_pubkey != user.pubkey {
+        if let Some(ids) = sessions_by_pubkey.get_mut(&user.pubkey) {
+            ids.remove(&args.session_id);
+        }
+    }
+
+    session.broadcast_event(
+        &user.pubkey,
+        SessionEvent::Declined {
+            pubkey: user.pubkey.clone(),
+            reason: args.reason,
+        },
+    );
+    state.persist_session(&args.session_id, session);
+    Ok(Json(()))
+}
+
+/// Implement the abort_session API, which lets any member of a session
+/// abort it. The other members are notified with a [`SessionEvent::Aborted`]
+/// event, and the session is closed after a grace period that allows them to
+/// receive it.
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
+pub(crate) async fn abort_session(
+    State(state): State<SharedState>,
+    user: User,
+    Json(args): Json<AbortSessionArgs>,
+) -> Result<Json<()>, IntoResponseError> {
+    if args
+        .message
+        .as_ref()
+        .is_some_and(|m| m.len() > MAX_REASON_LEN)
+    {
+        return Err(
+            IntoResponseError::from(Error::TooLarge("message is too long".into())).field("message"),
+        );
+    }
+
+    let mut sessions = state.sessions.sessions.write().unwrap();
+    let mut sessions_by_pubkey = state.sessions.sessions_by_pubkey.write().unwrap();
+
+    let session = sessions
+        .get_mut(&args.session_id)
+        .ok_or(Error::SessionNotFound)?;
+
+    if !session.is_member(&user.pubkey) {
+        return Err(Error::NotInSession.into());
+    }
+    // Aborting twice is fine, e.g. if the first response was lost or if
+    // multiple members hit an error.
+    if session.aborted {
+        return Ok(Json(()));
+    }
+    session.aborted = true;
+    session.seen(&user.pubkey);
+
+    let by_coordinator = user.pubkey == session.coordinator_pubkey;
+    session.broadcast_event(
+        &user.pubkey,
+        SessionEvent::Aborted {
+            pubkey: user.pubkey.clone(),
+            by_coordinator,
+            reason: args.reason,
+            message: args.message,
+        },
+    );
+
+    // Stop listing the session, and close it after the grace period.
+    for pubkey in session
+        .pubkeys
+        .iter()
+        .chain(std::iter::once(&session.coordinator_pubkey))
+    {
+        if let Some(ids) = sessions_by_pubkey.get_mut(pubkey) {
+            ids.remove(&args.session_id);
+        }
+    }
+    session.expires_at = SystemTime::now() + ABORT_GRACE_PERIOD;
+    state.persist_session(&args.session_id, session);
+    sessions.update_timeout(&args.session_id, ABORT_GRACE_PERIOD);
+    state.metrics.sessions_aborted.inc();
+    Ok(Json(()))
 }
 
 /// Implement the close_session API.
-#[tracing::instrument(level = "debug", ret, err(Debug), skip(state, user))]
+#[tracing::instrument(
+    level = "debug",
+    ret,
+    err(Debug),
+    skip_all,
+    fields(session_id = %args.session_id, user = %user.pubkey_hash())
+)]
 pub(crate) async fn close_session(
     State(state): State<SharedState>,
     user: User,
@@ -308,17 +1074,14 @@ pub(crate) async fn close_session(
         return Err(Error::NotCoordinator.into());
     }
 
-    // Remove session from each participant list...
-    for pubkey in session.pubkeys.clone() {
-        if let Some(v) = sessions_by_pubkey.get_mut(&pubkey) {
-            v.remove(&args.session_id);
-        }
-    }
-    // And also remove from the coordinator's list
-    // (might have been already removed if they are also a participant)
-    if let Some(v) = sessions_by_pubkey.get_mut(&user.pubkey) {
-        v.remove(&args.session_id);
-    }
-    sessions.remove(&args.session_id);
+    state.remove_session(&mut sessions, &mut sessions_by_pubkey, &args.session_id);
+    state.metrics.sessions_closed.inc();
     Ok(Json(()))
 }
+
+/// Convert a time to a UNIX timestamp in seconds.
+pub(crate) fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
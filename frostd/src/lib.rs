@@ -1,47 +1,196 @@
+mod admin;
 pub mod args;
+pub mod cli;
+pub mod config;
 mod functions;
+pub mod limits;
+pub mod logging;
+mod metrics;
+pub mod openapi;
+pub mod registry;
 mod state;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tls;
+pub mod token;
 mod user;
 
-use std::net::SocketAddr;
+use std::{
+    future::{Future, IntoFuture as _},
+    io,
+    net::SocketAddr,
+    time::Duration,
+};
 
-use axum::{routing::post, Router};
-use axum_server::tls_rustls::RustlsConfig;
-use eyre::OptionExt;
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request},
+    middleware,
+    response::Response,
+    routing::get,
+    Router,
+};
 use thiserror::Error;
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
-use args::Args;
+use config::Config;
 pub use frost_client::api::*;
-pub use state::{AppState, SharedState};
+use registry::UserRegistry;
+pub use state::{AppState, AppStateOptions, Session, SessionParticipant, SharedState};
+use storage::FileStorage;
+use token::TokenKey;
 
 /// Create the axum Router for the server.
 /// Maps specific endpoints to handler functions.
-// TODO: use methods of a single object instead of separate functions?
+///
+/// The API is served under the prefix of each version in [`API_VERSIONS`]
+/// (e.g. `/v1/send`). It is also served without a prefix for compatibility
+/// with older clients; those responses include a `Deprecation` header. The
+/// OpenAPI description of the API is served at `/openapi.json`.
+///
+/// Each request is identified by its `x-request-id` header, which is
+/// generated if missing and included in the response and in the logs.
 pub fn router(shared_state: SharedState) -> Router {
+    let api = api_router();
     // Shared state that is passed to each handler by axum
     Router::new()
-        .route("/challenge", post(functions::challenge))
-        .route("/login", post(functions::login))
-        .route("/logout", post(functions::logout))
-        .route("/create_new_session", post(functions::create_new_session))
-        .route("/list_sessions", post(functions::list_sessions))
-        .route("/get_session_info", post(functions::get_session_info))
-        .route("/send", post(functions::send))
-        .route("/receive", post(functions::receive))
-        .route("/close_session", post(functions::close_session))
-        .layer(TraceLayer::new_for_http())
+        .route("/healthz", get(functions::healthz))
+        .route("/readyz", get(functions::readyz))
+        .route("/openapi.json", get(openapi::openapi))
+        .nest("/v1", api.clone())
+        .merge(api.layer(middleware::map_response(deprecated)))
+        .layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            limits::filter_ip,
+        ))
+        .layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            metrics::track_requests,
+        ))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .with_state(shared_state)
 }
 
-/// Run the server with the specified arguments.
-pub async fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let shared_state = AppState::new().await?;
-    let app = router(shared_state.clone());
+/// Create the span of a request, which is the parent of the spans of the
+/// handlers.
+fn request_span(request: &Request<Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
+}
+
+/// Create the Router with the API endpoints, without a version prefix.
+fn api_router() -> Router<SharedState> {
+    openapi::ENDPOINTS
+        .iter()
+        .fold(Router::new(), |router, endpoint| {
+            router.route(endpoint.path, (endpoint.handler)())
+        })
+}
+
+/// Mark a response of the unversioned API as deprecated.
+async fn deprecated(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    response
+}
+
+/// Create the axum Router for the metrics endpoint, which exports metrics in
+/// the Prometheus text format. It is served on a separate address so that it
+/// is not exposed to users.
+pub fn metrics_router(shared_state: SharedState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics::metrics))
+        .with_state(shared_state)
+}
+
+/// Run the server with the specified configuration.
+pub async fn run(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = AppStateOptions::default();
+    if let Some(storage_dir) = &config.storage_dir {
+        tracing::info!("persisting state in {}", storage_dir);
+        options.storage = Box::new(FileStorage::new(storage_dir)?);
+    }
+    let admins = config
+        .admin_pubkeys
+        .iter()
+        .map(|p| registry::parse_pubkey(p))
+        .collect::<Result<_, _>>()?;
+    options.users = if let Some(allowlist) = &config.allowlist {
+        tracing::info!("only users in the allowlist {} can log in", allowlist);
+        UserRegistry::from_file(admins, allowlist)?
+    } else {
+        UserRegistry::new(admins, None)
+    };
+    options.limits = config.limits.clone();
+    options.timeouts = config.timeouts.clone();
+    if config.server_names.is_empty() {
+        tracing::warn!(
+            "server_names is not set; users logging in to a malicious server \
+            could be impersonated on this one. Set it unless this server is \
+            only used for development"
+        );
+    }
+    options.server_names = config.server_names.clone();
+    options.allow_legacy_login = config.allow_legacy_login;
+    options.bind_client_cert = config.tls_client_cert_binding;
+    if let Some(token_key_file) = &config.token_key_file {
+        tracing::info!(
+            "issuing access tokens signed with the key in {}",
+            token_key_file
+        );
+        options.token_key = Some(TokenKey::from_file(token_key_file)?);
+    }
+    let shared_state = AppState::with_options(options).await?;
+    // The connect info is needed to enforce the per-IP limits.
+    let app = router(shared_state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+
+    // Start shutting down when a termination signal is received.
+    tokio::spawn(shutdown_on_signal(shared_state.clone()));
+    let shutdown_timeout = config.timeouts.shutdown;
+
+    if let Some(metrics_addr) = &config.metrics_addr {
+        let metrics_addr: SocketAddr = metrics_addr.parse()?;
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        let metrics_app = metrics_router(shared_state.clone());
+        tracing::info!("serving metrics at http://{}/metrics", metrics_addr);
+        let state = shared_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, metrics_app)
+                .with_graceful_shutdown(async move { state.wait_for_shutdown().await })
+                .await
+            {
+                tracing::error!("metrics server failed: {}", e);
+            }
+        });
+    }
 
-    let addr: SocketAddr = format!("{}:{}", args.ip(), args.port).parse()?;
+    let addr: SocketAddr = format!("{}:{}", config.ip(), config.port).parse()?;
 
-    if args.no_tls_very_insecure {
+    let result = if let Some(unix_socket) = &config.unix_socket {
+        serve_unix_socket(
+            unix_socket,
+            config.unix_socket_mode()?,
+            &shared_state,
+            shutdown_timeout,
+        )
+        .await
+    } else if config.no_tls_very_insecure {
         tracing::warn!(
             "starting an INSECURE HTTP server at {}. This should be done only \
             for testing or if you are providing TLS/HTTPS with a separate \
@@ -49,24 +198,155 @@ pub async fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
             addr,
         );
         let listener = tokio::net::TcpListener::bind(addr).await?;
-        Ok(axum::serve(listener, app).await?)
+        let state = shared_state.clone();
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { state.wait_for_shutdown().await });
+        with_shutdown_timeout(server.into_future(), &shared_state, shutdown_timeout).await
     } else {
-        rustls::crypto::ring::default_provider()
-            .install_default()
-            .expect("Failed to install rustls crypto provider");
-        let config = RustlsConfig::from_pem_file(
-            args.tls_cert
-                .clone()
-                .ok_or_eyre("tls-cert argument is required")?,
-            args.tls_key
-                .clone()
-                .ok_or_eyre("tls-key argument is required")?,
-        )
-        .await?;
+        // This fails if a provider was already installed, e.g. by another
+        // server in the same process, which is fine.
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let tls_config = tls::rustls_config(config)?;
+        if !config.dev_tls {
+            tokio::spawn(tls::reload_on_change(
+                config.clone(),
+                tls_config.clone(),
+                shared_state.clone(),
+            ));
+        }
 
+        let handle = axum_server::Handle::new();
+        let state = shared_state.clone();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            state.wait_for_shutdown().await;
+            shutdown_handle.graceful_shutdown(Some(shutdown_timeout));
+        });
+
+        if config.tls_client_ca.is_some() {
+            tracing::info!(
+                "clients authenticate with certificates ({:?})",
+                config.tls_client_auth
+            );
+        }
         tracing::info!("starting HTTPS server at {}", addr);
-        Ok(axum_server::bind_rustls(addr, config)
-            .serve(app.into_make_service())
-            .await?)
+        axum_server::bind(addr)
+            .acceptor(tls::TlsAcceptor::new(tls_config))
+            .handle(handle)
+            .serve(app)
+            .await
+            .map_err(Into::into)
+    };
+
+    shared_state.persist_all_sessions();
+    shared_state.flush_storage().await;
+    tracing::info!("server stopped");
+    result
+}
+
+/// Wait for the given plain HTTP server to stop. axum does not support a
+/// timeout for the graceful shutdown, so we implement it ourselves.
+async fn with_shutdown_timeout(
+    server: impl Future<Output = io::Result<()>>,
+    state: &SharedState,
+    shutdown_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tokio::select! {
+        r = server => r.map_err(Into::into),
+        _ = async {
+            state.wait_for_shutdown().await;
+            tokio::time::sleep(shutdown_timeout).await;
+        } => {
+            tracing::warn!("timed out waiting for requests to finish");
+            Ok(())
+        }
+    }
+}
+
+/// Serve the API over plain HTTP on a Unix domain socket at the given path,
+/// with the given file permissions, until the server shuts down. The socket
+/// file is removed when it stops.
+///
+/// Since there is no client IP, the per-IP limits and IP filters do not apply
+/// to these requests; they should be enforced by the reverse proxy, if needed.
+#[cfg(unix)]
+async fn serve_unix_socket(
+    path: &str,
+    mode: u32,
+    state: &SharedState,
+    shutdown_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt as _, PermissionsExt as _},
+    };
+
+    // Remove the socket of a previous run, which would make binding fail, but
+    // nothing else.
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(eyre::eyre!("{} exists and is not a socket", path).into());
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    tracing::info!(
+        "starting an HTTP server at unix socket {} (mode {:o})",
+        path,
+        mode
+    );
+    let shutdown_state = state.clone();
+    let server = axum::serve(listener, router(state.clone()))
+        .with_graceful_shutdown(async move { shutdown_state.wait_for_shutdown().await });
+    let result = with_shutdown_timeout(server.into_future(), state, shutdown_timeout).await;
+    if let Err(e) = fs::remove_file(path) {
+        tracing::warn!("error removing unix socket {}: {}", path, e);
+    }
+    result
+}
+
+#[cfg(not(unix))]
+async fn serve_unix_socket(
+    _path: &str,
+    _mode: u32,
+    _state: &SharedState,
+    _shutdown_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err(eyre::eyre!("unix sockets are not supported on this platform").into())
+}
+
+/// Wait for a termination signal (SIGTERM, or Ctrl-C) and start shutting down
+/// the server.
+async fn shutdown_on_signal(state: SharedState) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("error listening for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("error listening for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+        // Shutdown was started by other means.
+        _ = state.wait_for_shutdown() => return,
     }
+    tracing::info!("received termination signal, shutting down");
+    state.shutdown();
 }
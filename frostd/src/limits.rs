@@ -0,0 +1,252 @@
+//! Rate limits and other abuse controls.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use eyre::eyre;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{functions::IntoResponseError, state::SharedState, Error, PublicKey, MAX_MSG_SIZE};
+
+/// The window used for the rate limits.
+pub(crate) const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits enforced by the server. A value of zero means no limit, except for
+/// `max_msg_size`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum size of a single message. Can't be larger than
+    /// [`MAX_MSG_SIZE`].
+    pub max_msg_size: usize,
+    /// Maximum number of challenges that can be requested per minute from a
+    /// single IP.
+    pub challenges_per_ip: u32,
+    /// Maximum number of login attempts per minute from a single IP.
+    pub logins_per_ip: u32,
+    /// Maximum number of login attempts per minute for a single public key.
+    pub logins_per_pubkey: u32,
+    /// Maximum number of open sessions a single user can coordinate.
+    pub sessions_per_coordinator: usize,
+    /// Maximum number of messages queued for a single recipient in a session.
+    pub queued_messages_per_recipient: usize,
+    /// Maximum total size of the messages queued for a single recipient in a
+    /// session.
+    pub queued_bytes_per_recipient: usize,
+    /// If not empty, only requests from these IP ranges are allowed.
+    pub ip_allowlist: Vec<IpRange>,
+    /// Requests from these IP ranges are rejected.
+    pub ip_denylist: Vec<IpRange>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_msg_size: MAX_MSG_SIZE,
+            challenges_per_ip: 60,
+            logins_per_ip: 60,
+            logins_per_pubkey: 20,
+            sessions_per_coordinator: 100,
+            queued_messages_per_recipient: 1000,
+            queued_bytes_per_recipient: 16 * 1024 * 1024,
+            ip_allowlist: Vec::new(),
+            ip_denylist: Vec::new(),
+        }
+    }
+}
+
+impl Limits {
+    /// Return if requests from the given IP are allowed.
+    pub(crate) fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !self.ip_denylist.iter().any(|r| r.contains(ip))
+            && (self.ip_allowlist.is_empty() || self.ip_allowlist.iter().any(|r| r.contains(ip)))
+    }
+}
+
+/// A range of IP addresses in CIDR notation (e.g. `10.0.0.0/8`), or a single
+/// IP address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Return if the given IP is in the range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(addr) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(addr) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for IpRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>()?, Some(prefix_len.parse::<u8>()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+        if prefix_len > max_prefix_len {
+            return Err(eyre!("invalid prefix length in IP range {s}"));
+        }
+        Ok(Self {
+            addr: addr.to_canonical(),
+            prefix_len,
+        })
+    }
+}
+
+/// A fixed-window rate limiter.
+#[derive(Debug)]
+pub(crate) struct RateLimiter<K> {
+    /// The maximum number of requests per window; zero means no limit.
+    limit: u32,
+    /// For each key, the start of its current window and the number of
+    /// requests made in it. Also stores when the map was last pruned.
+    counters: Mutex<(HashMap<K, (Instant, u32)>, Instant)>,
+}
+
+impl<K: Clone + Eq + Hash> RateLimiter<K> {
+    /// Create a new RateLimiter allowing `limit` requests per
+    /// [`RATE_LIMIT_WINDOW`].
+    pub(crate) fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            counters: Mutex::new((HashMap::new(), Instant::now())),
+        }
+    }
+
+    /// Register a request for the given key, returning an error if the limit
+    /// was exceeded. The error tells the client when the window ends.
+    pub(crate) fn check(&self, key: &K, what: &str) -> Result<(), IntoResponseError> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut guard = self.counters.lock().unwrap();
+        let (counters, last_pruned) = &mut *guard;
+        // Remove stale entries once in a while to keep memory bounded.
+        if now.duration_since(*last_pruned) >= RATE_LIMIT_WINDOW {
+            counters.retain(|_, (start, _)| now.duration_since(*start) < RATE_LIMIT_WINDOW);
+            *last_pruned = now;
+        }
+        let (start, count) = counters.entry(key.clone()).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_LIMIT_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            let retry_after = RATE_LIMIT_WINDOW.saturating_sub(now.duration_since(*start));
+            return Err(
+                IntoResponseError::from(Error::LimitExceeded(format!("too many {what}")))
+                    .retry_after(retry_after),
+            );
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// The rate limiters used by the server.
+#[derive(Debug)]
+pub(crate) struct RateLimiters {
+    pub(crate) challenges_by_ip: RateLimiter<IpAddr>,
+    pub(crate) logins_by_ip: RateLimiter<IpAddr>,
+    pub(crate) logins_by_pubkey: RateLimiter<PublicKey>,
+}
+
+impl RateLimiters {
+    pub(crate) fn new(limits: &Limits) -> Self {
+        Self {
+            challenges_by_ip: RateLimiter::new(limits.challenges_per_ip),
+            logins_by_ip: RateLimiter::new(limits.logins_per_ip),
+            logins_by_pubkey: RateLimiter::new(limits.logins_per_pubkey),
+        }
+    }
+}
+
+/// The IP of the client making the request, if known. It is not known if the
+/// server was not set up to provide the connection info (e.g. in tests).
+///
+/// Note that if the server is behind a reverse proxy, this will be the IP of
+/// the proxy.
+#[derive(Debug)]
+pub(crate) struct ClientIp(pub(crate) Option<IpAddr>);
+
+impl<S: Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_canonical()),
+        ))
+    }
+}
+
+/// Middleware that rejects requests from IPs that are not allowed.
+pub(crate) async fn filter_ip(
+    State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, IntoResponseError> {
+    if let Some(ip) = ip {
+        if !state.limits.is_ip_allowed(ip) {
+            tracing::debug!("rejecting request from {}", ip);
+            return Err(Error::IpNotAllowed.into());
+        }
+    }
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,123 @@
+//! Logging setup.
+//!
+//! Logs can be written as text or as JSON (one object per line). In both
+//! cases each event includes the fields of the spans it happened in: the
+//! `request` span has the request ID, and the span of each API handler has
+//! the session ID (if any) and a hash of the user's public key, so that all
+//! the requests related to a session can be found. The handler spans are at
+//! the debug level, so their fields are only included if it is enabled for
+//! frostd (e.g. with `log_level = "info,frostd=debug"`).
+//!
+//! If frostd is built with the `otlp` feature, spans can also be exported to
+//! an OpenTelemetry collector with OTLP.
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{
+    layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter, Layer,
+};
+
+use crate::config::Config;
+
+/// The format of the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Flushes the spans not exported yet when dropped. It must be kept alive
+/// while the server runs.
+pub struct Guard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(e) = tracer_provider.shutdown() {
+                eprintln!("error flushing spans: {e}");
+            }
+        }
+    }
+}
+
+/// Initialize logging with the given configuration.
+pub fn init(config: &Config) -> Result<Guard, Box<dyn std::error::Error>> {
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.log_level))?;
+    let fmt = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(filter).with(fmt);
+
+    #[cfg(feature = "otlp")]
+    {
+        let (otlp, tracer_provider) = match &config.otlp_endpoint {
+            Some(endpoint) => {
+                let (layer, tracer_provider) = otlp::layer(endpoint)?;
+                (Some(layer), Some(tracer_provider))
+            }
+            None => (None, None),
+        };
+        subscriber.with(otlp).init();
+        Ok(Guard { tracer_provider })
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        if config.otlp_endpoint.is_some() {
+            return Err(eyre::eyre!(
+                "otlp_endpoint requires frostd to be built with the otlp feature"
+            )
+            .into());
+        }
+        subscriber.init();
+        Ok(Guard {})
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::WithExportConfig as _;
+    use opentelemetry_sdk::{
+        runtime,
+        trace::{Tracer, TracerProvider},
+        Resource,
+    };
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Create a layer that exports spans to the OTLP collector (using gRPC)
+    /// at the given endpoint, e.g. `http://localhost:4317`.
+    pub(super) fn layer<S>(
+        endpoint: &str,
+    ) -> Result<(OpenTelemetryLayer<S, Tracer>, TracerProvider), Box<dyn std::error::Error>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", "frostd")]))
+            .build();
+        let tracer = tracer_provider.tracer("frostd");
+        Ok((
+            tracing_opentelemetry::layer().with_tracer(tracer),
+            tracer_provider,
+        ))
+    }
+}
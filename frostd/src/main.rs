@@ -1,20 +1,29 @@
 use clap::Parser;
-use frostd::args::Args;
+use frostd::args::{Args, Command};
+use frostd::config::Config;
 use frostd::run;
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    // initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
+    match args.command {
+        Some(Command::Admin {
+            config,
+            server_url,
+            connection,
+            command,
+        }) => return frostd::cli::admin(config, &server_url, &connection, &command).await,
+        Some(Command::Openapi) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&frostd::openapi::spec())?
+            );
+            return Ok(());
+        }
+        None => {}
+    }
+    let config = Config::load(&args)?;
+    let _guard = frostd::logging::init(&config)?;
     tracing::event!(tracing::Level::INFO, "server running");
-    run(&args).await
+    run(&config).await
 }
@@ -0,0 +1,239 @@
+//! Prometheus metrics.
+//!
+//! The metrics are kept in simple atomic counters and rendered in the
+//! Prometheus text exposition format when scraped, which avoids pulling a
+//! metrics library for the handful of metrics we need.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::state::SharedState;
+
+/// The upper bounds (in seconds) of the request latency histogram buckets.
+/// They go up to a minute since `receive` calls may be long polling.
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// A monotonically increasing counter.
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub(crate) fn inc(&self) {
+        self.add(1);
+    }
+
+    pub(crate) fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram of request latencies.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The number of observations in each bucket (not cumulative).
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The metrics collected by the server. Gauges (e.g. the number of active
+/// sessions) are not stored here but computed from the state when scraped.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) sessions_created: Counter,
+    pub(crate) sessions_closed: Counter,
+    pub(crate) sessions_timed_out: Counter,
+    pub(crate) sessions_aborted: Counter,
+    pub(crate) logins_succeeded: Counter,
+    pub(crate) logins_failed: Counter,
+    pub(crate) messages_sent: Counter,
+    pub(crate) messages_received: Counter,
+    /// Request latencies, keyed by route and status code.
+    request_durations: Mutex<BTreeMap<(String, u16), Histogram>>,
+}
+
+impl Metrics {
+    /// Render the metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self, state: &SharedState) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            (
+                "frostd_sessions_created_total",
+                "Number of sessions created.",
+                &self.sessions_created,
+            ),
+            (
+                "frostd_sessions_closed_total",
+                "Number of sessions closed by their coordinator.",
+                &self.sessions_closed,
+            ),
+            (
+                "frostd_sessions_aborted_total",
+                "Number of sessions aborted by one of their members.",
+                &self.sessions_aborted,
+            ),
+            (
+                "frostd_sessions_timed_out_total",
+                "Number of sessions that timed out.",
+                &self.sessions_timed_out,
+            ),
+            (
+                "frostd_logins_succeeded_total",
+                "Number of successful logins.",
+                &self.logins_succeeded,
+            ),
+            (
+                "frostd_logins_failed_total",
+                "Number of failed logins.",
+                &self.logins_failed,
+            ),
+            (
+                "frostd_messages_sent_total",
+                "Number of messages queued, counting each recipient separately.",
+                &self.messages_sent,
+            ),
+            (
+                "frostd_messages_received_total",
+                "Number of messages delivered to recipients.",
+                &self.messages_received,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            write_metric(&mut out, name, help, "counter", counter.get() as f64);
+        }
+
+        let (active_sessions, queued_messages, queued_bytes) = {
+            let sessions = state.sessions.sessions.read().unwrap();
+            let queues = sessions
+                .iter()
+                .flat_map(|(_, session)| session.queue.values());
+            let (msgs, bytes) = queues.fold((0, 0), |(msgs, bytes), queue| {
+                (
+                    msgs + queue.len(),
+                    bytes + queue.iter().map(|m| m.msg.len()).sum::<usize>(),
+                )
+            });
+            (sessions.len(), msgs, bytes)
+        };
+        let access_tokens = state.access_tokens.read().unwrap().len();
+        let gauges = [
+            (
+                "frostd_active_sessions",
+                "Number of open sessions.",
+                active_sessions,
+            ),
+            (
+                "frostd_queued_messages",
+                "Number of messages waiting to be received.",
+                queued_messages,
+            ),
+            (
+                "frostd_queued_bytes",
+                "Total size of the messages waiting to be received.",
+                queued_bytes,
+            ),
+            (
+                "frostd_access_tokens",
+                "Number of valid access tokens.",
+                access_tokens,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            write_metric(&mut out, name, help, "gauge", value as f64);
+        }
+
+        let name = "frostd_http_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Latency of HTTP requests.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for ((route, status), histogram) in self.request_durations.lock().unwrap().iter() {
+            let labels = format!("route=\"{route}\",status=\"{status}\"");
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count);
+        }
+
+        out
+    }
+
+    fn observe_request(&self, route: &str, status: u16, seconds: f64) {
+        self.request_durations
+            .lock()
+            .unwrap()
+            .entry((route.to_string(), status))
+            .or_default()
+            .observe(seconds);
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Middleware that records the latency of each request, per route.
+pub(crate) async fn track_requests(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Use the route rather than the actual path to avoid unbounded
+    // cardinality (e.g. if someone scans the server for random paths).
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state.metrics.observe_request(
+        &route,
+        response.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// Implement the metrics API.
+pub(crate) async fn metrics(State(state): State<SharedState>) -> String {
+    state.metrics.render(&state)
+}
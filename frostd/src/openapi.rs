@@ -0,0 +1,610 @@
+//! OpenAPI description of the frostd API.
+//!
+//! The API router is built from [`ENDPOINTS`], so every endpoint served is
+//! described in the document. The schemas of the arguments and outputs are
+//! written by hand to match the serde representation of the types in
+//! [`frost_client::api`]; the integration tests check that they stay in sync.
+
+use axum::{
+    routing::{post, MethodRouter},
+    Json,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{admin, functions, state::SharedState, API_VERSIONS};
+
+/// Who can call an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Auth {
+    /// Anyone.
+    None,
+    /// Logged in users.
+    User,
+    /// Logged in administrators.
+    Admin,
+}
+
+/// An API endpoint.
+pub(crate) struct Endpoint {
+    /// The path of the endpoint, without the version prefix.
+    pub(crate) path: &'static str,
+    /// A short description of what the endpoint does.
+    pub(crate) summary: &'static str,
+    pub(crate) auth: Auth,
+    /// The name of the schema of the arguments, if the endpoint takes any.
+    pub(crate) args: Option<&'static str>,
+    /// The name of the schema of the output, if the endpoint returns any.
+    pub(crate) output: Option<&'static str>,
+    /// Create the handler of the endpoint.
+    pub(crate) handler: fn() -> MethodRouter<SharedState>,
+}
+
+/// The API endpoints. All of them are called with POST and JSON arguments.
+// TODO: use methods of a single object instead of separate functions?
+pub(crate) const ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        path: "/info",
+        summary: "Get information about the server and the limits it enforces",
+        auth: Auth::None,
+        args: None,
+        output: Some("InfoOutput"),
+        handler: || post(functions::info),
+    },
+    Endpoint {
+        path: "/challenge",
+        summary: "Get a challenge to sign in order to log in",
+        auth: Auth::None,
+        args: None,
+        output: Some("ChallengeOutput"),
+        handler: || post(functions::challenge),
+    },
+    Endpoint {
+        path: "/login",
+        summary: "Log in with a signed challenge, getting an access token",
+        auth: Auth::None,
+        args: Some("LoginArgs"),
+        output: Some("LoginOutput"),
+        handler: || post(functions::login),
+    },
+    Endpoint {
+        path: "/logout",
+        summary: "Log out, invalidating the access token",
+        auth: Auth::User,
+        args: None,
+        output: None,
+        handler: || post(functions::logout),
+    },
+    Endpoint {
+        path: "/refresh",
+        summary: "Get a new access token, invalidating the current one",
+        auth: Auth::User,
+        args: None,
+        output: Some("LoginOutput"),
+        handler: || post(functions::refresh),
+    },
+    Endpoint {
+        path: "/create_new_session",
+        summary: "Create a session, becoming its coordinator",
+        auth: Auth::User,
+        args: Some("CreateNewSessionArgs"),
+        output: Some("CreateNewSessionOutput"),
+        handler: || post(functions::create_new_session),
+    },
+    Endpoint {
+        path: "/list_sessions",
+        summary: "List the sessions the user is part of",
+        auth: Auth::User,
+        args: None,
+        output: Some("ListSessionsOutput"),
+        handler: || post(functions::list_sessions),
+    },
+    Endpoint {
+        path: "/get_session_info",
+        summary: "Get information about a session and the status of its participants",
+        auth: Auth::User,
+        args: Some("GetSessionInfoArgs"),
+        output: Some("GetSessionInfoOutput"),
+        handler: || post(functions::get_session_info),
+    },
+    Endpoint {
+        path: "/send",
+        summary: "Send a message to members of a session",
+        auth: Auth::User,
+        args: Some("SendArgs"),
+        output: None,
+        handler: || post(functions::send),
+    },
+    Endpoint {
+        path: "/receive",
+        summary: "Receive the messages sent to the user in a session",
+        auth: Auth::User,
+        args: Some("ReceiveArgs"),
+        output: Some("ReceiveOutput"),
+        handler: || post(functions::receive),
+    },
+    Endpoint {
+        path: "/ack",
+        summary: "Acknowledge received messages, removing them from the server",
+        auth: Auth::User,
+        args: Some("AckArgs"),
+        output: None,
+        handler: || post(functions::ack),
+    },
+    Endpoint {
+        path: "/broadcast",
+        summary: "Broadcast a message to all members of a session",
+        auth: Auth::User,
+        args: Some("BroadcastArgs"),
+        output: None,
+        handler: || post(functions::broadcast),
+    },
+    Endpoint {
+        path: "/get_broadcasts",
+        summary: "Get the messages broadcast in a session, attested by the server",
+        auth: Auth::User,
+        args: Some("GetBroadcastsArgs"),
+        output: Some("GetBroadcastsOutput"),
+        handler: || post(functions::get_broadcasts),
+    },
+    Endpoint {
+        path: "/accept_session",
+        summary: "Accept the invitation to take part in a session",
+        auth: Auth::User,
+        args: Some("AcceptSessionArgs"),
+        output: None,
+        handler: || post(functions::accept_session),
+    },
+    Endpoint {
+        path: "/decline_session",
+        summary: "Decline to take part in a session",
+        auth: Auth::User,
+        args: Some("DeclineSessionArgs"),
+        output: None,
+        handler: || post(functions::decline_session),
+    },
+    Endpoint {
+        path: "/abort_session",
+        summary: "Abort a session, notifying its other members",
+        auth: Auth::User,
+        args: Some("AbortSessionArgs"),
+        output: None,
+        handler: || post(functions::abort_session),
+    },
+    Endpoint {
+        path: "/close_session",
+        summary: "Close a session coordinated by the user",
+        auth: Auth::User,
+        args: Some("CloseSessionArgs"),
+        output: None,
+        handler: || post(functions::close_session),
+    },
+    Endpoint {
+        path: "/admin/add_user",
+        summary: "Add a user to the allowlist",
+        auth: Auth::Admin,
+        args: Some("AdminAddUserArgs"),
+        output: None,
+        handler: || post(admin::add_user),
+    },
+    Endpoint {
+        path: "/admin/remove_user",
+        summary: "Remove a user from the allowlist, revoking their access tokens",
+        auth: Auth::Admin,
+        args: Some("AdminRemoveUserArgs"),
+        output: None,
+        handler: || post(admin::remove_user),
+    },
+    Endpoint {
+        path: "/admin/list_users",
+        summary: "List the users in the allowlist",
+        auth: Auth::Admin,
+        args: None,
+        output: Some("AdminListUsersOutput"),
+        handler: || post(admin::list_users),
+    },
+    Endpoint {
+        path: "/admin/list_sessions",
+        summary: "List all open sessions",
+        auth: Auth::Admin,
+        args: None,
+        output: Some("AdminListSessionsOutput"),
+        handler: || post(admin::list_sessions),
+    },
+    Endpoint {
+        path: "/admin/close_session",
+        summary: "Close any session",
+        auth: Auth::Admin,
+        args: Some("AdminCloseSessionArgs"),
+        output: None,
+        handler: || post(admin::close_session),
+    },
+    Endpoint {
+        path: "/admin/revoke_tokens",
+        summary: "Revoke all access tokens of a user",
+        auth: Auth::Admin,
+        args: Some("AdminRevokeTokensArgs"),
+        output: Some("AdminRevokeTokensOutput"),
+        handler: || post(admin::revoke_tokens),
+    },
+];
+
+/// Implement the openapi.json endpoint.
+pub(crate) async fn openapi() -> Json<Value> {
+    Json(spec())
+}
+
+/// Return the OpenAPI 3.1 document describing the API.
+pub fn spec() -> Value {
+    let mut paths = Map::new();
+    for endpoint in ENDPOINTS {
+        paths.insert(endpoint.path.to_string(), path_item(endpoint));
+    }
+    let servers: Vec<Value> = API_VERSIONS
+        .iter()
+        .rev()
+        .map(|v| json!({ "url": format!("/{v}") }))
+        .collect();
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "frostd",
+            "description": "Server that helps FROST participants and coordinators \
+                communicate. All endpoints are called with POST and return \
+                errors as a LowError JSON object. Binary values are hex-encoded.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": servers,
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "accessToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The access token returned by /login.",
+                },
+            },
+        },
+    })
+}
+
+/// Describe an endpoint.
+fn path_item(endpoint: &Endpoint) -> Value {
+    let mut operation = json!({
+        "operationId": endpoint.path.trim_start_matches('/').replace('/', "_"),
+        "summary": endpoint.summary,
+        "responses": {
+            "200": match endpoint.output {
+                Some(output) => json!({
+                    "description": "Success",
+                    "content": { "application/json": { "schema": reference(output) } },
+                }),
+                None => json!({ "description": "Success" }),
+            },
+            "default": {
+                "description": "Error. The status code depends on the error; \
+                    older servers always return 500.",
+                "content": { "application/json": { "schema": reference("LowError") } },
+            },
+        },
+    });
+    if let Some(args) = endpoint.args {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": reference(args) } },
+        });
+    }
+    if endpoint.auth != Auth::None {
+        operation["security"] = json!([{ "accessToken": [] }]);
+    }
+    if endpoint.auth == Auth::Admin {
+        operation["tags"] = json!(["admin"]);
+        operation["description"] = json!("Requires being logged in as an administrator.");
+    }
+    json!({ "post": operation })
+}
+
+/// Reference the schema with the given name.
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// An object schema with the given required and optional properties.
+fn object(required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = required
+        .iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    let required: Vec<&str> = required.iter().map(|(name, _)| *name).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// A schema that also allows null.
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn uint(max: Option<u64>) -> Value {
+    match max {
+        Some(max) => json!({ "type": "integer", "minimum": 0, "maximum": max }),
+        None => json!({ "type": "integer", "format": "int64", "minimum": 0 }),
+    }
+}
+
+fn uuid() -> Value {
+    json!({ "type": "string", "format": "uuid" })
+}
+
+fn bytes() -> Value {
+    json!({
+        "type": "string",
+        "pattern": "^([0-9a-f]{2})*$",
+        "description": "Hex-encoded bytes.",
+    })
+}
+
+fn timestamp(description: &str) -> Value {
+    json!({
+        "type": "integer",
+        "format": "int64",
+        "minimum": 0,
+        "description": description,
+    })
+}
+
+/// The schemas of the API types, named after them.
+fn schemas() -> Value {
+    let pubkey = reference("PublicKey");
+    let pubkeys = array(pubkey.clone());
+    json!({
+        "PublicKey": {
+            "type": "string",
+            "pattern": "^[0-9a-f]{64}$",
+            "description": "Hex-encoded communication public key (X25519).",
+        },
+        "InfoOutput": object(
+            &[
+                ("version", string()),
+                ("max_msg_size", uint(None)),
+                ("max_wait_timeout_ms", uint(None)),
+                ("session_timeout_secs", uint(None)),
+                ("challenge_timeout_secs", uint(None)),
+                ("access_token_timeout_secs", uint(None)),
+                ("max_sessions_per_coordinator", uint(None)),
+                ("max_queued_messages_per_recipient", uint(None)),
+                ("max_queued_bytes_per_recipient", uint(None)),
+            ],
+            &[
+                ("api_versions", array(string())),
+                ("features", array(string())),
+            ],
+        ),
+        "ChallengeOutput": object(&[("challenge", uuid())], &[]),
+        "LoginArgs": object(
+            &[
+                ("challenge", uuid()),
+                ("pubkey", pubkey.clone()),
+                ("signature", bytes()),
+            ],
+            &[],
+        ),
+        "LoginOutput": object(
+            &[],
+            &[("access_token", uuid()), ("signed_access_token", string())],
+        ),
+        "CreateNewSessionArgs": object(
+            &[("pubkeys", pubkeys.clone()), ("message_count", uint(Some(255)))],
+            &[("metadata", reference("SessionMetadata"))],
+        ),
+        "SessionMetadata": object(
+            &[],
+            &[
+                ("group", nullable(bytes())),
+                ("ciphersuite", nullable(string())),
+                ("kind", nullable(reference("SessionKind"))),
+                ("description", nullable(string())),
+            ],
+        ),
+        "SessionKind": {
+            "type": "string",
+            "enum": ["signing", "dkg", "refresh", "other"],
+        },
+        "CreateNewSessionOutput": object(&[("session_id", uuid())], &[]),
+        "ListSessionsOutput": object(&[("session_ids", array(uuid()))], &[]),
+        "GetSessionInfoArgs": object(&[("session_id", uuid())], &[]),
+        "GetSessionInfoOutput": object(
+            &[
+                ("message_count", uint(Some(255))),
+                ("pubkeys", pubkeys.clone()),
+                ("coordinator_pubkey", pubkey.clone()),
+            ],
+            &[
+                ("created_at", timestamp("UNIX timestamp in seconds; 0 if unknown.")),
+                ("last_activity", timestamp("UNIX timestamp in seconds; 0 if unknown.")),
+                ("expires_at", timestamp("UNIX timestamp in seconds; 0 if unknown.")),
+                ("participants", array(reference("ParticipantStatus"))),
+                ("metadata", reference("SessionMetadata")),
+            ],
+        ),
+        "ParticipantStatus": object(
+            &[
+                ("pubkey", pubkey.clone()),
+                ("messages_sent", uint(None)),
+                ("messages_pending", uint(None)),
+            ],
+            &[
+                ("last_seen", nullable(timestamp("UNIX timestamp in seconds."))),
+                ("invitation", reference("InvitationStatus")),
+                ("decline_reason", nullable(string())),
+            ],
+        ),
+        "InvitationStatus": {
+            "type": "string",
+            "enum": ["pending", "accepted", "declined"],
+        },
+        "AcceptSessionArgs": object(&[("session_id", uuid())], &[]),
+        "DeclineSessionArgs": object(
+            &[("session_id", uuid())],
+            &[("reason", nullable(string()))],
+        ),
+        "AbortSessionArgs": object(
+            &[("session_id", uuid()), ("reason", reference("AbortReason"))],
+            &[("message", nullable(string()))],
+        ),
+        "AbortReason": {
+            "type": "string",
+            "enum": ["cancelled", "error", "other"],
+        },
+        "SendArgs": object(
+            &[
+                ("session_id", uuid()),
+                ("recipients", pubkeys.clone()),
+                ("msg", bytes()),
+            ],
+            &[("msg_id", nullable(uuid()))],
+        ),
+        "ReceiveArgs": object(
+            &[("session_id", uuid()), ("as_coordinator", boolean())],
+            &[
+                ("wait_timeout_ms", nullable(uint(None))),
+                ("after_seq", nullable(uint(None))),
+            ],
+        ),
+        "ReceiveOutput": object(&[("msgs", array(reference("Msg")))], &[]),
+        "Msg": object(
+            &[("sender", pubkey.clone()), ("msg", bytes())],
+            &[("seq", uint(None)), ("event", reference("SessionEvent"))],
+        ),
+        "SessionEvent": {
+            "oneOf": [
+                object(
+                    &[
+                        ("type", json!({ "const": "declined" })),
+                        ("pubkey", pubkey.clone()),
+                    ],
+                    &[("reason", nullable(string()))],
+                ),
+                object(
+                    &[
+                        ("type", json!({ "const": "aborted" })),
+                        ("pubkey", pubkey.clone()),
+                        ("by_coordinator", boolean()),
+                        ("reason", reference("AbortReason")),
+                    ],
+                    &[("message", nullable(string()))],
+                ),
+            ],
+            "discriminator": { "propertyName": "type" },
+        },
+        "AckArgs": object(
+            &[
+                ("session_id", uuid()),
+                ("as_coordinator", boolean()),
+                ("seq", uint(None)),
+            ],
+            &[],
+        ),
+        "BroadcastArgs": object(&[("session_id", uuid()), ("msg", bytes())], &[]),
+        "GetBroadcastsArgs": object(
+            &[("session_id", uuid())],
+            &[("wait_timeout_ms", nullable(uint(None)))],
+        ),
+        "GetBroadcastsOutput": object(
+            &[
+                ("msgs", array(reference("Msg"))),
+                ("attestation", nullable(reference("BroadcastAttestation"))),
+            ],
+            &[],
+        ),
+        "BroadcastHash": object(&[("sender", pubkey.clone()), ("hash", bytes())], &[]),
+        "BroadcastAttestation": object(
+            &[
+                ("session_id", uuid()),
+                ("hashes", array(reference("BroadcastHash"))),
+                ("server_pubkey", pubkey.clone()),
+                ("signature", bytes()),
+            ],
+            &[],
+        ),
+        "CloseSessionArgs": object(&[("session_id", uuid())], &[]),
+        "AdminAddUserArgs": object(&[("pubkey", pubkey.clone())], &[]),
+        "AdminRemoveUserArgs": object(&[("pubkey", pubkey.clone())], &[]),
+        "AdminListUsersOutput": object(&[], &[("pubkeys", nullable(pubkeys.clone()))]),
+        "AdminSessionInfo": object(
+            &[
+                ("session_id", uuid()),
+                ("coordinator_pubkey", pubkey.clone()),
+                ("pubkeys", pubkeys.clone()),
+                ("message_count", uint(Some(255))),
+                ("created_at", timestamp("UNIX timestamp in seconds.")),
+                ("queued_messages", uint(None)),
+                ("queued_bytes", uint(None)),
+                ("last_activity", timestamp("UNIX timestamp in seconds.")),
+                ("expires_at", timestamp("UNIX timestamp in seconds.")),
+            ],
+            &[],
+        ),
+        "AdminListSessionsOutput": object(
+            &[("sessions", array(reference("AdminSessionInfo")))],
+            &[],
+        ),
+        "AdminCloseSessionArgs": object(&[("session_id", uuid())], &[]),
+        "AdminRevokeTokensArgs": object(&[("pubkey", pubkey)], &[]),
+        "AdminRevokeTokensOutput": object(&[("revoked", uint(None))], &[]),
+        "LowError": object(
+            &[
+                ("code", uint(None)),
+                ("msg", string()),
+                ("error", reference("Error")),
+            ],
+            &[("details", reference("ErrorDetails"))],
+        ),
+        "Error": object(
+            &[(
+                "code",
+                json!({
+                    "type": "string",
+                    "enum": [
+                        "InvalidArgument",
+                        "Unauthorized",
+                        "SessionNotFound",
+                        "NotCoordinator",
+                        "NotInSession",
+                        "UnregisteredUser",
+                        "NotAdmin",
+                        "LimitExceeded",
+                        "IpNotAllowed",
+                        "ShuttingDown",
+                        "SessionAborted",
+                        "TooLarge",
+                    ],
+                }),
+            )],
+            &[("err", string())],
+        ),
+        "ErrorDetails": object(
+            &[],
+            &[
+                ("field", string()),
+                ("retry_after_secs", uint(None)),
+            ],
+        ),
+    })
+}
@@ -0,0 +1,140 @@
+//! Registry of the users allowed to use the server.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use eyre::eyre;
+
+use crate::{Error, PublicKey};
+
+/// The users allowed to log in to the server, and the server administrators.
+#[derive(Debug, Default)]
+pub struct UserRegistry {
+    /// The public keys of the users allowed to log in. If `None`, any user
+    /// can log in.
+    allowlist: Option<RwLock<HashSet<PublicKey>>>,
+    /// The file the allowlist was loaded from, which is rewritten when the
+    /// allowlist is changed with the admin API.
+    allowlist_path: Option<PathBuf>,
+    /// The public keys of the administrators, which can always log in and
+    /// can call the admin API.
+    admins: HashSet<PublicKey>,
+}
+
+impl UserRegistry {
+    /// Create a new UserRegistry with the given administrators and optional
+    /// allowlist. The allowlist will not be persisted.
+    pub fn new(admins: HashSet<PublicKey>, allowlist: Option<HashSet<PublicKey>>) -> Self {
+        Self {
+            allowlist: allowlist.map(RwLock::new),
+            allowlist_path: None,
+            admins,
+        }
+    }
+
+    /// Create a new UserRegistry with the given administrators, reading the
+    /// allowlist from the given file.
+    ///
+    /// The file must contain one hex-encoded public key per line. Empty lines
+    /// and lines starting with `#` are ignored. Note that comments are lost
+    /// if the allowlist is changed with the admin API, since the file is
+    /// rewritten.
+    pub fn from_file(
+        admins: HashSet<PublicKey>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(&path)?;
+        let allowlist = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(parse_pubkey)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            allowlist: Some(RwLock::new(allowlist)),
+            allowlist_path: Some(path.as_ref().to_path_buf()),
+            admins,
+        })
+    }
+
+    /// Return if the given user is allowed to log in.
+    pub(crate) fn is_allowed(&self, pubkey: &PublicKey) -> bool {
+        match &self.allowlist {
+            Some(allowlist) => self.is_admin(pubkey) || allowlist.read().unwrap().contains(pubkey),
+            None => true,
+        }
+    }
+
+    /// Return if the given user is an administrator.
+    pub(crate) fn is_admin(&self, pubkey: &PublicKey) -> bool {
+        self.admins.contains(pubkey)
+    }
+
+    /// Return the users in the allowlist, or `None` if the allowlist is not
+    /// enabled.
+    pub(crate) fn list(&self) -> Option<Vec<PublicKey>> {
+        self.allowlist
+            .as_ref()
+            .map(|allowlist| allowlist.read().unwrap().iter().cloned().collect())
+    }
+
+    /// Add a user to the allowlist.
+    pub(crate) fn add(&self, pubkey: PublicKey) -> Result<(), Error> {
+        let mut allowlist = self.allowlist()?.write().unwrap();
+        if allowlist.insert(pubkey) {
+            self.persist(&allowlist);
+        }
+        Ok(())
+    }
+
+    /// Remove a user from the allowlist.
+    pub(crate) fn remove(&self, pubkey: &PublicKey) -> Result<(), Error> {
+        let mut allowlist = self.allowlist()?.write().unwrap();
+        if allowlist.remove(pubkey) {
+            self.persist(&allowlist);
+        }
+        Ok(())
+    }
+
+    fn allowlist(&self) -> Result<&RwLock<HashSet<PublicKey>>, Error> {
+        self.allowlist
+            .as_ref()
+            .ok_or_else(|| Error::InvalidArgument("the allowlist is not enabled".into()))
+    }
+
+    /// Rewrite the allowlist file, if any. Errors are logged but otherwise
+    /// ignored, since the in-memory allowlist is still valid.
+    fn persist(&self, allowlist: &HashSet<PublicKey>) {
+        let Some(path) = &self.allowlist_path else {
+            return;
+        };
+        let mut pubkeys: Vec<_> = allowlist.iter().map(|p| hex::encode(&p.0)).collect();
+        pubkeys.sort();
+        let write = || -> std::io::Result<()> {
+            let tmp_path = path.with_extension("tmp");
+            let mut file = fs::File::create(&tmp_path)?;
+            for pubkey in pubkeys {
+                writeln!(file, "{pubkey}")?;
+            }
+            file.sync_all()?;
+            fs::rename(tmp_path, path)
+        };
+        if let Err(e) = write() {
+            tracing::error!("error writing allowlist to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Parse a hex-encoded communication public key.
+pub fn parse_pubkey(s: &str) -> Result<PublicKey, eyre::Report> {
+    let pubkey = hex::decode(s.trim())?;
+    if pubkey.len() != 32 {
+        return Err(eyre!("invalid public key length: {s}"));
+    }
+    Ok(PublicKey(pubkey))
+}
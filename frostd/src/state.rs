@@ -1,24 +1,54 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    future::Future,
     pin::Pin,
     sync::{Arc, RwLock},
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use delay_map::{HashMapDelay, HashSetDelay};
+use frost_client::cipher::{Cipher, PrivateKey};
 use futures::{Stream, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Notify};
 use uuid::Uuid;
 
-use crate::{Msg, PublicKey};
+use crate::{
+    config::Timeouts,
+    limits::{Limits, RateLimiters},
+    metrics::Metrics,
+    registry::UserRegistry,
+    storage::{
+        MemoryStorage, Storage, StorageOp, StorageWriter, StoredAccessToken, StoredQueue,
+        StoredRevocation,
+    },
+    token::{TokenClaims, TokenKey},
+    InvitationStatus, LoginOutput, Msg, PublicKey, SessionEvent, SessionMetadata,
+};
 
-/// How long a session stays open.
+/// How long a session stays open, by default.
 pub(crate) const SESSION_TIMEOUT: std::time::Duration =
     std::time::Duration::from_secs(60 * 60 * 24);
-/// How long a challenge can be replied to.
-const CHALLENGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
-/// How long an acesss token lasts.
-const ACCESS_TOKEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// How long a challenge can be replied to, by default.
+pub(crate) const CHALLENGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long an acesss token lasts, by default.
+pub(crate) const ACCESS_TOKEN_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(60 * 60);
+/// How long to wait for in-flight requests when shutting down, by default.
+/// Long polling `receive` calls return immediately on shutdown, so this can
+/// be short.
+pub(crate) const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How many of the most recent message IDs of each sender are kept to ignore
+/// retried `send` calls. Clients only retry their latest calls, so older IDs
+/// are forgotten to keep the session (and its persisted copy) bounded.
+pub(crate) const MAX_MSG_IDS_PER_SENDER: usize = 32;
+
+/// How long an aborted session is kept before being closed, so that its
+/// members can receive the abort event. It's longer than the maximum long
+/// polling time so that members currently polling get it.
+pub(crate) const ABORT_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(60);
 
 /// Helper struct that allows calling `next()` on a `Stream` behind a `RwLock`
 /// (namely a `HashMapDelay` or `HashSetDelay` in our case) without locking
@@ -36,14 +66,14 @@ impl<T: Stream + Unpin> Stream for RwLockStream<'_, T> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SessionParticipant {
     Coordinator,
     Participant(PublicKey),
 }
 
 /// A particular signing session.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
     /// The public keys of the participants
     pub(crate) pubkeys: Vec<PublicKey>,
@@ -51,8 +81,229 @@ pub struct Session {
     pub(crate) coordinator_pubkey: PublicKey,
     /// The number of messages being simultaneously signed.
     pub(crate) message_count: u8,
-    /// The message queue.
+    /// The message queue. Messages are kept until acknowledged by the
+    /// recipient (or received, for clients that do not use sequence numbers).
+    /// It is persisted separately, one entry per recipient (see
+    /// [`crate::storage::Storage::save_queue`]), but is still read from
+    /// sessions persisted by older versions.
+    #[serde(default, skip_serializing, deserialize_with = "pairs::deserialize")]
     pub(crate) queue: HashMap<SessionParticipant, VecDeque<Msg>>,
+    /// The sequence number of the last message sent to each recipient.
+    #[serde(default, with = "pairs")]
+    pub(crate) last_seq: HashMap<SessionParticipant, u64>,
+    /// The IDs of the latest messages sent by each sender in the session (up
+    /// to [`MAX_MSG_IDS_PER_SENDER`]), used to ignore retried `send` calls.
+    #[serde(default, with = "pairs")]
+    pub(crate) msg_ids: HashMap<PublicKey, VecDeque<Uuid>>,
+    /// Notified when messages are added to the queue or the session is
+    /// closed, to wake up long polling `receive` calls.
+    #[serde(skip)]
+    pub(crate) notify: Arc<Notify>,
+    /// The activity of each user (participants and coordinator) in the
+    /// session.
+    #[serde(default, with = "pairs")]
+    pub(crate) activity: HashMap<PublicKey, UserActivity>,
+    /// When the session was created.
+    #[serde(default = "SystemTime::now")]
+    pub(crate) created_at: SystemTime,
+    /// Whether the session was aborted. Aborted sessions no longer accept
+    /// messages and are closed after [`ABORT_GRACE_PERIOD`].
+    #[serde(default)]
+    pub(crate) aborted: bool,
+    /// When the session expires, unless renewed. Tracked separately from the
+    /// `HashMapDelay` timeout so that it can be persisted.
+    pub(crate) expires_at: SystemTime,
+    /// When a message was last sent or received in the session (or when it
+    /// was created, if none was).
+    #[serde(default = "SystemTime::now")]
+    pub(crate) last_activity: SystemTime,
+    /// The message broadcast by each member of the session, if any. They
+    /// can't be changed once broadcast.
+    #[serde(default, with = "pairs")]
+    pub(crate) broadcasts: HashMap<PublicKey, Vec<u8>>,
+    /// The metadata attached to the session by the coordinator.
+    #[serde(default)]
+    pub(crate) metadata: SessionMetadata,
+}
+
+/// The activity of a user in a session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct UserActivity {
+    /// When the user last made a call related to the session, if ever.
+    pub(crate) last_seen: Option<SystemTime>,
+    /// The number of messages sent by the user in the session.
+    pub(crate) messages_sent: u64,
+    /// The response of the user to the invitation, if they are a
+    /// participant.
+    #[serde(default)]
+    pub(crate) invitation: InvitationStatus,
+    /// The reason given by the user when declining, if any.
+    #[serde(default)]
+    pub(crate) decline_reason: Option<String>,
+}
+
+impl Session {
+    /// Return a copy of the session without its message queue, to be
+    /// persisted (see [`AppState::persist_session_info`]).
+    fn snapshot(&self) -> Session {
+        Session {
+            pubkeys: self.pubkeys.clone(),
+            coordinator_pubkey: self.coordinator_pubkey.clone(),
+            message_count: self.message_count,
+            queue: Default::default(),
+            last_seq: self.last_seq.clone(),
+            msg_ids: self.msg_ids.clone(),
+            notify: Default::default(),
+            activity: self.activity.clone(),
+            created_at: self.created_at,
+            aborted: self.aborted,
+            expires_at: self.expires_at,
+            last_activity: self.last_activity,
+            broadcasts: self.broadcasts.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Record activity in the session, renewing its expiration. Note that the
+    /// caller must also update the timeout in the sessions `HashMapDelay`.
+    pub(crate) fn touch(&mut self, timeout: Duration) {
+        self.last_activity = SystemTime::now();
+        self.expires_at = self.last_activity + timeout;
+    }
+
+    /// Record that the user made a call related to the session.
+    pub(crate) fn seen(&mut self, pubkey: &PublicKey) {
+        self.activity.entry(pubkey.clone()).or_default().last_seen = Some(SystemTime::now());
+    }
+
+    /// Return if the user can take part in the session, i.e. if they are
+    /// its coordinator or a participant who did not decline.
+    pub(crate) fn is_member(&self, pubkey: &PublicKey) -> bool {
+        *pubkey == self.coordinator_pubkey
+            || (self.pubkeys.contains(pubkey) && !self.has_declined(pubkey))
+    }
+
+    /// Return if the user declined the invitation to the session.
+    pub(crate) fn has_declined(&self, pubkey: &PublicKey) -> bool {
+        self.activity.get(pubkey).map(|a| a.invitation) == Some(InvitationStatus::Declined)
+    }
+
+    /// Return the messages broadcast in the session, sorted by sender, and
+    /// whether every participant who did not decline has broadcast.
+    pub(crate) fn broadcasts(&self) -> (Vec<Msg>, bool) {
+        let mut msgs: Vec<_> = self
+            .broadcasts
+            .iter()
+            .map(|(sender, msg)| Msg {
+                sender: sender.clone(),
+                msg: msg.clone(),
+                seq: 0,
+                event: None,
+            })
+            .collect();
+        msgs.sort_by(|a, b| a.sender.0.cmp(&b.sender.0));
+        let complete = self
+            .pubkeys
+            .iter()
+            .filter(|p| self.is_member(p))
+            .all(|p| self.broadcasts.contains_key(p));
+        (msgs, complete)
+    }
+
+    /// Return if the given message ID was recently used by the sender.
+    pub(crate) fn has_msg_id(&self, sender: &PublicKey, msg_id: &Uuid) -> bool {
+        self.msg_ids
+            .get(sender)
+            .is_some_and(|ids| ids.contains(msg_id))
+    }
+
+    /// Record a message ID used by the sender, forgetting the oldest one if
+    /// there are too many.
+    pub(crate) fn add_msg_id(&mut self, sender: &PublicKey, msg_id: Uuid) {
+        let ids = self.msg_ids.entry(sender.clone()).or_default();
+        if ids.len() >= MAX_MSG_IDS_PER_SENDER {
+            ids.pop_front();
+        }
+        ids.push_back(msg_id);
+    }
+
+    /// Add a message to the queue of the given recipient, assigning it the
+    /// next sequence number for that recipient.
+    pub(crate) fn enqueue(&mut self, recipient: SessionParticipant, mut msg: Msg) {
+        let last_seq = self.last_seq.entry(recipient.clone()).or_default();
+        *last_seq += 1;
+        msg.seq = *last_seq;
+        self.queue.entry(recipient).or_default().push_back(msg);
+    }
+
+    /// Deliver an event caused by the given user to all other members of the
+    /// session, including the coordinator.
+    pub(crate) fn broadcast_event(&mut self, sender: &PublicKey, event: SessionEvent) {
+        let coordinator =
+            (*sender != self.coordinator_pubkey).then_some(SessionParticipant::Coordinator);
+        let recipients: Vec<_> = coordinator
+            .into_iter()
+            .chain(
+                self.pubkeys
+                    .iter()
+                    .filter(|p| *p != sender && self.is_member(p))
+                    .cloned()
+                    .map(SessionParticipant::Participant),
+            )
+            .collect();
+        for recipient in recipients {
+            self.enqueue(
+                recipient,
+                Msg {
+                    sender: sender.clone(),
+                    msg: Vec::new(),
+                    seq: 0,
+                    event: Some(event.clone()),
+                },
+            );
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Remove the messages of the given recipient with sequence number up to
+    /// `seq`, returning how many were removed.
+    pub(crate) fn ack(&mut self, recipient: &SessionParticipant, seq: u64) -> usize {
+        let Some(queue) = self.queue.get_mut(recipient) else {
+            return 0;
+        };
+        let len = queue.len();
+        while queue.front().is_some_and(|m| m.seq <= seq) {
+            queue.pop_front();
+        }
+        len - queue.len()
+    }
+}
+
+/// Serializes maps with non-string keys (e.g. the message queue) as a list of
+/// pairs, since JSON (which is used by the storage backends) does not support
+/// them.
+mod pairs {
+    use std::{collections::HashMap, hash::Hash};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S: Serializer, K: Serialize, T: Serialize>(
+        map: &HashMap<K, T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub(super) fn deserialize<'de, D, K, T>(deserializer: D) -> Result<HashMap<K, T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + Eq + Hash,
+        T: Deserialize<'de>,
+    {
+        Ok(Vec::<(K, T)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
 }
 
 /// The global state of the server.
@@ -61,6 +312,91 @@ pub struct AppState {
     pub(crate) sessions: SessionState,
     pub(crate) challenges: Arc<RwLock<HashSetDelay<Uuid>>>,
     pub(crate) access_tokens: Arc<RwLock<HashMapDelay<Uuid, PublicKey>>>,
+    /// The key used to sign access tokens, if they are signed instead of
+    /// being kept in `access_tokens` (see [`crate::token`]).
+    pub(crate) token_key: Option<TokenKey>,
+    /// The IDs of the signed access tokens that were revoked. They are kept
+    /// until the tokens expire.
+    pub(crate) revoked_tokens: Arc<RwLock<HashSetDelay<Uuid>>>,
+    /// The users whose signed access tokens were all revoked, with the ID of
+    /// the revocation in the storage backend and when it was made. They are
+    /// kept until the revoked tokens expire.
+    pub(crate) revoked_users: Arc<RwLock<HashMapDelay<PublicKey, (Uuid, SystemTime)>>>,
+    /// Writes the changes to the backend where sessions and access tokens
+    /// are persisted.
+    pub(crate) storage: StorageWriter,
+    /// The users allowed to log in, and the administrators.
+    pub(crate) users: UserRegistry,
+    /// The limits enforced by the server.
+    pub(crate) limits: Limits,
+    /// The rate limiters for the challenge and login APIs.
+    pub(crate) rate_limiters: RateLimiters,
+    /// The metrics exported by the metrics endpoint.
+    pub(crate) metrics: Metrics,
+    /// The timeouts of sessions, challenges and access tokens.
+    pub(crate) timeouts: Timeouts,
+    /// Set to true when the server starts shutting down.
+    shutdown: watch::Sender<bool>,
+    /// The key used to sign broadcast attestations. It is generated when the
+    /// server starts.
+    pub(crate) attestation_key: PrivateKey,
+    pub(crate) attestation_pubkey: PublicKey,
+    /// The identities of the server that users can sign when logging in (see
+    /// [`crate::server_identity()`]). If empty, the identity is taken from
+    /// each login request, which is only allowed for development (see
+    /// [`crate::config::Config`]).
+    pub(crate) server_names: Vec<String>,
+    /// Whether to accept login signatures of just the challenge, as made by
+    /// older clients.
+    pub(crate) allow_legacy_login: bool,
+    /// Whether users can only log in with the public key named in their TLS
+    /// client certificate.
+    pub(crate) bind_client_cert: bool,
+}
+
+/// Options used to create an [`AppState`].
+#[derive(Debug)]
+pub struct AppStateOptions {
+    /// The backend where sessions and access tokens are persisted.
+    pub storage: Box<dyn Storage>,
+    /// The users allowed to log in, and the administrators.
+    pub users: UserRegistry,
+    /// The limits enforced by the server.
+    pub limits: Limits,
+    /// The timeouts of sessions, challenges and access tokens.
+    pub timeouts: Timeouts,
+    /// The names (`host[:port]`) clients use to reach the server. Login
+    /// signatures are only accepted if made for one of them; if empty, for
+    /// the name in the login request itself, which does not prevent
+    /// malicious servers from relaying logins to this one.
+    pub server_names: Vec<String>,
+    /// Whether to accept login signatures of just the challenge, as made by
+    /// older clients. They can be relayed by malicious servers.
+    pub allow_legacy_login: bool,
+    /// Whether users can only log in with the public key named in their TLS
+    /// client certificate (see [`crate::tls`]). Requires the server to ask
+    /// clients for certificates.
+    pub bind_client_cert: bool,
+    /// The key used to sign access tokens. If set, access tokens are signed
+    /// and can be validated by any server with the same key (but revoking
+    /// them only affects this one); otherwise they are only valid in this
+    /// server.
+    pub token_key: Option<TokenKey>,
+}
+
+impl Default for AppStateOptions {
+    fn default() -> Self {
+        Self {
+            storage: Box::new(MemoryStorage),
+            users: Default::default(),
+            limits: Default::default(),
+            timeouts: Default::default(),
+            server_names: Vec::new(),
+            allow_legacy_login: false,
+            bind_client_cert: false,
+            token_key: None,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -81,70 +417,473 @@ impl SessionState {
 }
 
 impl AppState {
+    /// Create a new AppState with the default options, which keeps all state
+    /// in memory only and allows any user to log in.
     pub async fn new() -> Result<SharedState, Box<dyn std::error::Error>> {
+        Self::with_options(Default::default()).await
+    }
+
+    /// Create a new AppState with the given options. Any sessions and access
+    /// tokens previously saved in the storage backend that have not expired
+    /// yet are restored.
+    pub async fn with_options(
+        options: AppStateOptions,
+    ) -> Result<SharedState, Box<dyn std::error::Error>> {
+        let (attestation_key, attestation_pubkey) = Cipher::generate_keypair()?;
         let state = Arc::new(Self {
-            sessions: SessionState::new(SESSION_TIMEOUT),
-            challenges: RwLock::new(HashSetDelay::new(CHALLENGE_TIMEOUT)).into(),
-            access_tokens: RwLock::new(HashMapDelay::new(ACCESS_TOKEN_TIMEOUT)).into(),
+            sessions: SessionState::new(options.timeouts.session),
+            challenges: RwLock::new(HashSetDelay::new(options.timeouts.challenge)).into(),
+            access_tokens: RwLock::new(HashMapDelay::new(options.timeouts.access_token)).into(),
+            token_key: options.token_key,
+            revoked_tokens: RwLock::new(HashSetDelay::new(options.timeouts.access_token)).into(),
+            revoked_users: RwLock::new(HashMapDelay::new(options.timeouts.access_token)).into(),
+            storage: StorageWriter::new(options.storage)?,
+            users: options.users,
+            rate_limiters: RateLimiters::new(&options.limits),
+            limits: options.limits,
+            metrics: Default::default(),
+            timeouts: options.timeouts,
+            shutdown: watch::Sender::new(false),
+            attestation_key,
+            attestation_pubkey,
+            server_names: options
+                .server_names
+                .iter()
+                .map(|name| crate::server_identity(name))
+                .collect(),
+            allow_legacy_login: options.allow_legacy_login,
+            bind_client_cert: options.bind_client_cert,
         });
+        state.restore()?;
 
         // In order to effectively removed timed out entries, we need to
         // repeatedly call `next()` on them.
-        // These tasks will just run forever and will stop when the server stops.
+        // These tasks run until the server shuts down.
 
-        let state_clone = state.clone();
-        tokio::task::spawn(async move {
-            loop {
-                match RwLockStream(&state_clone.sessions.sessions).next().await {
-                    Some(Ok((uuid, session))) => {
-                        tracing::debug!("session {} timed out", uuid);
-                        let mut sessions_by_pubkey =
-                            state_clone.sessions.sessions_by_pubkey.write().unwrap();
-                        for pubkey in session.pubkeys {
-                            if let Some(sessions) = sessions_by_pubkey.get_mut(&pubkey) {
-                                sessions.remove(&uuid);
-                            }
-                        }
+        state.spawn_until_shutdown(|state| async move {
+            match RwLockStream(&state.sessions.sessions).next().await {
+                Some(Ok((uuid, session))) => {
+                    tracing::debug!("session {} timed out", uuid);
+                    // Aborted sessions are closed by timing out, but they
+                    // were already counted as aborted.
+                    if !session.aborted {
+                        state.metrics.sessions_timed_out.inc();
                     }
-                    _ => {
-                        // Annoyingly, if the map is empty, it returns
-                        // immediately instead of waiting for an entry to be
-                        // inserted and waiting for that to timeout. To avoid a
-                        // busy loop when the map is empty, we sleep for a bit.
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    session.notify.notify_waiters();
+                    state.remove_persisted_session(&uuid);
+                    let mut sessions_by_pubkey = state.sessions.sessions_by_pubkey.write().unwrap();
+                    for pubkey in session
+                        .pubkeys
+                        .iter()
+                        .chain(std::iter::once(&session.coordinator_pubkey))
+                    {
+                        if let Some(sessions) = sessions_by_pubkey.get_mut(pubkey) {
+                            sessions.remove(&uuid);
+                        }
                     }
                 }
+                _ => {
+                    // Annoyingly, if the map is empty, it returns
+                    // immediately instead of waiting for an entry to be
+                    // inserted and waiting for that to timeout. To avoid a
+                    // busy loop when the map is empty, we sleep for a bit.
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
             }
         });
-        // TODO: we could refactor these two loops with a generic function
-        // but it's just simpler to do this directly currently
-        let state_clone = state.clone();
-        tokio::task::spawn(async move {
-            loop {
-                match RwLockStream(&state_clone.challenges).next().await {
-                    Some(Ok(challenge)) => {
-                        tracing::debug!("challenge {} timed out", challenge);
-                    }
-                    _ => {
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
+        state.spawn_until_shutdown(|state| async move {
+            match RwLockStream(&state.challenges).next().await {
+                Some(Ok(challenge)) => {
+                    tracing::debug!("challenge {} timed out", challenge);
+                }
+                _ => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        state.spawn_until_shutdown(|state| async move {
+            match RwLockStream(&state.access_tokens).next().await {
+                Some(Ok((access_token, _pubkey))) => {
+                    tracing::debug!("access_token {} timed out", access_token);
+                    state.remove_persisted_access_token(&access_token);
+                }
+                _ => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        state.spawn_until_shutdown(|state| async move {
+            match RwLockStream(&state.revoked_tokens).next().await {
+                Some(Ok(id)) => {
+                    state.remove_persisted_revocation(&id);
+                }
+                _ => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
                 }
             }
         });
-        let state_clone = state.clone();
+        state.spawn_until_shutdown(|state| async move {
+            match RwLockStream(&state.revoked_users).next().await {
+                Some(Ok((_pubkey, (id, _revoked_at)))) => {
+                    state.remove_persisted_revocation(&id);
+                }
+                _ => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        Ok(state)
+    }
+
+    /// Spawn a task that repeatedly runs `f` until the server shuts down.
+    /// `f` is cancelled if it is running when that happens.
+    fn spawn_until_shutdown<F, Fut>(self: &Arc<Self>, f: F)
+    where
+        F: Fn(SharedState) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let state = self.clone();
         tokio::task::spawn(async move {
             loop {
-                match RwLockStream(&state_clone.access_tokens).next().await {
-                    Some(Ok((access_token, _pubkey))) => {
-                        tracing::debug!("access_token {} timed out", access_token);
-                    }
-                    _ => {
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
+                tokio::select! {
+                    _ = state.wait_for_shutdown() => break,
+                    _ = f(state.clone()) => {}
                 }
             }
         });
-        Ok(state)
+    }
+
+    /// Start shutting down the server: new sessions are no longer accepted,
+    /// the server is reported as not ready, long polling `receive` calls
+    /// return immediately and the background tasks stop.
+    ///
+    /// This does not stop the HTTP server itself, which should be done by
+    /// the caller (see [`crate::run`]).
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        for (_, session) in self.sessions.sessions.read().unwrap().iter() {
+            session.notify.notify_waiters();
+        }
+    }
+
+    /// Return if the server is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Wait until [`AppState::shutdown`] is called.
+    pub async fn wait_for_shutdown(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        // This can't fail since we hold the sender.
+        let _ = shutdown.wait_for(|s| *s).await;
+    }
+
+    /// Persist all sessions in the storage backend. Sessions are persisted
+    /// whenever they change, so this is only a safety net for when the
+    /// server stops.
+    pub fn persist_all_sessions(&self) {
+        for (id, session) in self.sessions.sessions.read().unwrap().iter() {
+            self.persist_session(id, session);
+        }
+    }
+
+    /// Wait until all the changes made so far are written to the storage
+    /// backend, which is done in the background.
+    pub async fn flush_storage(&self) {
+        self.storage.flush().await;
+    }
+
+    /// Load the persisted state from the storage backend into memory,
+    /// discarding entries that expired while the server was not running.
+    fn restore(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let stored = self.storage.load()?;
+        let now = SystemTime::now();
+
+        let mut queues: HashMap<Uuid, Vec<StoredQueue>> = HashMap::new();
+        for (id, queue) in stored.queues {
+            queues.entry(id).or_default().push(queue);
+        }
+
+        let mut sessions = self.sessions.sessions.write().unwrap();
+        let mut sessions_by_pubkey = self.sessions.sessions_by_pubkey.write().unwrap();
+        for (id, mut session) in stored.sessions {
+            let session_queues = queues.remove(&id).unwrap_or_default();
+            let Ok(remaining) = session.expires_at.duration_since(now) else {
+                self.remove_persisted_session(&id);
+                continue;
+            };
+            for queue in session_queues {
+                session.queue.insert(queue.recipient, queue.msgs);
+            }
+            // The queues of participants who declined are discarded, but
+            // they may have been persisted before that.
+            let declined: Vec<_> = session
+                .queue
+                .keys()
+                .filter(|recipient| match recipient {
+                    SessionParticipant::Participant(pubkey) => session.has_declined(pubkey),
+                    SessionParticipant::Coordinator => false,
+                })
+                .cloned()
+                .collect();
+            for recipient in declined {
+                session.queue.remove(&recipient);
+                self.remove_persisted_queue(&id, &recipient);
+            }
+            // Sessions persisted by older versions include their queues;
+            // save them again so that they're stored separately from now on.
+            self.persist_session(&id, &session);
+            for pubkey in session
+                .pubkeys
+                .iter()
+                .chain(std::iter::once(&session.coordinator_pubkey))
+                .filter(|pubkey| session.is_member(pubkey))
+            {
+                sessions_by_pubkey
+                    .entry(pubkey.clone())
+                    .or_default()
+                    .insert(id);
+            }
+            sessions.insert_at(id, session, remaining);
+        }
+        if !sessions.is_empty() {
+            tracing::info!("restored {} sessions", sessions.len());
+        }
+        // Queues of sessions that were not persisted or could not be read.
+        for id in queues.keys() {
+            self.remove_persisted_session(id);
+        }
+
+        let mut access_tokens = self.access_tokens.write().unwrap();
+        for (token, access_token) in stored.access_tokens {
+            let Ok(remaining) = access_token.expires_at.duration_since(now) else {
+                self.remove_persisted_access_token(&token);
+                continue;
+            };
+            access_tokens.insert_at(token, access_token.pubkey, remaining);
+        }
+
+        let mut revoked_tokens = self.revoked_tokens.write().unwrap();
+        let mut revoked_users = self.revoked_users.write().unwrap();
+        for (id, revocation) in stored.revocations {
+            let Ok(remaining) = revocation.expires_at.duration_since(now) else {
+                self.remove_persisted_revocation(&id);
+                continue;
+            };
+            match revocation.pubkey {
+                Some(pubkey) => {
+                    revoked_users.insert_at(pubkey, (id, revocation.revoked_at), remaining)
+                }
+                None => revoked_tokens.insert_at(id, remaining),
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a session from the given (locked) session maps and from the
+    /// storage backend, waking up any long polling `receive` calls. Returns
+    /// the removed session, if it existed.
+    pub(crate) fn remove_session(
+        &self,
+        sessions: &mut HashMapDelay<Uuid, Session>,
+        sessions_by_pubkey: &mut HashMap<PublicKey, HashSet<Uuid>>,
+        id: &Uuid,
+    ) -> Option<Session> {
+        let session = sessions.remove(id)?;
+        // Wake up any long polling `receive` calls so they can return an error.
+        session.notify.notify_waiters();
+        // Remove session from each participant list, and also from the
+        // coordinator's list (might have been already removed if they are
+        // also a participant)
+        for pubkey in session
+            .pubkeys
+            .iter()
+            .chain(std::iter::once(&session.coordinator_pubkey))
+        {
+            if let Some(v) = sessions_by_pubkey.get_mut(pubkey) {
+                v.remove(id);
+            }
+        }
+        self.remove_persisted_session(id);
+        Some(session)
+    }
+
+    /// Persist a session in the storage backend, including all its message
+    /// queues. Like all changes to the storage backend, this is done in the
+    /// background, and errors are only logged since the in-memory state is
+    /// still valid.
+    pub(crate) fn persist_session(&self, id: &Uuid, session: &Session) {
+        self.persist_session_info(id, session);
+        for recipient in session.queue.keys() {
+            self.persist_queue(id, session, recipient);
+        }
+    }
+
+    /// Persist a session in the storage backend, without its message queues.
+    pub(crate) fn persist_session_info(&self, id: &Uuid, session: &Session) {
+        self.storage
+            .send(StorageOp::SaveSession(*id, Box::new(session.snapshot())));
+    }
+
+    /// Persist the message queue of the given recipient in a session.
+    pub(crate) fn persist_queue(
+        &self,
+        id: &Uuid,
+        session: &Session,
+        recipient: &SessionParticipant,
+    ) {
+        let queue = StoredQueue {
+            recipient: recipient.clone(),
+            msgs: session.queue.get(recipient).cloned().unwrap_or_default(),
+        };
+        self.storage.send(StorageOp::SaveQueue(*id, queue));
+    }
+
+    /// Remove the message queue of the given recipient in a session from the
+    /// storage backend.
+    pub(crate) fn remove_persisted_queue(&self, id: &Uuid, recipient: &SessionParticipant) {
+        self.storage
+            .send(StorageOp::RemoveQueue(*id, recipient.clone()));
+    }
+
+    /// Remove a session from the storage backend.
+    pub(crate) fn remove_persisted_session(&self, id: &Uuid) {
+        self.storage.send(StorageOp::RemoveSession(*id));
+    }
+
+    /// Persist an access token in the storage backend.
+    pub(crate) fn persist_access_token(&self, token: &Uuid, pubkey: &PublicKey) {
+        let access_token = StoredAccessToken {
+            pubkey: pubkey.clone(),
+            expires_at: SystemTime::now() + self.timeouts.access_token,
+        };
+        self.storage
+            .send(StorageOp::SaveAccessToken(*token, access_token));
+    }
+
+    /// Issue an access token to the given user. It is signed if the server
+    /// has a token key, and otherwise tracked in `access_tokens`.
+    pub(crate) fn issue_access_token(&self, pubkey: &PublicKey) -> LoginOutput {
+        let access_token = Uuid::new_v4();
+        let Some(token_key) = &self.token_key else {
+            let mut access_tokens = self.access_tokens.write().unwrap();
+            self.persist_access_token(&access_token, pubkey);
+            access_tokens.insert(access_token, pubkey.clone());
+            return LoginOutput {
+                access_token: Some(access_token),
+                signed_access_token: None,
+            };
+        };
+        let issued_at = SystemTime::now();
+        let signed_access_token = token_key.sign(&TokenClaims {
+            id: access_token,
+            pubkey: pubkey.clone(),
+            issued_at,
+            expires_at: issued_at + self.timeouts.access_token,
+        });
+        LoginOutput {
+            access_token: None,
+            signed_access_token: Some(signed_access_token),
+        }
+    }
+
+    /// Return the claims of the given signed access token if it is valid,
+    /// i.e. if it was signed with the token key of the server and has not
+    /// expired nor been revoked.
+    pub(crate) fn check_signed_token(&self, token: &str) -> Option<TokenClaims> {
+        let claims = self.token_key.as_ref()?.verify(token)?;
+        if claims.expires_at <= SystemTime::now()
+            || self.revoked_tokens.read().unwrap().contains_key(&claims.id)
+        {
+            return None;
+        }
+        let revoked_users = self.revoked_users.read().unwrap();
+        if let Some((_, revoked_at)) = revoked_users.get(&claims.pubkey) {
+            if claims.issued_at <= *revoked_at {
+                return None;
+            }
+        }
+        Some(claims)
+    }
+
+    /// Revoke a single access token. `signed_expires_at` is when the token
+    /// expires if it is signed, or `None` if it is tracked in
+    /// `access_tokens`.
+    pub(crate) fn revoke_access_token(&self, token: &Uuid, signed_expires_at: Option<SystemTime>) {
+        let Some(expires_at) = signed_expires_at else {
+            self.access_tokens.write().unwrap().remove(token);
+            self.remove_persisted_access_token(token);
+            return;
+        };
+        let now = SystemTime::now();
+        let Ok(remaining) = expires_at.duration_since(now) else {
+            // Already expired.
+            return;
+        };
+        let mut revoked_tokens = self.revoked_tokens.write().unwrap();
+        self.persist_revocation(
+            token,
+            StoredRevocation {
+                pubkey: None,
+                revoked_at: now,
+                expires_at,
+            },
+        );
+        revoked_tokens.insert_at(*token, remaining);
+    }
+
+    /// Revoke all access tokens issued to the given user, returning how many
+    /// were revoked. Signed access tokens are revoked too, but they are not
+    /// counted since the server does not keep track of them.
+    pub(crate) fn revoke_access_tokens(&self, pubkey: &PublicKey) -> usize {
+        if self.token_key.is_some() {
+            // Any token issued before now expires after the access token
+            // timeout, at the latest.
+            let id = Uuid::new_v4();
+            let now = SystemTime::now();
+            let mut revoked_users = self.revoked_users.write().unwrap();
+            self.persist_revocation(
+                &id,
+                StoredRevocation {
+                    pubkey: Some(pubkey.clone()),
+                    revoked_at: now,
+                    expires_at: now + self.timeouts.access_token,
+                },
+            );
+            // A newer revocation supersedes the previous one.
+            if let Some((old_id, _)) = revoked_users.get(pubkey) {
+                self.remove_persisted_revocation(old_id);
+            }
+            revoked_users.insert(pubkey.clone(), (id, now));
+        }
+
+        let mut access_tokens = self.access_tokens.write().unwrap();
+        let tokens: Vec<_> = access_tokens
+            .iter()
+            .filter(|(_, p)| *p == pubkey)
+            .map(|(token, _)| *token)
+            .collect();
+        for token in &tokens {
+            access_tokens.remove(token);
+            self.remove_persisted_access_token(token);
+        }
+        tokens.len()
+    }
+
+    /// Remove an access token from the storage backend.
+    pub(crate) fn remove_persisted_access_token(&self, token: &Uuid) {
+        self.storage.send(StorageOp::RemoveAccessToken(*token));
+    }
+
+    /// Persist a revocation of signed access tokens in the storage backend.
+    fn persist_revocation(&self, id: &Uuid, revocation: StoredRevocation) {
+        self.storage
+            .send(StorageOp::SaveRevocation(*id, revocation));
+    }
+
+    /// Remove a revocation of signed access tokens from the storage backend.
+    fn remove_persisted_revocation(&self, id: &Uuid) {
+        self.storage.send(StorageOp::RemoveRevocation(*id));
     }
 }
 
@@ -0,0 +1,512 @@
+//! Storage backends for the server state.
+//!
+//! The server always keeps its working state in memory (see
+//! [`crate::AppState`]). A [`Storage`] backend is notified of every change to
+//! sessions (including their message queues), access tokens and revocations of
+//! signed access tokens, and is used to restore them when the server starts,
+//! allowing in-flight sessions to survive a restart.
+//!
+//! Changes are written by a background thread (see [`StorageWriter`]) so that
+//! request handlers never wait for the backend, in particular while holding
+//! the sessions lock. The message queues of a session are saved separately
+//! from the rest of the session, so that sending or receiving a message only
+//! rewrites the queues it changed.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::{
+    state::{Session, SessionParticipant},
+    Msg, PublicKey,
+};
+
+/// Errors returned by storage backends.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// An access token as persisted by a [`Storage`] backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredAccessToken {
+    /// The public key of the user the token was issued to.
+    pub pubkey: PublicKey,
+    /// When the token expires.
+    pub expires_at: SystemTime,
+}
+
+/// A revocation of signed access tokens (see [`crate::token`]) as persisted
+/// by a [`Storage`] backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredRevocation {
+    /// If set, all the tokens issued to this user up to `revoked_at` are
+    /// revoked. Otherwise, the revoked token is the one with the ID the
+    /// revocation is stored with.
+    pub pubkey: Option<PublicKey>,
+    /// When the tokens were revoked.
+    pub revoked_at: SystemTime,
+    /// When all the revoked tokens have expired, so the revocation can be
+    /// removed.
+    pub expires_at: SystemTime,
+}
+
+/// The message queue of a recipient in a session, as persisted by a
+/// [`Storage`] backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredQueue {
+    /// The recipient of the messages.
+    pub recipient: SessionParticipant,
+    /// The messages not yet acknowledged by the recipient.
+    pub msgs: VecDeque<Msg>,
+}
+
+/// The state loaded from a [`Storage`] backend when the server starts.
+#[derive(Debug, Default)]
+pub struct StoredState {
+    /// The persisted sessions, keyed by session ID.
+    pub sessions: Vec<(Uuid, Session)>,
+    /// The persisted message queues, keyed by the ID of their session.
+    pub queues: Vec<(Uuid, StoredQueue)>,
+    /// The persisted access tokens.
+    pub access_tokens: Vec<(Uuid, StoredAccessToken)>,
+    /// The persisted revocations of signed access tokens.
+    pub revocations: Vec<(Uuid, StoredRevocation)>,
+}
+
+/// A storage backend for the server state.
+///
+/// Implementations must be able to be shared between threads. They are only
+/// called from the [`StorageWriter`] thread, except for [`Storage::load`].
+///
+/// Entries that can't be read should be skipped by [`Storage::load`] rather
+/// than failing it, so that they don't prevent the server from starting.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// Load all persisted state. Called once when the server starts.
+    fn load(&self) -> Result<StoredState, StorageError>;
+
+    /// Insert or update a session. Its message queues are not saved (see
+    /// [`Storage::save_queue`]).
+    fn save_session(&self, id: &Uuid, session: &Session) -> Result<(), StorageError>;
+
+    /// Insert or update the message queue of a recipient in a session.
+    fn save_queue(&self, id: &Uuid, queue: &StoredQueue) -> Result<(), StorageError>;
+
+    /// Remove the message queue of a recipient in a session. Removing a
+    /// queue that does not exist is not an error.
+    fn remove_queue(&self, id: &Uuid, recipient: &SessionParticipant) -> Result<(), StorageError>;
+
+    /// Remove a session and its message queues. Removing a session that does
+    /// not exist is not an error.
+    fn remove_session(&self, id: &Uuid) -> Result<(), StorageError>;
+
+    /// Insert or update an access token.
+    fn save_access_token(
+        &self,
+        token: &Uuid,
+        access_token: &StoredAccessToken,
+    ) -> Result<(), StorageError>;
+
+    /// Remove an access token. Removing a token that does not exist is not an
+    /// error.
+    fn remove_access_token(&self, token: &Uuid) -> Result<(), StorageError>;
+
+    /// Insert or update a revocation of signed access tokens.
+    fn save_revocation(&self, id: &Uuid, revocation: &StoredRevocation)
+        -> Result<(), StorageError>;
+
+    /// Remove a revocation. Removing a revocation that does not exist is not
+    /// an error.
+    fn remove_revocation(&self, id: &Uuid) -> Result<(), StorageError>;
+}
+
+/// A storage backend that does not persist anything. All state is lost when
+/// the server stops. This is the default.
+#[derive(Debug, Default)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> Result<StoredState, StorageError> {
+        Ok(StoredState::default())
+    }
+
+    fn save_session(&self, _id: &Uuid, _session: &Session) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn save_queue(&self, _id: &Uuid, _queue: &StoredQueue) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn remove_queue(
+        &self,
+        _id: &Uuid,
+        _recipient: &SessionParticipant,
+    ) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn remove_session(&self, _id: &Uuid) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn save_access_token(
+        &self,
+        _token: &Uuid,
+        _access_token: &StoredAccessToken,
+    ) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn remove_access_token(&self, _token: &Uuid) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn save_revocation(
+        &self,
+        _id: &Uuid,
+        _revocation: &StoredRevocation,
+    ) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn remove_revocation(&self, _id: &Uuid) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// A storage backend that persists the state as JSON files in a directory,
+/// one file per session, message queue, access token and revocation.
+///
+/// Files are written atomically (by writing to a temporary file and renaming
+/// it) so that a crash never leaves a partially written entry behind. Files
+/// that can't be read anyway when loading are renamed with a `.corrupt`
+/// extension and ignored.
+///
+/// Note that access tokens are stored in clear; the directory should only be
+/// readable by the user running the server.
+#[derive(Debug)]
+pub struct FileStorage {
+    sessions_dir: PathBuf,
+    queues_dir: PathBuf,
+    access_tokens_dir: PathBuf,
+    revocations_dir: PathBuf,
+}
+
+impl FileStorage {
+    /// Create a new FileStorage that will persist the state in the given
+    /// directory, creating it if needed.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let sessions_dir = path.as_ref().join("sessions");
+        let queues_dir = path.as_ref().join("queues");
+        let access_tokens_dir = path.as_ref().join("access_tokens");
+        let revocations_dir = path.as_ref().join("revocations");
+        fs::create_dir_all(&sessions_dir)?;
+        fs::create_dir_all(&queues_dir)?;
+        fs::create_dir_all(&access_tokens_dir)?;
+        fs::create_dir_all(&revocations_dir)?;
+        Ok(Self {
+            sessions_dir,
+            queues_dir,
+            access_tokens_dir,
+            revocations_dir,
+        })
+    }
+
+    fn entry_path(dir: &Path, id: &Uuid) -> PathBuf {
+        dir.join(format!("{id}.json"))
+    }
+
+    /// Return the path of the queue of the given recipient in a session. The
+    /// queues of a session are kept in their own directory so they can be
+    /// removed together.
+    fn queue_path(&self, id: &Uuid, recipient: &SessionParticipant) -> PathBuf {
+        let name = match recipient {
+            SessionParticipant::Coordinator => "coordinator".to_string(),
+            SessionParticipant::Participant(pubkey) => hex::encode(&pubkey.0),
+        };
+        self.queues_dir
+            .join(id.to_string())
+            .join(format!("{name}.json"))
+    }
+
+    fn write_entry<T: Serialize>(dir: &Path, id: &Uuid, value: &T) -> Result<(), StorageError> {
+        Self::write_file(&Self::entry_path(dir, id), value)
+    }
+
+    fn write_file<T: Serialize>(path: &Path, value: &T) -> Result<(), StorageError> {
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(value)?)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Read a file, or return `None` (after moving it out of the way) if it
+    /// can't be read or parsed.
+    fn read_file<T: DeserializeOwned>(path: &Path) -> Option<T> {
+        let result = fs::read(path)
+            .map_err(StorageError::from)
+            .and_then(|data| Ok(serde_json::from_slice(&data)?));
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::error!("ignoring unreadable file {}: {}", path.display(), e);
+                if let Err(e) = fs::rename(path, path.with_extension("json.corrupt")) {
+                    tracing::error!("error renaming {}: {}", path.display(), e);
+                }
+                None
+            }
+        }
+    }
+
+    fn remove_entry(dir: &Path, id: &Uuid) -> Result<(), StorageError> {
+        Self::remove_file(&Self::entry_path(dir, id))
+    }
+
+    fn remove_file(path: &Path) -> Result<(), StorageError> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_entries<T: DeserializeOwned>(dir: &Path) -> Result<Vec<(Uuid, T)>, StorageError> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                // Leftover temporary file from an interrupted write.
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| Uuid::parse_str(s).ok())
+            else {
+                tracing::warn!("ignoring unexpected file {}", path.display());
+                continue;
+            };
+            if let Some(value) = Self::read_file(&path) {
+                entries.push((id, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_queues(&self) -> Result<Vec<(Uuid, StoredQueue)>, StorageError> {
+        let mut queues = Vec::new();
+        for entry in fs::read_dir(&self.queues_dir)? {
+            let path = entry?.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .and_then(|s| Uuid::parse_str(s).ok())
+            else {
+                tracing::warn!("ignoring unexpected file {}", path.display());
+                continue;
+            };
+            for entry in fs::read_dir(&path)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                if let Some(queue) = Self::read_file(&path) {
+                    queues.push((id, queue));
+                }
+            }
+        }
+        Ok(queues)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> Result<StoredState, StorageError> {
+        Ok(StoredState {
+            sessions: Self::read_entries(&self.sessions_dir)?,
+            queues: self.read_queues()?,
+            access_tokens: Self::read_entries(&self.access_tokens_dir)?,
+            revocations: Self::read_entries(&self.revocations_dir)?,
+        })
+    }
+
+    fn save_session(&self, id: &Uuid, session: &Session) -> Result<(), StorageError> {
+        Self::write_entry(&self.sessions_dir, id, session)
+    }
+
+    fn save_queue(&self, id: &Uuid, queue: &StoredQueue) -> Result<(), StorageError> {
+        let path = self.queue_path(id, &queue.recipient);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Self::write_file(&path, queue)
+    }
+
+    fn remove_queue(&self, id: &Uuid, recipient: &SessionParticipant) -> Result<(), StorageError> {
+        Self::remove_file(&self.queue_path(id, recipient))
+    }
+
+    fn remove_session(&self, id: &Uuid) -> Result<(), StorageError> {
+        Self::remove_entry(&self.sessions_dir, id)?;
+        match fs::remove_dir_all(self.queues_dir.join(id.to_string())) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn save_access_token(
+        &self,
+        token: &Uuid,
+        access_token: &StoredAccessToken,
+    ) -> Result<(), StorageError> {
+        Self::write_entry(&self.access_tokens_dir, token, access_token)
+    }
+
+    fn remove_access_token(&self, token: &Uuid) -> Result<(), StorageError> {
+        Self::remove_entry(&self.access_tokens_dir, token)
+    }
+
+    fn save_revocation(
+        &self,
+        id: &Uuid,
+        revocation: &StoredRevocation,
+    ) -> Result<(), StorageError> {
+        Self::write_entry(&self.revocations_dir, id, revocation)
+    }
+
+    fn remove_revocation(&self, id: &Uuid) -> Result<(), StorageError> {
+        Self::remove_entry(&self.revocations_dir, id)
+    }
+}
+
+/// A change to be written to a [`Storage`] backend by the [`StorageWriter`].
+#[derive(Debug)]
+pub(crate) enum StorageOp {
+    SaveSession(Uuid, Box<Session>),
+    SaveQueue(Uuid, StoredQueue),
+    RemoveQueue(Uuid, SessionParticipant),
+    RemoveSession(Uuid),
+    SaveAccessToken(Uuid, StoredAccessToken),
+    RemoveAccessToken(Uuid),
+    SaveRevocation(Uuid, StoredRevocation),
+    RemoveRevocation(Uuid),
+    /// Signals the sender once all the previous changes were written.
+    Flush(oneshot::Sender<()>),
+}
+
+impl StorageOp {
+    /// Write the change to the backend. Errors are logged but otherwise
+    /// ignored, since the in-memory state is still valid.
+    fn run(self, storage: &dyn Storage) {
+        let (result, what, id) = match self {
+            StorageOp::SaveSession(id, session) => (
+                storage.save_session(&id, &session),
+                "persisting session",
+                id,
+            ),
+            StorageOp::SaveQueue(id, queue) => (
+                storage.save_queue(&id, &queue),
+                "persisting queue of session",
+                id,
+            ),
+            StorageOp::RemoveQueue(id, recipient) => (
+                storage.remove_queue(&id, &recipient),
+                "removing persisted queue of session",
+                id,
+            ),
+            StorageOp::RemoveSession(id) => (
+                storage.remove_session(&id),
+                "removing persisted session",
+                id,
+            ),
+            // Don't log the access tokens themselves.
+            StorageOp::SaveAccessToken(token, access_token) => {
+                if let Err(e) = storage.save_access_token(&token, &access_token) {
+                    tracing::error!("error persisting access token: {}", e);
+                }
+                return;
+            }
+            StorageOp::RemoveAccessToken(token) => {
+                if let Err(e) = storage.remove_access_token(&token) {
+                    tracing::error!("error removing persisted access token: {}", e);
+                }
+                return;
+            }
+            StorageOp::SaveRevocation(id, revocation) => (
+                storage.save_revocation(&id, &revocation),
+                "persisting revocation",
+                id,
+            ),
+            StorageOp::RemoveRevocation(id) => (
+                storage.remove_revocation(&id),
+                "removing persisted revocation",
+                id,
+            ),
+            StorageOp::Flush(done) => {
+                let _ = done.send(());
+                return;
+            }
+        };
+        if let Err(e) = result {
+            tracing::error!("error {} {}: {}", what, id, e);
+        }
+    }
+}
+
+/// Writes changes to a [`Storage`] backend in a background thread, in the
+/// order they are made. The thread stops when the writer is dropped, after
+/// writing the pending changes.
+#[derive(Debug)]
+pub(crate) struct StorageWriter {
+    storage: Arc<dyn Storage>,
+    sender: mpsc::UnboundedSender<StorageOp>,
+}
+
+impl StorageWriter {
+    /// Create a new StorageWriter for the given backend, starting its thread.
+    pub(crate) fn new(storage: Box<dyn Storage>) -> std::io::Result<Self> {
+        let storage: Arc<dyn Storage> = storage.into();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<StorageOp>();
+        let backend = storage.clone();
+        std::thread::Builder::new()
+            .name("frostd-storage".into())
+            .spawn(move || {
+                while let Some(op) = receiver.blocking_recv() {
+                    op.run(&*backend);
+                }
+            })?;
+        Ok(Self { storage, sender })
+    }
+
+    /// Load all persisted state from the backend.
+    pub(crate) fn load(&self) -> Result<StoredState, StorageError> {
+        self.storage.load()
+    }
+
+    /// Queue a change to be written to the backend.
+    pub(crate) fn send(&self, op: StorageOp) {
+        // This can't fail since the thread runs until we drop the sender.
+        let _ = self.sender.send(op);
+    }
+
+    /// Wait until all the changes queued so far are written to the backend.
+    pub(crate) async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        self.send(StorageOp::Flush(done));
+        let _ = wait.await;
+    }
+}
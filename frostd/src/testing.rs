@@ -0,0 +1,119 @@
+//! A frostd server running in the same process, for tests.
+//!
+//! It listens on an ephemeral port of the loopback interface using a freshly
+//! generated self-signed certificate, and stops when dropped.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let server = frostd::testing::Server::spawn().await?;
+//! let client = server.client()?;
+//! client.info().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::SocketAddr;
+
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use frost_client::client::Client;
+use rcgen::{generate_simple_self_signed, CertifiedKey};
+
+use crate::{router, AppState, AppStateOptions, SharedState};
+
+/// A running server. It is shut down when dropped.
+pub struct Server {
+    addr: SocketAddr,
+    cert_pem: String,
+    state: SharedState,
+    handle: Handle,
+}
+
+impl Server {
+    /// Spawn a server with the default options.
+    pub async fn spawn() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_options(Default::default()).await
+    }
+
+    /// Spawn a server with the given options.
+    pub async fn with_options(
+        options: AppStateOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // This fails if a provider was already installed, e.g. by another
+        // server, which is fine.
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let subject_alt_names = vec!["127.0.0.1".to_string(), "localhost".to_string()];
+        let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names)?;
+        let tls_config = RustlsConfig::from_pem(
+            cert.pem().into_bytes(),
+            key_pair.serialize_pem().into_bytes(),
+        )
+        .await?;
+
+        // Bind before spawning the server so that it can be connected to
+        // as soon as this returns.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        let state = AppState::with_options(options).await?;
+        let app = router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        let handle = Handle::new();
+        let server = axum_server::from_tcp_rustls(listener, tls_config).handle(handle.clone());
+        tokio::spawn(async move {
+            if let Err(e) = server.serve(app).await {
+                tracing::error!("test server failed: {}", e);
+            }
+        });
+
+        Ok(Self {
+            addr,
+            cert_pem: cert.pem(),
+            state,
+            handle,
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of the server, e.g. `https://127.0.0.1:12345`.
+    pub fn url(&self) -> String {
+        format!("https://{}", self.addr)
+    }
+
+    /// The self-signed certificate of the server (PEM format).
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// The state of the server.
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    /// Create an HTTP client that trusts the certificate of the server.
+    pub fn http_client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        reqwest::Client::builder()
+            // Required for the root certificate to be used; see `test_http`
+            // in the integration tests.
+            .use_rustls_tls()
+            .add_root_certificate(reqwest::Certificate::from_pem(self.cert_pem.as_bytes())?)
+            .build()
+    }
+
+    /// Create a frost-client client connected to the server.
+    pub fn client(&self) -> Result<Client, reqwest::Error> {
+        Ok(Client::with_http_client(self.url(), self.http_client()?))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Wake up long polling calls, and then stop the server.
+        self.state.shutdown();
+        self.handle.shutdown();
+    }
+}
@@ -0,0 +1,307 @@
+//! TLS setup, including the optional authentication of clients with
+//! certificates (mutual TLS).
+//!
+//! The certificate is reloaded when its files change (see
+//! [`reload_on_change`]), and can be generated on startup for development
+//! (see `dev_tls`).
+//!
+//! If a client CA is configured, clients are asked for a certificate issued by
+//! it. The identities in the certificate are added to the extensions of the
+//! requests of the connection (see [`TlsInfo`]), so that the server can check
+//! that users log in with the public key named in their certificate.
+
+use std::{fs, io, net::IpAddr, sync::Arc, time::Duration};
+
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use eyre::{eyre, OptionExt as _};
+use futures::future::BoxFuture;
+use rcgen::{generate_simple_self_signed, CertifiedKey};
+use rustls::{
+    pki_types::{pem::PemObject as _, CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tower_http::add_extension::AddExtension;
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+use crate::{config::Config, server_identity, PublicKey, SharedState};
+
+/// How often the certificate files are checked for changes, to reload them.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The prefix of URI subject alternative names that name a public key, e.g.
+/// `urn:frost:pubkey:1234...`.
+pub const PUBKEY_URI_PREFIX: &str = "urn:frost:pubkey:";
+
+/// Whether clients must authenticate with a certificate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuth {
+    /// Connections without a valid client certificate are rejected.
+    #[default]
+    Required,
+    /// Clients can connect without a certificate, but certificates that are
+    /// sent must be valid.
+    Optional,
+}
+
+/// Information about the TLS connection a request was received in. It is
+/// added to the extensions of the request.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TlsInfo {
+    /// The identities in the client certificate, if the client sent one: the
+    /// common names of its subject and its URI subject alternative names.
+    pub(crate) client_identities: Option<Vec<String>>,
+}
+
+impl TlsInfo {
+    /// Return if the client certificate names the given public key, i.e. if
+    /// one of its identities is the hex-encoded key, optionally prefixed with
+    /// [`PUBKEY_URI_PREFIX`].
+    pub(crate) fn is_bound_to(&self, pubkey: &PublicKey) -> bool {
+        let pubkey = hex::encode(&pubkey.0);
+        self.client_identities.iter().flatten().any(|identity| {
+            let identity = identity.strip_prefix(PUBKEY_URI_PREFIX).unwrap_or(identity);
+            identity.eq_ignore_ascii_case(&pubkey)
+        })
+    }
+}
+
+/// Create the TLS configuration of the server. With `dev_tls`, a self-signed
+/// certificate is generated; otherwise, the certificate and key are read from
+/// `tls_cert` and `tls_key`.
+pub(crate) fn rustls_config(config: &Config) -> Result<RustlsConfig, Box<dyn std::error::Error>> {
+    let server_config = if config.dev_tls {
+        dev_server_config(config)?
+    } else {
+        load_server_config(config)?
+    };
+    Ok(RustlsConfig::from_config(Arc::new(server_config)))
+}
+
+/// Read the certificate and key files, and create a TLS configuration with
+/// them.
+fn load_server_config(config: &Config) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let tls_cert = config
+        .tls_cert
+        .as_ref()
+        .ok_or_eyre("tls-cert argument is required (or dev-tls)")?;
+    let tls_key = config
+        .tls_key
+        .as_ref()
+        .ok_or_eyre("tls-key argument is required")?;
+    let certs = CertificateDer::pem_file_iter(tls_cert)
+        .map_err(|e| eyre!("error reading {}: {}", tls_cert, e))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(tls_key)
+        .map_err(|e| eyre!("error reading {}: {}", tls_key, e))?;
+    server_config(config, certs, key)
+}
+
+/// Create a TLS configuration with the given certificate chain and key, and
+/// the client authentication options of the configuration.
+fn server_config(
+    config: &Config,
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let builder = ServerConfig::builder();
+    let builder = match &config.tls_client_ca {
+        Some(tls_client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca in CertificateDer::pem_file_iter(tls_client_ca)
+                .map_err(|e| eyre!("error reading {}: {}", tls_client_ca, e))?
+            {
+                roots.add(ca?)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = match config.tls_client_auth {
+                ClientAuth::Required => verifier,
+                ClientAuth::Optional => verifier.allow_unauthenticated(),
+            }
+            .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    // The same protocols as `RustlsConfig::from_pem_file`.
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+/// Create a TLS configuration with a self-signed certificate for development,
+/// logging its fingerprint so that clients can check it. It is valid for
+/// localhost, the IP the server binds to and the server names.
+fn dev_server_config(config: &Config) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    if let Ok(ip) = config.ip().parse::<IpAddr>() {
+        if !ip.is_unspecified() {
+            names.push(ip.to_string());
+        }
+    }
+    for server_name in &config.server_names {
+        let identity = server_identity(server_name);
+        let host = identity
+            .rsplit_once(':')
+            .map_or(&*identity, |(host, _)| host);
+        names.push(host.trim_matches(['[', ']']).to_string());
+    }
+    names.sort();
+    names.dedup();
+
+    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(names.clone())?;
+    let fingerprint = Sha256::digest(cert.der())
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":");
+    tracing::warn!(
+        "using a self-signed certificate for development, valid for {}; DO NOT use \
+        it in production. Its SHA-256 fingerprint is {}",
+        names.join(", "),
+        fingerprint,
+    );
+    server_config(
+        config,
+        vec![cert.der().clone()],
+        PrivatePkcs8KeyDer::from(key_pair.serialize_der()).into(),
+    )
+}
+
+/// Reload the certificate and key (and the client CA, if any) when their
+/// files change or SIGHUP is received, so that renewed certificates are used
+/// without restarting the server. Runs until the server shuts down.
+///
+/// If the new files can't be loaded (e.g. because only one of them was
+/// written so far), the current certificate is kept.
+pub(crate) async fn reload_on_change(config: Config, tls_config: RustlsConfig, state: SharedState) {
+    let paths: Vec<&String> = [&config.tls_cert, &config.tls_key, &config.tls_client_ca]
+        .into_iter()
+        .flatten()
+        .collect();
+    let modified = || {
+        paths
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>()
+    };
+    let mut last_modified = modified();
+    let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+    #[cfg(unix)]
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            tracing::error!("error listening for SIGHUP: {}", e);
+            None
+        }
+    };
+
+    loop {
+        let hangup_received = async {
+            #[cfg(unix)]
+            if let Some(hangup) = &mut hangup {
+                hangup.recv().await;
+                return;
+            }
+            std::future::pending::<()>().await
+        };
+        tokio::select! {
+            _ = state.wait_for_shutdown() => break,
+            _ = hangup_received => {
+                tracing::info!("received SIGHUP, reloading the TLS certificate");
+            }
+            _ = interval.tick() => {
+                if modified() == last_modified {
+                    continue;
+                }
+                tracing::info!("TLS certificate files changed, reloading them");
+            }
+        }
+        last_modified = modified();
+        match load_server_config(&config) {
+            Ok(server_config) => {
+                tls_config.reload_from_config(Arc::new(server_config));
+                tracing::info!("reloaded the TLS certificate");
+            }
+            Err(e) => {
+                tracing::error!("error reloading the TLS certificate: {}", e);
+            }
+        }
+    }
+}
+
+/// Return the identities in the given client certificate (see
+/// [`TlsInfo::client_identities`]).
+fn client_identities(cert: &CertificateDer) -> Vec<String> {
+    let Ok((_, cert)) = parse_x509_certificate(cert.as_ref()) else {
+        // It was already validated by rustls, so this should not happen.
+        return Vec::new();
+    };
+    let common_names = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string);
+    let uris: Vec<_> = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .into_iter()
+        .flat_map(|san| san.value.general_names.iter())
+        .filter_map(|name| match name {
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+        .collect();
+    common_names.chain(uris).collect()
+}
+
+/// Accepts TLS connections, adding a [`TlsInfo`] to the extensions of their
+/// requests.
+#[derive(Clone, Debug)]
+pub(crate) struct TlsAcceptor(RustlsAcceptor);
+
+impl TlsAcceptor {
+    /// Create an acceptor with the given TLS configuration.
+    pub(crate) fn new(config: RustlsConfig) -> Self {
+        Self(RustlsAcceptor::new(config))
+    }
+}
+
+impl<I, S> Accept<I, S> for TlsAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = AddExtension<S, TlsInfo>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.0.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let (_, connection) = stream.get_ref();
+            let info = TlsInfo {
+                client_identities: connection
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .map(client_identities),
+            };
+            Ok((stream, AddExtension::new(service, info)))
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! Signed access tokens.
+//!
+//! By default, access tokens are random UUIDs that the server keeps track of
+//! (see [`crate::AppState`]), so they are only valid in the server that issued
+//! them, and only while it keeps them in memory or in its storage backend.
+//!
+//! If a [`TokenKey`] is configured, the server instead issues self-contained
+//! tokens with the public key of the user, an ID, and when they were issued
+//! and expire, authenticated with HMAC-SHA256. They can be validated without
+//! any state, including by other replicas configured with the same key.
+//! Since they can't be forgotten by the server, they are revoked by adding
+//! them to a revocation list until they expire. The list is not shared, so
+//! other replicas keep accepting revoked tokens.
+
+use std::{
+    fmt, fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::eyre;
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+use uuid::Uuid;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::PublicKey;
+
+/// The version of the token format, which is the first byte of the tokens.
+const TOKEN_VERSION: u8 = 1;
+/// The minimum length of a [`TokenKey`], in bytes.
+const MIN_KEY_LEN: usize = 32;
+/// The length of the HMAC-SHA256 tag at the end of tokens.
+const TAG_LEN: usize = 32;
+
+/// The secret key used to authenticate signed access tokens.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct TokenKey(Vec<u8>);
+
+impl fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TokenKey(..)")
+    }
+}
+
+impl TokenKey {
+    /// Create a key from its hex encoding. It must have at least 32 bytes.
+    pub fn from_hex(key: &str) -> Result<Self, eyre::Report> {
+        let key = hex::decode(key.trim()).map_err(|e| eyre!("invalid token key: {e}"))?;
+        if key.len() < MIN_KEY_LEN {
+            return Err(eyre!(
+                "the token key must have at least {MIN_KEY_LEN} bytes"
+            ));
+        }
+        Ok(Self(key))
+    }
+
+    /// Read a hex-encoded key from a file, which can be created with e.g.
+    /// `openssl rand -hex 32`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, eyre::Report> {
+        let key = fs::read_to_string(&path)
+            .map_err(|e| eyre!("error reading {}: {}", path.as_ref().display(), e))?;
+        Self::from_hex(&key)
+    }
+
+    /// Create a token with the given claims.
+    pub(crate) fn sign(&self, claims: &TokenClaims) -> String {
+        let mut token = vec![TOKEN_VERSION];
+        token.extend_from_slice(claims.id.as_bytes());
+        token.extend_from_slice(&micros(claims.issued_at).to_be_bytes());
+        token.extend_from_slice(&micros(claims.expires_at).to_be_bytes());
+        token.extend_from_slice(&claims.pubkey.0);
+        let tag = self.mac(&token).finalize().into_bytes();
+        token.extend_from_slice(&tag);
+        hex::encode(token)
+    }
+
+    /// Return the claims of the given token, if it is well-formed and was
+    /// created with this key. Note that its expiration is not checked.
+    pub(crate) fn verify(&self, token: &str) -> Option<TokenClaims> {
+        let token = hex::decode(token).ok()?;
+        let (payload, tag) = token.split_at_checked(token.len().checked_sub(TAG_LEN)?)?;
+        self.mac(payload).verify_slice(tag).ok()?;
+
+        let (&version, payload) = payload.split_first()?;
+        if version != TOKEN_VERSION {
+            return None;
+        }
+        let (id, payload) = payload.split_first_chunk::<16>()?;
+        let (issued_at, payload) = payload.split_first_chunk::<8>()?;
+        let (expires_at, pubkey) = payload.split_first_chunk::<8>()?;
+        Some(TokenClaims {
+            id: Uuid::from_bytes(*id),
+            pubkey: PublicKey(pubkey.to_vec()),
+            issued_at: UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(*issued_at)),
+            expires_at: UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(*expires_at)),
+        })
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Return the number of microseconds since the UNIX epoch of the given time.
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// The contents of a signed access token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TokenClaims {
+    /// The ID of the token, used to revoke it.
+    pub(crate) id: Uuid,
+    /// The public key of the user the token was issued to.
+    pub(crate) pubkey: PublicKey,
+    /// When the token was issued. Times are rounded down to microseconds.
+    pub(crate) issued_at: SystemTime,
+    /// When the token expires.
+    pub(crate) expires_at: SystemTime,
+}
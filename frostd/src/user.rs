@@ -1,10 +1,11 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::SystemTime};
 
 use axum::{extract::FromRequestParts, http::request::Parts, RequestPartsExt};
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
+use sha2::{Digest as _, Sha256};
 use uuid::Uuid;
 
 use crate::{functions::IntoResponseError, state::SharedState, Error, PublicKey};
@@ -14,7 +15,23 @@ use crate::{functions::IntoResponseError, state::SharedState, Error, PublicKey};
 #[allow(dead_code)]
 pub(crate) struct User {
     pub(crate) pubkey: PublicKey,
+    /// The access token used in the request or, if it is signed, its ID.
     pub(crate) current_token: Uuid,
+    /// When the access token expires, if it is signed.
+    pub(crate) signed_token_expires_at: Option<SystemTime>,
+}
+
+impl User {
+    /// A short hash of the user's public key; see [`pubkey_hash()`].
+    pub(crate) fn pubkey_hash(&self) -> String {
+        pubkey_hash(&self.pubkey)
+    }
+}
+
+/// A short hash of a public key, used to identify users in the logs (and
+/// correlate their requests) without logging their keys.
+pub(crate) fn pubkey_hash(pubkey: &PublicKey) -> String {
+    hex::encode(&Sha256::digest(&pubkey.0)[..8])
 }
 
 /// Read a User from a request. This is used to authenticate users. If any axum
@@ -33,8 +50,18 @@ impl FromRequestParts<SharedState> for User {
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| Error::Unauthorized)?;
-        // Decode the user data
-        let access_token = Uuid::from_str(bearer.token()).map_err(|_| Error::Unauthorized)?;
+        // Decode the user data. Tokens are either UUIDs issued by this
+        // server, or signed tokens (see `crate::token`).
+        let Ok(access_token) = Uuid::from_str(bearer.token()) else {
+            let claims = state
+                .check_signed_token(bearer.token())
+                .ok_or(Error::Unauthorized)?;
+            return Ok(User {
+                pubkey: claims.pubkey,
+                current_token: claims.id,
+                signed_token_expires_at: Some(claims.expires_at),
+            });
+        };
 
         let pubkey = state
             .access_tokens
@@ -47,9 +74,31 @@ impl FromRequestParts<SharedState> for User {
             Ok(User {
                 pubkey,
                 current_token: access_token,
+                signed_token_expires_at: None,
             })
         } else {
             return Err(Error::Unauthorized.into());
         }
     }
 }
+
+/// An authenticated administrator. If any axum handler has an Admin argument,
+/// the user will be authenticated and checked to be an administrator.
+#[derive(Debug)]
+pub(crate) struct Admin(pub(crate) User);
+
+impl FromRequestParts<SharedState> for Admin {
+    type Rejection = IntoResponseError;
+
+    #[tracing::instrument(err(Debug), skip(parts, state))]
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = User::from_request_parts(parts, state).await?;
+        if !state.users.is_admin(&user.pubkey) {
+            return Err(Error::NotAdmin.into());
+        }
+        Ok(Admin(user))
+    }
+}
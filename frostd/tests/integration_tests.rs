@@ -11,9 +11,24 @@ use reqwest::Certificate;
 use uuid::Uuid;
 use xeddsa::{xed25519, Sign, Verify};
 
+use clap::Parser as _;
 use frost_client::{cipher::Cipher, session::CoordinatorSessionState};
 use frost_core as frost;
-use frostd::{args::Args, router, AppState, SendSigningPackageArgs};
+use frostd::{
+    args::Args,
+    config::{Config, Timeouts},
+    limits::{IpRange, Limits},
+    metrics_router,
+    registry::UserRegistry,
+    router,
+    storage::FileStorage,
+    token::TokenKey,
+    AppState, AppStateOptions, SendSigningPackageArgs,
+};
+
+/// The identity of servers created with `TestServer`, which sends requests to
+/// `http://localhost`. Users sign it when logging in.
+const TEST_SERVER_IDENTITY: &str = "localhost:443";
 
 #[tokio::test]
 async fn test_main_router_ed25519() -> Result<(), Box<dyn std::error::Error>> {
@@ -75,7 +90,10 @@ async fn test_main_router<
     let r: frostd::ChallengeOutput = res.json();
     let bob_challenge = r.challenge;
 
-    let alice_signature: [u8; 64] = alice_privkey.sign(alice_challenge.as_bytes(), &mut rng)?;
+    let alice_signature: [u8; 64] = alice_privkey.sign(
+        &frostd::login_payload(TEST_SERVER_IDENTITY, &alice_challenge),
+        &mut rng,
+    )?;
     let res = server
         .post("/login")
         .json(&frostd::LoginArgs {
@@ -86,9 +104,12 @@ async fn test_main_router<
         .await;
     res.assert_status_ok();
     let r: frostd::LoginOutput = res.json();
-    let alice_token = r.access_token;
+    let alice_token = r.access_token.unwrap();
 
-    let bob_signature: [u8; 64] = bob_privkey.sign(bob_challenge.as_bytes(), &mut rng)?;
+    let bob_signature: [u8; 64] = bob_privkey.sign(
+        &frostd::login_payload(TEST_SERVER_IDENTITY, &bob_challenge),
+        &mut rng,
+    )?;
     let res = server
         .post("/login")
         .json(&frostd::LoginArgs {
@@ -99,7 +120,7 @@ async fn test_main_router<
         .await;
     res.assert_status_ok();
     let r: frostd::LoginOutput = res.json();
-    let bob_token = r.access_token;
+    let bob_token = r.access_token.unwrap();
     let tokens = [alice_token, bob_token];
 
     // As the coordinator, create a new signing session with all participants,
@@ -110,6 +131,7 @@ async fn test_main_router<
         .json(&frostd::CreateNewSessionArgs {
             pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
             message_count: 2,
+            metadata: Default::default(),
         })
         .await;
     res.assert_status_ok();
@@ -156,6 +178,7 @@ async fn test_main_router<
                 // Empty recipients: Coordinator
                 recipients: vec![],
                 msg: serde_json::to_vec(&commitments_vec)?,
+                msg_id: None,
             })
             .await;
         if res.status_code() != 200 {
@@ -178,6 +201,8 @@ async fn test_main_router<
             .json(&frostd::ReceiveArgs {
                 session_id,
                 as_coordinator: true,
+                wait_timeout_ms: None,
+                after_seq: None,
             })
             .await;
         res.assert_status_ok();
@@ -230,6 +255,7 @@ async fn test_main_router<
             session_id,
             recipients: usernames.keys().cloned().collect(),
             msg: serde_json::to_vec(&send_signing_package_args)?,
+            msg_id: None,
         })
         .await;
     res.assert_status_ok();
@@ -247,6 +273,8 @@ async fn test_main_router<
                 .json(&frostd::ReceiveArgs {
                     session_id,
                     as_coordinator: false,
+                    wait_timeout_ms: None,
+                    after_seq: None,
                 })
                 .await
                 .json::<frostd::ReceiveOutput>();
@@ -291,6 +319,7 @@ async fn test_main_router<
                 // Empty recipients: Coordinator
                 recipients: vec![],
                 msg: serde_json::to_vec(&signature_shares)?,
+                msg_id: None,
             })
             .await;
         res.assert_status_ok();
@@ -304,6 +333,8 @@ async fn test_main_router<
             .json(&frostd::ReceiveArgs {
                 session_id,
                 as_coordinator: true,
+                wait_timeout_ms: None,
+                after_seq: None,
             })
             .await
             .json::<frostd::ReceiveOutput>();
@@ -369,6 +400,7 @@ async fn test_main_router<
         .json(&frostd::CreateNewSessionArgs {
             pubkeys: vec![alice_pubkey.clone()],
             message_count: 2,
+            metadata: Default::default(),
         })
         .await;
     res.assert_status_ok();
@@ -383,9 +415,10 @@ async fn test_main_router<
             session_id,
             recipients: vec![bob_pubkey.clone()],
             msg: vec![],
+            msg_id: None,
         })
         .await;
-    res.assert_status_internal_server_error();
+    res.assert_status_forbidden();
     let r: frostd::LowError = res.json();
     assert_eq!(r.code, frostd::NOT_IN_SESSION);
 
@@ -397,9 +430,10 @@ async fn test_main_router<
             session_id,
             recipients: vec![alice_pubkey.clone()],
             msg: vec![],
+            msg_id: None,
         })
         .await;
-    res.assert_status_internal_server_error();
+    res.assert_status_forbidden();
     let r: frostd::LowError = res.json();
     assert_eq!(r.code, frostd::NOT_IN_SESSION);
 
@@ -411,11 +445,13 @@ async fn test_main_router<
             session_id,
             recipients: vec![alice_pubkey.clone()],
             msg: [0; frostd::MAX_MSG_SIZE + 1].to_vec(),
+            msg_id: None,
         })
         .await;
-    res.assert_status_internal_server_error();
+    res.assert_status(axum::http::StatusCode::PAYLOAD_TOO_LARGE);
     let r: frostd::LowError = res.json();
-    assert_eq!(r.code, frostd::INVALID_ARGUMENT);
+    assert_eq!(r.code, frostd::TOO_LARGE);
+    assert_eq!(r.details.field.as_deref(), Some("msg"));
 
     Ok(())
 }
@@ -442,7 +478,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
 
     // Spawn server for testing
     tokio::spawn(async move {
-        frostd::run(&Args {
+        frostd::run(&Config {
             ip: "127.0.0.1".to_string(),
             port: 2744,
             tls_cert: Some(
@@ -462,6 +498,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
                     .to_string(),
             ),
             no_tls_very_insecure: false,
+            ..Default::default()
         })
         .await
         .unwrap();
@@ -498,7 +535,10 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
     let alice_challenge = r.challenge;
 
     // Call key_login to authenticate
-    let alice_signature: [u8; 64] = alice_privkey.sign(alice_challenge.as_bytes(), &mut rng)?;
+    let alice_signature: [u8; 64] = alice_privkey.sign(
+        &frostd::login_payload("127.0.0.1:2744", &alice_challenge),
+        &mut rng,
+    )?;
     let r = client
         .post("https://127.0.0.1:2744/login")
         .json(&frostd::LoginArgs {
@@ -512,7 +552,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         panic!("{:?}", r.json::<frostd::LowError>().await?)
     }
     let r = r.json::<frostd::LoginOutput>().await?;
-    let access_token = r.access_token;
+    let access_token = r.access_token.unwrap();
 
     // Call create_new_session
     let r = client
@@ -521,6 +561,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         .json(&frostd::CreateNewSessionArgs {
             pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
             message_count: 1,
+            metadata: Default::default(),
         })
         .send()
         .await?;
@@ -543,7 +584,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         })
         .send()
         .await?;
-    assert_eq!(r.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
     let r = r.json::<frostd::LowError>().await?;
     assert_eq!(r.code, frostd::SESSION_NOT_FOUND);
 
@@ -556,7 +597,10 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     let r = r.json::<frostd::ChallengeOutput>().await?;
     let bob_challenge = r.challenge;
-    let bob_signature: [u8; 64] = bob_privkey.sign(bob_challenge.as_bytes(), &mut rng)?;
+    let bob_signature: [u8; 64] = bob_privkey.sign(
+        &frostd::login_payload("127.0.0.1:2744", &bob_challenge),
+        &mut rng,
+    )?;
     let r = client
         .post("https://127.0.0.1:2744/login")
         .json(&frostd::LoginArgs {
@@ -567,7 +611,7 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         .send()
         .await?;
     let r = r.json::<frostd::LoginOutput>().await?;
-    let bob_access_token = r.access_token;
+    let bob_access_token = r.access_token.unwrap();
     // Try to close the session
     let r = client
         .post("https://127.0.0.1:2744/close_session")
@@ -575,13 +619,2410 @@ async fn test_http() -> Result<(), Box<dyn std::error::Error>> {
         .json(&frostd::CloseSessionArgs { session_id })
         .send()
         .await?;
-    assert_eq!(r.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(r.status(), reqwest::StatusCode::FORBIDDEN);
     let r = r.json::<frostd::LowError>().await?;
     assert_eq!(r.code, frostd::NOT_COORDINATOR);
 
     Ok(())
 }
 
+/// Test if clients must authenticate with a certificate issued by the client
+/// CA, and can only log in with the public key named in it.
+#[tokio::test]
+async fn test_client_cert() -> Result<(), Box<dyn std::error::Error>> {
+    use frost_client::client::Client;
+    use rcgen::{
+        generate_simple_self_signed, BasicConstraints, CertificateParams, CertifiedKey, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+
+    let CertifiedKey { cert, key_pair } =
+        generate_simple_self_signed(vec!["127.0.0.1".to_string()])?;
+    let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate()?;
+    let ca_cert = ca_params.self_signed(&ca_key)?;
+    // Alice's certificate names her public key.
+    let mut alice_params = CertificateParams::new(Vec::<String>::new())?;
+    alice_params
+        .distinguished_name
+        .push(DnType::CommonName, hex::encode(&alice_pubkey.0));
+    alice_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let alice_key = KeyPair::generate()?;
+    let alice_cert = alice_params.signed_by(&alice_key, &ca_cert, &ca_key)?;
+
+    let temp_dir = tempfile::tempdir()?;
+    let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+    std::fs::write(path("cert.pem"), cert.pem())?;
+    std::fs::write(path("cert.key.pem"), key_pair.serialize_pem())?;
+    std::fs::write(path("ca.pem"), ca_cert.pem())?;
+    let config = Config {
+        ip: "127.0.0.1".to_string(),
+        port: 2745,
+        tls_cert: Some(path("cert.pem")),
+        tls_key: Some(path("cert.key.pem")),
+        tls_client_ca: Some(path("ca.pem")),
+        tls_client_cert_binding: true,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        frostd::run(&config).await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let http_client = |identity: Option<reqwest::Identity>| {
+        let builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(Certificate::from_pem(cert.pem().as_bytes())?);
+        match identity {
+            Some(identity) => builder.identity(identity),
+            None => builder,
+        }
+        .build()
+    };
+    let alice_identity = || {
+        reqwest::Identity::from_pem(
+            format!("{}{}", alice_cert.pem(), alice_key.serialize_pem()).as_bytes(),
+        )
+    };
+    async fn login(
+        client: &mut Client,
+        privkey: &frost_client::cipher::PrivateKey,
+        pubkey: &frostd::PublicKey,
+    ) -> Result<frostd::LoginOutput, frost_client::client::Error> {
+        let challenge = client.challenge().await?.challenge;
+        let payload = client.login_payload(&challenge).await?;
+        let signature: [u8; 64] = privkey.sign(&payload, thread_rng()).unwrap();
+        client
+            .login(&frostd::LoginArgs {
+                challenge,
+                pubkey: pubkey.clone(),
+                signature: signature.to_vec(),
+            })
+            .await
+    }
+
+    // Clients without a certificate can't connect.
+    let client = Client::with_http_client("https://127.0.0.1:2745".to_string(), http_client(None)?);
+    assert!(client.info().await.is_err());
+
+    // Alice can log in with her certificate, but Bob can't use it.
+    let mut client = Client::with_http_client(
+        "https://127.0.0.1:2745".to_string(),
+        http_client(Some(alice_identity()?))?,
+    );
+    login(&mut client, &alice_privkey, &alice_pubkey).await?;
+    client.list_sessions().await?;
+    let mut client = Client::with_http_client(
+        "https://127.0.0.1:2745".to_string(),
+        http_client(Some(alice_identity()?))?,
+    );
+    let err = login(&mut client, &bob_privkey, &bob_pubkey)
+        .await
+        .expect_err("certificate does not name Bob");
+    assert_eq!(err.server_error(), Some(&frostd::Error::Unauthorized));
+
+    Ok(())
+}
+
+/// Test if the server can run with a generated self-signed certificate.
+#[tokio::test]
+async fn test_dev_tls() -> Result<(), Box<dyn std::error::Error>> {
+    tokio::spawn(async move {
+        frostd::run(&Config {
+            ip: "127.0.0.1".to_string(),
+            port: 2746,
+            dev_tls: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    // The certificate is not trusted, so its fingerprint would have to be
+    // checked by the client.
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let r = client.post("https://127.0.0.1:2746/info").send().await?;
+    assert_eq!(r.status(), reqwest::StatusCode::OK);
+
+    Ok(())
+}
+
+/// Test if the certificate is reloaded on SIGHUP.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_tls_reload() -> Result<(), Box<dyn std::error::Error>> {
+    use rcgen::{generate_simple_self_signed, CertifiedKey};
+
+    let temp_dir = tempfile::tempdir()?;
+    let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+    let write_cert = || -> Result<String, Box<dyn std::error::Error>> {
+        let CertifiedKey { cert, key_pair } =
+            generate_simple_self_signed(vec!["127.0.0.1".to_string()])?;
+        std::fs::write(path("cert.pem"), cert.pem())?;
+        std::fs::write(path("cert.key.pem"), key_pair.serialize_pem())?;
+        Ok(cert.pem())
+    };
+    // Check if a client that only trusts the given certificate can connect.
+    let connects = |cert_pem: String| async move {
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(Certificate::from_pem(cert_pem.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        client
+            .post("https://127.0.0.1:2747/info")
+            .send()
+            .await
+            .is_ok()
+    };
+
+    let old_cert = write_cert()?;
+    let config = Config {
+        ip: "127.0.0.1".to_string(),
+        port: 2747,
+        tls_cert: Some(path("cert.pem")),
+        tls_key: Some(path("cert.key.pem")),
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        frostd::run(&config).await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(connects(old_cert.clone()).await);
+
+    let new_cert = write_cert()?;
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()?;
+    assert!(status.success());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(connects(new_cert).await);
+    assert!(!connects(old_cert).await);
+
+    Ok(())
+}
+
+/// Test if the server can listen on a Unix domain socket, and the client
+/// connect to it.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use frost_client::client::Client;
+
+    let temp_dir = tempfile::tempdir()?;
+    let socket_path = temp_dir.path().join("frostd.sock");
+    // A stale socket of a previous run is replaced.
+    drop(std::os::unix::net::UnixListener::bind(&socket_path)?);
+
+    let config = Config {
+        unix_socket: Some(socket_path.to_str().unwrap().to_string()),
+        unix_socket_mode: "600".to_string(),
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        frostd::run(&config).await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(
+        std::fs::metadata(&socket_path)?.permissions().mode() & 0o777,
+        0o600
+    );
+
+    let (privkey, pubkey) = Cipher::generate_keypair()?;
+    let mut client =
+        Client::with_unix_socket("https://frost.example.com".to_string(), &socket_path);
+    assert_eq!(client.api_version().await?, Some("v1"));
+    let challenge = client.challenge().await?.challenge;
+    let payload = client.login_payload(&challenge).await?;
+    let signature: [u8; 64] = privkey.sign(&payload, thread_rng()).unwrap();
+    client
+        .login(&frostd::LoginArgs {
+            challenge,
+            pubkey,
+            signature: signature.to_vec(),
+        })
+        .await?;
+    let r = client.list_sessions().await?;
+    assert!(r.session_ids.is_empty());
+
+    // Server errors are returned as such.
+    let err = client
+        .get_session_info(&frostd::GetSessionInfoArgs {
+            session_id: Uuid::new_v4(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.server_error(), Some(&frostd::Error::SessionNotFound));
+
+    Ok(())
+}
+
+/// Test if sessions, their queues and access tokens survive a server restart
+/// when using a persistent storage backend.
+#[tokio::test]
+async fn test_storage() -> Result<(), Box<dyn std::error::Error>> {
+    let mut rng = thread_rng();
+    let temp_dir = tempfile::tempdir()?;
+
+    let shared_state = AppState::with_options(AppStateOptions {
+        storage: Box::new(FileStorage::new(temp_dir.path())?),
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state.clone()))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let res = server.post("/challenge").await;
+    res.assert_status_ok();
+    let r: frostd::ChallengeOutput = res.json();
+    let alice_signature: [u8; 64] = alice_privkey.sign(
+        &frostd::login_payload(TEST_SERVER_IDENTITY, &r.challenge),
+        &mut rng,
+    )?;
+    let res = server
+        .post("/login")
+        .json(&frostd::LoginArgs {
+            challenge: r.challenge,
+            pubkey: alice_pubkey.clone(),
+            signature: alice_signature.to_vec(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+    let bob_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![alice_pubkey.clone()],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+
+    // Bob declines after a message was queued for him, which discards it.
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![bob_pubkey.clone()],
+            msg: b"hello bob".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let res = server
+        .post("/decline_session")
+        .authorization_bearer(bob_token)
+        .json(&frostd::DeclineSessionArgs {
+            session_id,
+            reason: None,
+        })
+        .await;
+    res.assert_status_ok();
+
+    // "Restart" the server by creating a new state from the same directory.
+    // Changes are persisted in the background, so wait for them first.
+    shared_state.flush_storage().await;
+    drop(server);
+    // A corrupt entry must not prevent the server from starting.
+    let corrupt_path = temp_dir
+        .path()
+        .join("sessions")
+        .join(format!("{}.json", Uuid::new_v4()));
+    std::fs::write(&corrupt_path, b"{\"pubkeys\":")?;
+    let shared_state = AppState::with_options(AppStateOptions {
+        storage: Box::new(FileStorage::new(temp_dir.path())?),
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    // The access token, the session and the queued message must still exist.
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(alice_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ListSessionsOutput = res.json();
+    assert_eq!(r.session_ids, vec![session_id]);
+
+    let res = server
+        .post("/receive")
+        .authorization_bearer(alice_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].msg, b"hello");
+    assert_eq!(r.msgs[0].sender, alice_pubkey);
+
+    // Bob still declined the session: it's not listed for him, and his
+    // discarded queue was not restored.
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(bob_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ListSessionsOutput = res.json();
+    assert!(r.session_ids.is_empty());
+    let queues_dir = temp_dir.path().join("queues").join(session_id.to_string());
+    assert!(!queues_dir
+        .join(format!("{}.json", hex::encode(&bob_pubkey.0)))
+        .exists());
+
+    // The corrupt entry was moved out of the way.
+    assert!(!corrupt_path.exists());
+    assert!(corrupt_path.with_extension("json.corrupt").exists());
+
+    Ok(())
+}
+
+/// Test if a long polling `receive` call returns as soon as a message is sent,
+/// and returns nothing after the timeout if no message is sent.
+#[tokio::test]
+async fn test_long_polling() -> Result<(), Box<dyn std::error::Error>> {
+    let mut rng = thread_rng();
+
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let res = server.post("/challenge").await;
+    res.assert_status_ok();
+    let r: frostd::ChallengeOutput = res.json();
+    let alice_signature: [u8; 64] = alice_privkey.sign(
+        &frostd::login_payload(TEST_SERVER_IDENTITY, &r.challenge),
+        &mut rng,
+    )?;
+    let res = server
+        .post("/login")
+        .json(&frostd::LoginArgs {
+            challenge: r.challenge,
+            pubkey: alice_pubkey.clone(),
+            signature: alice_signature.to_vec(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    // Nothing is sent; the call must return empty after the timeout.
+    let start = std::time::Instant::now();
+    let res = server
+        .post("/receive")
+        .authorization_bearer(alice_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: Some(500),
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert!(r.msgs.is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(500));
+
+    // Send a message while a long polling call is waiting; it must return
+    // the message well before the timeout.
+    let start = std::time::Instant::now();
+    let receive = async {
+        server
+            .post("/receive")
+            .authorization_bearer(alice_token)
+            .json(&frostd::ReceiveArgs {
+                session_id,
+                as_coordinator: false,
+                wait_timeout_ms: Some(20_000),
+                after_seq: None,
+            })
+            .await
+    };
+    let send = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server
+            .post("/send")
+            .authorization_bearer(alice_token)
+            .json(&frostd::SendArgs {
+                session_id,
+                recipients: vec![alice_pubkey.clone()],
+                msg: b"hello".to_vec(),
+                msg_id: None,
+            })
+            .await
+    };
+    let (res, send_res) = tokio::join!(receive, send);
+    send_res.assert_status_ok();
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].msg, b"hello");
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    Ok(())
+}
+
+/// Test if messages received with a cursor are kept until acknowledged, so
+/// that they are not lost if a response is lost.
+#[tokio::test]
+async fn test_acknowledgements() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    for msg in [b"one", b"two"] {
+        let res = server
+            .post("/send")
+            .authorization_bearer(alice_token)
+            .json(&frostd::SendArgs {
+                session_id,
+                recipients: vec![alice_pubkey.clone()],
+                msg: msg.to_vec(),
+                msg_id: None,
+            })
+            .await;
+        res.assert_status_ok();
+    }
+
+    let receive = |after_seq| {
+        server
+            .post("/receive")
+            .authorization_bearer(alice_token)
+            .json(&frostd::ReceiveArgs {
+                session_id,
+                as_coordinator: false,
+                wait_timeout_ms: None,
+                after_seq,
+            })
+    };
+
+    // Receiving twice with the same cursor (e.g. if the first response was
+    // lost) must return the same messages.
+    for _ in 0..2 {
+        let res = receive(Some(0)).await;
+        res.assert_status_ok();
+        let r: frostd::ReceiveOutput = res.json();
+        assert_eq!(r.msgs.len(), 2);
+        assert_eq!(r.msgs[0].msg, b"one");
+        assert_eq!(r.msgs[0].seq, 1);
+        assert_eq!(r.msgs[1].msg, b"two");
+        assert_eq!(r.msgs[1].seq, 2);
+    }
+
+    // Advancing the cursor acknowledges the previous messages.
+    let res = receive(Some(1)).await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].seq, 2);
+
+    // Acknowledge the last message explicitly.
+    let res = server
+        .post("/ack")
+        .authorization_bearer(alice_token)
+        .json(&frostd::AckArgs {
+            session_id,
+            as_coordinator: false,
+            seq: 2,
+        })
+        .await;
+    res.assert_status_ok();
+    let res = receive(None).await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert!(r.msgs.is_empty());
+
+    // Sequence numbers keep increasing after the queue is emptied.
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![alice_pubkey.clone()],
+            msg: b"three".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let res = receive(Some(2)).await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].seq, 3);
+
+    Ok(())
+}
+
+/// Test if retried `send` calls with the same message ID are ignored.
+#[tokio::test]
+async fn test_idempotent_send() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    let msg_id = Uuid::new_v4();
+    for (msg, msg_id) in [(b"one", msg_id), (b"one", msg_id), (b"two", Uuid::new_v4())] {
+        let res = server
+            .post("/send")
+            .authorization_bearer(alice_token)
+            .json(&frostd::SendArgs {
+                session_id,
+                recipients: vec![alice_pubkey.clone()],
+                msg: msg.to_vec(),
+                msg_id: Some(msg_id),
+            })
+            .await;
+        res.assert_status_ok();
+    }
+
+    let res = server
+        .post("/receive")
+        .authorization_bearer(alice_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 2);
+    assert_eq!(r.msgs[0].msg, b"one");
+    assert_eq!(r.msgs[1].msg, b"two");
+
+    Ok(())
+}
+
+/// Test if the session info reports the status of the participants.
+#[tokio::test]
+async fn test_session_info() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let bob_token = r.access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![bob_pubkey.clone()],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+
+    let get_session_info = |token| {
+        server
+            .post("/get_session_info")
+            .authorization_bearer(token)
+            .json(&frostd::GetSessionInfoArgs { session_id })
+    };
+
+    let res = get_session_info(alice_token).await;
+    res.assert_status_ok();
+    let r: frostd::GetSessionInfoOutput = res.json();
+    assert!(r.created_at > 0);
+    assert!(r.expires_at > r.created_at);
+    assert_eq!(r.participants.len(), 2);
+    let (alice, bob) = (&r.participants[0], &r.participants[1]);
+    assert_eq!(alice.pubkey, alice_pubkey);
+    assert!(alice.last_seen.is_some());
+    assert_eq!(alice.messages_sent, 1);
+    assert_eq!(alice.messages_pending, 0);
+    assert_eq!(bob.pubkey, bob_pubkey);
+    assert!(bob.last_seen.is_none());
+    assert_eq!(bob.messages_sent, 0);
+    assert_eq!(bob.messages_pending, 1);
+
+    // Once Bob receives the message, he has been seen and has nothing
+    // pending.
+    let res = server
+        .post("/receive")
+        .authorization_bearer(bob_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let res = get_session_info(alice_token).await;
+    res.assert_status_ok();
+    let r: frostd::GetSessionInfoOutput = res.json();
+    let bob = &r.participants[1];
+    assert!(bob.last_seen.is_some());
+    assert_eq!(bob.messages_pending, 0);
+
+    Ok(())
+}
+
+/// Test if participants can accept and decline sessions, and if declines are
+/// delivered to the other members of the session.
+#[tokio::test]
+async fn test_invitations() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let mut tokens = Vec::new();
+    let mut pubkeys = Vec::new();
+    for _ in 0..3 {
+        let (privkey, pubkey) = Cipher::generate_keypair()?;
+        let res = login(&server, &privkey, &pubkey).await?;
+        res.assert_status_ok();
+        let r: frostd::LoginOutput = res.json();
+        tokens.push(r.access_token.unwrap());
+        pubkeys.push(pubkey);
+    }
+    let (alice_token, bob_token, carol_token) = (tokens[0], tokens[1], tokens[2]);
+    let carol_pubkey = pubkeys[2].clone();
+
+    // Alice coordinates a session with Bob and Carol.
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: pubkeys[1..].to_vec(),
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    let res = server
+        .post("/accept_session")
+        .authorization_bearer(bob_token)
+        .json(&frostd::AcceptSessionArgs { session_id })
+        .await;
+    res.assert_status_ok();
+
+    // Declining twice must succeed (e.g. if the first response was lost).
+    for _ in 0..2 {
+        let res = server
+            .post("/decline_session")
+            .authorization_bearer(carol_token)
+            .json(&frostd::DeclineSessionArgs {
+                session_id,
+                reason: Some("not today".to_string()),
+            })
+            .await;
+        res.assert_status_ok();
+    }
+
+    // Carol can no longer accept or take part in the session.
+    let res = server
+        .post("/accept_session")
+        .authorization_bearer(carol_token)
+        .json(&frostd::AcceptSessionArgs { session_id })
+        .await;
+    res.assert_status_bad_request();
+    let res = server
+        .post("/receive")
+        .authorization_bearer(carol_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::NOT_IN_SESSION);
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(carol_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ListSessionsOutput = res.json();
+    assert!(r.session_ids.is_empty());
+
+    // Nor can messages be sent to her, like to users not in the session (and
+    // then nothing is sent to the other recipients either).
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: pubkeys[1..].to_vec(),
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::NOT_IN_SESSION);
+
+    // Alice (as coordinator) and Bob are notified, exactly once.
+    let expected_event = frostd::SessionEvent::Declined {
+        pubkey: carol_pubkey.clone(),
+        reason: Some("not today".to_string()),
+    };
+    for (token, as_coordinator) in [(alice_token, true), (bob_token, false)] {
+        let res = server
+            .post("/receive")
+            .authorization_bearer(token)
+            .json(&frostd::ReceiveArgs {
+                session_id,
+                as_coordinator,
+                wait_timeout_ms: None,
+                after_seq: None,
+            })
+            .await;
+        res.assert_status_ok();
+        let r: frostd::ReceiveOutput = res.json();
+        assert_eq!(r.msgs.len(), 1);
+        assert_eq!(r.msgs[0].sender, carol_pubkey);
+        assert_eq!(r.msgs[0].event, Some(expected_event.clone()));
+    }
+
+    let res = server
+        .post("/get_session_info")
+        .authorization_bearer(alice_token)
+        .json(&frostd::GetSessionInfoArgs { session_id })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::GetSessionInfoOutput = res.json();
+    assert_eq!(
+        r.participants[0].invitation,
+        frostd::InvitationStatus::Accepted
+    );
+    assert_eq!(
+        r.participants[1].invitation,
+        frostd::InvitationStatus::Declined
+    );
+    assert_eq!(
+        r.participants[1].decline_reason.as_deref(),
+        Some("not today")
+    );
+
+    Ok(())
+}
+
+/// Test if aborting a session notifies the other members and stops it from
+/// being used.
+#[tokio::test]
+async fn test_abort() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let alice_token = r.access_token.unwrap();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    let bob_token = r.access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::CreateNewSessionOutput = res.json();
+    let session_id = r.session_id;
+
+    // Aborting twice must succeed (e.g. if the first response was lost).
+    for _ in 0..2 {
+        let res = server
+            .post("/abort_session")
+            .authorization_bearer(alice_token)
+            .json(&frostd::AbortSessionArgs {
+                session_id,
+                reason: frostd::AbortReason::Error,
+                message: Some("invalid commitments".to_string()),
+            })
+            .await;
+        res.assert_status_ok();
+    }
+
+    // Bob is notified once.
+    let res = server
+        .post("/receive")
+        .authorization_bearer(bob_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ReceiveOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    let event = r.msgs[0].event.clone().expect("must be an event");
+    assert_eq!(
+        event,
+        frostd::SessionEvent::Aborted {
+            pubkey: alice_pubkey.clone(),
+            by_coordinator: true,
+            reason: frostd::AbortReason::Error,
+            message: Some("invalid commitments".to_string()),
+        }
+    );
+    assert_eq!(
+        event.to_string(),
+        "session aborted by coordinator: error (invalid commitments)"
+    );
+
+    // The session can no longer be used nor is it listed.
+    let res = server
+        .post("/send")
+        .authorization_bearer(bob_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status(axum::http::StatusCode::GONE);
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::SESSION_ABORTED);
+    for token in [alice_token, bob_token] {
+        let res = server
+            .post("/list_sessions")
+            .authorization_bearer(token)
+            .await;
+        res.assert_status_ok();
+        let r: frostd::ListSessionsOutput = res.json();
+        assert!(r.session_ids.is_empty());
+    }
+
+    Ok(())
+}
+
+/// Test if members can broadcast messages, and if the server attests them
+/// once every participant has broadcast.
+#[tokio::test]
+async fn test_broadcast() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let mut users = Vec::new();
+    for _ in 0..4 {
+        let (privkey, pubkey) = Cipher::generate_keypair()?;
+        let res = login(&server, &privkey, &pubkey).await?;
+        res.assert_status_ok();
+        let token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+        users.push((pubkey, token));
+    }
+    let [(alice_pubkey, alice_token), (bob_pubkey, bob_token), (carol_pubkey, carol_token), (_, eve_token)] =
+        users.try_into().unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![
+                alice_pubkey.clone(),
+                bob_pubkey.clone(),
+                carol_pubkey.clone(),
+            ],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+
+    let broadcast = |token, msg: &[u8]| {
+        server
+            .post("/broadcast")
+            .authorization_bearer(token)
+            .json(&frostd::BroadcastArgs {
+                session_id,
+                msg: msg.to_vec(),
+            })
+    };
+    let get_broadcasts = |token| {
+        server
+            .post("/get_broadcasts")
+            .authorization_bearer(token)
+            .json(&frostd::GetBroadcastsArgs {
+                session_id,
+                wait_timeout_ms: None,
+            })
+    };
+
+    broadcast(alice_token, b"alice").await.assert_status_ok();
+    // Broadcasting the same message again has no effect, but it can't be
+    // changed.
+    broadcast(alice_token, b"alice").await.assert_status_ok();
+    let res = broadcast(alice_token, b"mallory").await;
+    res.assert_status_bad_request();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::INVALID_ARGUMENT);
+    // Only members can broadcast or get the broadcasts.
+    let res = broadcast(eve_token, b"eve").await;
+    res.assert_status_forbidden();
+    get_broadcasts(eve_token).await.assert_status_forbidden();
+
+    // No attestation until every participant has broadcast.
+    let res = get_broadcasts(bob_token).await;
+    res.assert_status_ok();
+    let r: frostd::GetBroadcastsOutput = res.json();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].sender, alice_pubkey);
+    assert_eq!(r.msgs[0].msg, b"alice");
+    assert!(r.attestation.is_none());
+
+    broadcast(bob_token, b"bob").await.assert_status_ok();
+    broadcast(carol_token, b"carol").await.assert_status_ok();
+
+    let mut digests = Vec::new();
+    for token in [alice_token, bob_token, carol_token] {
+        let res = get_broadcasts(token).await;
+        res.assert_status_ok();
+        let r: frostd::GetBroadcastsOutput = res.json();
+        assert_eq!(r.msgs.len(), 3);
+        let attestation = r.attestation.unwrap();
+        assert_eq!(attestation.session_id, session_id);
+        assert!(attestation.verify(&r.msgs));
+        digests.push(frostd::BroadcastAttestation::digest(
+            &session_id,
+            &attestation.hashes,
+        ));
+
+        // Tampering with a message is detected.
+        let mut msgs = r.msgs.clone();
+        msgs[0].msg.push(0);
+        assert!(!attestation.verify(&msgs));
+    }
+    assert!(digests.iter().all(|d| *d == digests[0]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_versioned_api() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    // The versioned API is not deprecated.
+    let res = server.post("/v1/info").await;
+    res.assert_status_ok();
+    assert!(res.maybe_header("deprecation").is_none());
+    let r: frostd::InfoOutput = res.json();
+    assert!(r.api_versions.iter().any(|v| v == "v1"));
+
+    // The unversioned API still works, but is deprecated.
+    let res = server.post("/info").await;
+    res.assert_status_ok();
+    assert_eq!(res.header("deprecation"), "true");
+
+    // Tokens from the unversioned API work with the versioned API.
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = server
+        .post("/v1/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(alice_token)
+        .await;
+    res.assert_status_ok();
+    assert_eq!(res.header("deprecation"), "true");
+    let r: frostd::ListSessionsOutput = res.json();
+    assert_eq!(r.session_ids, vec![session_id]);
+
+    // Health checks are not versioned.
+    server.get("/healthz").await.assert_status_ok();
+    server.get("/v1/healthz").await.assert_status_not_found();
+
+    Ok(())
+}
+
+/// Test if the OpenAPI document describes the endpoints served and the JSON
+/// they accept and return.
+#[tokio::test]
+async fn test_openapi() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let res = server.get("/openapi.json").await;
+    res.assert_status_ok();
+    let spec: serde_json::Value = res.json();
+    assert_eq!(spec, frostd::openapi::spec());
+
+    // Every endpoint described is served, and every schema referenced exists.
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/send"));
+    for (path, item) in paths {
+        let res = server.post(&format!("/v1{path}")).await;
+        assert_ne!(
+            res.status_code(),
+            axum::http::StatusCode::NOT_FOUND,
+            "{path}"
+        );
+        assert_ne!(
+            res.status_code(),
+            axum::http::StatusCode::METHOD_NOT_ALLOWED,
+            "{path}"
+        );
+        check_refs(&spec, item);
+    }
+    server
+        .post("/v1/not_an_endpoint")
+        .await
+        .assert_status_not_found();
+
+    // The JSON sent and returned matches the schemas.
+    let res = server.post("/v1/info").await;
+    check_schema(&spec, "InfoOutput", &res.json());
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    check_schema(&spec, "LoginOutput", &res.json());
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let args = frostd::CreateNewSessionArgs {
+        pubkeys: vec![alice_pubkey.clone()],
+        message_count: 1,
+        metadata: frostd::SessionMetadata {
+            group: Some(vec![1; 32]),
+            kind: Some(frostd::SessionKind::Signing),
+            ..Default::default()
+        },
+    };
+    check_schema(&spec, "CreateNewSessionArgs", &serde_json::to_value(&args)?);
+    let res = server
+        .post("/v1/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&args)
+        .await;
+    check_schema(&spec, "CreateNewSessionOutput", &res.json());
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let args = frostd::SendArgs {
+        session_id,
+        recipients: vec![alice_pubkey.clone()],
+        msg: b"hello".to_vec(),
+        msg_id: Some(Uuid::new_v4()),
+    };
+    check_schema(&spec, "SendArgs", &serde_json::to_value(&args)?);
+    server
+        .post("/v1/send")
+        .authorization_bearer(alice_token)
+        .json(&args)
+        .await
+        .assert_status_ok();
+    let args = frostd::ReceiveArgs {
+        session_id,
+        as_coordinator: true,
+        wait_timeout_ms: None,
+        after_seq: Some(0),
+    };
+    check_schema(&spec, "ReceiveArgs", &serde_json::to_value(&args)?);
+    let res = server
+        .post("/v1/receive")
+        .authorization_bearer(alice_token)
+        .json(&args)
+        .await;
+    check_schema(&spec, "ReceiveOutput", &res.json());
+    let res = server
+        .post("/v1/get_session_info")
+        .authorization_bearer(alice_token)
+        .json(&frostd::GetSessionInfoArgs { session_id })
+        .await;
+    check_schema(&spec, "GetSessionInfoOutput", &res.json());
+    let args = frostd::AbortSessionArgs {
+        session_id,
+        reason: frostd::AbortReason::Cancelled,
+        message: Some("bye".to_string()),
+    };
+    check_schema(&spec, "AbortSessionArgs", &serde_json::to_value(&args)?);
+    let res = server
+        .post("/v1/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![alice_pubkey.clone()],
+            msg: vec![0; frostd::MAX_MSG_SIZE + 1],
+            msg_id: None,
+        })
+        .await;
+    check_schema(&spec, "LowError", &res.json());
+
+    // Every variant of the enums matches its schema and round-trips. The
+    // matches fail to compile when a variant is added, so that it is added to
+    // the lists (and to the schemas).
+    use frostd::{AbortReason, InvitationStatus, SessionEvent, SessionKind};
+    for kind in [
+        SessionKind::Signing,
+        SessionKind::Dkg,
+        SessionKind::Refresh,
+        SessionKind::Other,
+    ] {
+        match kind {
+            SessionKind::Signing | SessionKind::Dkg | SessionKind::Refresh | SessionKind::Other => {
+            }
+        }
+        check_round_trip(&spec, "SessionKind", &kind);
+    }
+    for status in [
+        InvitationStatus::Pending,
+        InvitationStatus::Accepted,
+        InvitationStatus::Declined,
+    ] {
+        match status {
+            InvitationStatus::Pending | InvitationStatus::Accepted | InvitationStatus::Declined => {
+            }
+        }
+        check_round_trip(&spec, "InvitationStatus", &status);
+    }
+    for reason in [
+        AbortReason::Cancelled,
+        AbortReason::Error,
+        AbortReason::Other,
+    ] {
+        match reason {
+            AbortReason::Cancelled | AbortReason::Error | AbortReason::Other => {}
+        }
+        check_round_trip(&spec, "AbortReason", &reason);
+    }
+    // The unknown variants are only used to parse values added by newer
+    // servers, which never send them.
+    for event in [
+        SessionEvent::Declined {
+            pubkey: alice_pubkey.clone(),
+            reason: Some("busy".to_string()),
+        },
+        SessionEvent::Aborted {
+            pubkey: alice_pubkey.clone(),
+            by_coordinator: true,
+            reason: AbortReason::Error,
+            message: None,
+        },
+    ] {
+        match event {
+            SessionEvent::Declined { .. }
+            | SessionEvent::Aborted { .. }
+            | SessionEvent::Unknown => {}
+        }
+        check_round_trip(&spec, "SessionEvent", &event);
+    }
+    for error in [
+        frostd::Error::InvalidArgument("pubkeys".to_string()),
+        frostd::Error::Unauthorized,
+        frostd::Error::SessionNotFound,
+        frostd::Error::NotCoordinator,
+        frostd::Error::NotInSession,
+        frostd::Error::UnregisteredUser,
+        frostd::Error::NotAdmin,
+        frostd::Error::LimitExceeded("sessions".to_string()),
+        frostd::Error::IpNotAllowed,
+        frostd::Error::ShuttingDown,
+        frostd::Error::SessionAborted,
+        frostd::Error::TooLarge("msg".to_string()),
+    ] {
+        match error {
+            frostd::Error::InvalidArgument(_)
+            | frostd::Error::Unauthorized
+            | frostd::Error::SessionNotFound
+            | frostd::Error::NotCoordinator
+            | frostd::Error::NotInSession
+            | frostd::Error::UnregisteredUser
+            | frostd::Error::NotAdmin
+            | frostd::Error::LimitExceeded(_)
+            | frostd::Error::IpNotAllowed
+            | frostd::Error::ShuttingDown
+            | frostd::Error::SessionAborted
+            | frostd::Error::TooLarge(_)
+            | frostd::Error::Unknown => {}
+        }
+        check_round_trip(&spec, "Error", &error);
+    }
+
+    Ok(())
+}
+
+/// Check that the given value matches the schema with the given name when
+/// serialized, and that it is deserialized back to the same value.
+fn check_round_trip<T>(spec: &serde_json::Value, name: &str, value: &T)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let json = serde_json::to_value(value).unwrap();
+    check_schema(spec, name, &json);
+    assert_eq!(&serde_json::from_value::<T>(json).unwrap(), value);
+}
+
+/// Check that every schema referenced in the given part of the OpenAPI
+/// document exists.
+fn check_refs(spec: &serde_json::Value, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(r) = map.get("$ref") {
+                let name = r.as_str().unwrap().rsplit('/').next().unwrap();
+                let schema = &spec["components"]["schemas"][name];
+                assert!(!schema.is_null(), "missing schema {name}");
+                check_refs(spec, schema);
+            }
+            map.values().for_each(|v| check_refs(spec, v));
+        }
+        serde_json::Value::Array(values) => values.iter().for_each(|v| check_refs(spec, v)),
+        _ => {}
+    }
+}
+
+/// Check that the given JSON value matches the schema with the given name in
+/// the OpenAPI document. Only the properties of objects and the values of
+/// enums are checked.
+fn check_schema(spec: &serde_json::Value, name: &str, value: &serde_json::Value) {
+    fn matches(
+        spec: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &serde_json::Value,
+    ) -> bool {
+        if let Some(r) = schema["$ref"].as_str() {
+            let name = r.rsplit('/').next().unwrap();
+            return matches(spec, &spec["components"]["schemas"][name], value);
+        }
+        if let Some(schemas) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+            return value.is_null() || schemas.iter().any(|s| matches(spec, s, value));
+        }
+        match (schema["type"].as_str(), value) {
+            (Some("object"), serde_json::Value::Object(map)) => {
+                let properties = schema["properties"].as_object().unwrap();
+                let required = schema["required"].as_array().unwrap();
+                required
+                    .iter()
+                    .all(|r| map.contains_key(r.as_str().unwrap()))
+                    && map
+                        .iter()
+                        .all(|(k, v)| properties.get(k).is_some_and(|s| matches(spec, s, v)))
+            }
+            (Some("array"), serde_json::Value::Array(values)) => {
+                values.iter().all(|v| matches(spec, &schema["items"], v))
+            }
+            (Some("string"), serde_json::Value::String(_)) => schema["enum"]
+                .as_array()
+                .is_none_or(|values| values.contains(value)),
+            (Some("integer"), serde_json::Value::Number(n)) => n.is_u64(),
+            (Some("boolean"), serde_json::Value::Bool(_)) => true,
+            (None, _) => schema["const"].is_null() || schema["const"] == *value,
+            _ => false,
+        }
+    }
+    let schema = serde_json::json!({ "$ref": format!("#/components/schemas/{name}") });
+    assert!(
+        matches(spec, &schema, value),
+        "{value} does not match {name}"
+    );
+}
+
+/// Test if the metadata attached to a session is returned to its members,
+/// and if oversized metadata is rejected.
+#[tokio::test]
+async fn test_session_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+    let alice_token = login(&server, &alice_privkey, &alice_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .access_token
+        .unwrap();
+    let bob_token = login(&server, &bob_privkey, &bob_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .access_token
+        .unwrap();
+
+    let metadata = frostd::SessionMetadata {
+        group: Some(vec![0xab; 32]),
+        ciphersuite: Some("FROST-ED25519-SHA512-v1".to_string()),
+        kind: Some(frostd::SessionKind::Signing),
+        description: Some("Monthly payouts".to_string()),
+    };
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![bob_pubkey.clone()],
+            message_count: 1,
+            metadata: metadata.clone(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+
+    let res = server
+        .post("/get_session_info")
+        .authorization_bearer(bob_token)
+        .json(&frostd::GetSessionInfoArgs { session_id })
+        .await;
+    res.assert_status_ok();
+    let r = res.json::<frostd::GetSessionInfoOutput>();
+    assert_eq!(r.metadata, metadata);
+
+    // Sessions created without metadata (e.g. by older clients) have none.
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&serde_json::json!({
+            "pubkeys": [bob_pubkey],
+            "message_count": 1,
+        }))
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = server
+        .post("/get_session_info")
+        .authorization_bearer(bob_token)
+        .json(&frostd::GetSessionInfoArgs { session_id })
+        .await;
+    res.assert_status_ok();
+    let r = res.json::<frostd::GetSessionInfoOutput>();
+    assert!(r.metadata.is_empty());
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![bob_pubkey.clone()],
+            message_count: 1,
+            metadata: frostd::SessionMetadata {
+                description: Some("a".repeat(frostd::MAX_METADATA_LEN + 1)),
+                ..Default::default()
+            },
+        })
+        .await;
+    res.assert_status(axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+    Ok(())
+}
+
+/// Test if responses include the request ID, generating it if the client did
+/// not send one.
+#[tokio::test]
+async fn test_request_id() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let res = server.post("/v1/info").await;
+    res.assert_status_ok();
+    let id = res.header("x-request-id");
+    Uuid::parse_str(id.to_str()?)?;
+    let res = server.post("/v1/info").await;
+    assert_ne!(res.header("x-request-id"), id);
+
+    // IDs sent by clients (or reverse proxies) are kept.
+    let res = server
+        .post("/v1/info")
+        .add_header(
+            axum::http::HeaderName::from_static("x-request-id"),
+            axum::http::HeaderValue::from_static("my-request"),
+        )
+        .await;
+    res.assert_status_ok();
+    assert_eq!(res.header("x-request-id"), "my-request");
+
+    Ok(())
+}
+
+/// Test if login signatures are only accepted by the server they were made
+/// for.
+#[tokio::test]
+async fn test_login_server_identity() -> Result<(), Box<dyn std::error::Error>> {
+    let (privkey, pubkey) = Cipher::generate_keypair()?;
+    // Log in signing the given payload (created from the challenge).
+    async fn login_with(
+        server: &TestServer,
+        privkey: &frost_client::cipher::PrivateKey,
+        pubkey: &frostd::PublicKey,
+        payload: impl Fn(&Uuid) -> Vec<u8>,
+    ) -> axum_test::TestResponse {
+        let challenge = server
+            .post("/challenge")
+            .await
+            .json::<frostd::ChallengeOutput>()
+            .challenge;
+        let signature: [u8; 64] = privkey.sign(&payload(&challenge), thread_rng()).unwrap();
+        server
+            .post("/login")
+            .json(&frostd::LoginArgs {
+                challenge,
+                pubkey: pubkey.clone(),
+                signature: signature.to_vec(),
+            })
+            .await
+    }
+
+    // By default, the identity is taken from the request.
+    let server = TestServer::new(router(AppState::new().await?))?;
+    login_with(&server, &privkey, &pubkey, |c| {
+        frostd::login_payload(TEST_SERVER_IDENTITY, c)
+    })
+    .await
+    .assert_status_ok();
+    login_with(&server, &privkey, &pubkey, |c| {
+        frostd::login_payload("evil.example.com:443", c)
+    })
+    .await
+    .assert_status_unauthorized();
+    // Signatures of just the challenge are not accepted by default.
+    login_with(&server, &privkey, &pubkey, |c| c.as_bytes().to_vec())
+        .await
+        .assert_status_unauthorized();
+
+    let server = TestServer::new(router(
+        AppState::with_options(AppStateOptions {
+            server_names: vec!["FROST.example.com".to_string()],
+            allow_legacy_login: true,
+            ..Default::default()
+        })
+        .await?,
+    ))?;
+    login_with(&server, &privkey, &pubkey, |c| {
+        frostd::login_payload("frost.example.com:443", c)
+    })
+    .await
+    .assert_status_ok();
+    // Only the configured names are accepted, regardless of the request.
+    login_with(&server, &privkey, &pubkey, |c| {
+        frostd::login_payload(TEST_SERVER_IDENTITY, c)
+    })
+    .await
+    .assert_status_unauthorized();
+    // A malicious server can't relay a signature made for it by forging the
+    // Host header.
+    let challenge = server
+        .post("/challenge")
+        .await
+        .json::<frostd::ChallengeOutput>()
+        .challenge;
+    let signature: [u8; 64] = privkey
+        .sign(
+            &frostd::login_payload("evil.example.com:443", &challenge),
+            thread_rng(),
+        )
+        .unwrap();
+    server
+        .post("/login")
+        .add_header(
+            axum::http::header::HOST,
+            axum::http::HeaderValue::from_static("evil.example.com"),
+        )
+        .json(&frostd::LoginArgs {
+            challenge,
+            pubkey: pubkey.clone(),
+            signature: signature.to_vec(),
+        })
+        .await
+        .assert_status_unauthorized();
+    login_with(&server, &privkey, &pubkey, |c| c.as_bytes().to_vec())
+        .await
+        .assert_status_ok();
+
+    Ok(())
+}
+
+/// Test if access tokens can be refreshed, and if signed access tokens are
+/// accepted by other servers with the same key until they are revoked.
+#[tokio::test]
+async fn test_refresh() -> Result<(), Box<dyn std::error::Error>> {
+    let (admin_privkey, admin_pubkey) = Cipher::generate_keypair()?;
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    // Tokens kept by the server.
+    let server = TestServer::new(router(AppState::new().await?))?;
+    let token = login(&server, &alice_privkey, &alice_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .token();
+    let res = server.post("/refresh").authorization_bearer(&token).await;
+    res.assert_status_ok();
+    let r: frostd::LoginOutput = res.json();
+    assert!(r.signed_access_token.is_none());
+    server
+        .post("/list_sessions")
+        .authorization_bearer(r.token())
+        .await
+        .assert_status_ok();
+    // The old token was revoked.
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_unauthorized();
+
+    // Signed tokens, with two servers sharing the same key.
+    let token_key = TokenKey::from_hex(&"42".repeat(32))?;
+    let options = || AppStateOptions {
+        users: UserRegistry::new([admin_pubkey.clone()].into(), None),
+        token_key: Some(token_key.clone()),
+        ..Default::default()
+    };
+    let server = TestServer::new(router(AppState::with_options(options()).await?))?;
+    let replica = TestServer::new(router(AppState::with_options(options()).await?))?;
+
+    let r: frostd::LoginOutput = login(&server, &alice_privkey, &alice_pubkey).await?.json();
+    // Only the signed token is issued.
+    assert!(r.access_token.is_none());
+    let token = r.signed_access_token.expect("server has a token key");
+    replica
+        .post("/list_sessions")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+    // Tampered tokens and tokens signed with other keys are rejected.
+    let mut tampered = token.clone().into_bytes();
+    tampered[2] = if tampered[2] == b'0' { b'1' } else { b'0' };
+    server
+        .post("/list_sessions")
+        .authorization_bearer(String::from_utf8(tampered)?)
+        .await
+        .assert_status_unauthorized();
+    let other = TestServer::new(router(
+        AppState::with_options(AppStateOptions {
+            token_key: Some(TokenKey::from_hex(&"43".repeat(32))?),
+            ..Default::default()
+        })
+        .await?,
+    ))?;
+    other
+        .post("/list_sessions")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_unauthorized();
+
+    // Refreshing revokes the old token, and logging out the new one.
+    let res = server.post("/refresh").authorization_bearer(&token).await;
+    res.assert_status_ok();
+    let new_token = res.json::<frostd::LoginOutput>().token();
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_unauthorized();
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&new_token)
+        .await
+        .assert_status_ok();
+    server
+        .post("/logout")
+        .authorization_bearer(&new_token)
+        .await
+        .assert_status_ok();
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&new_token)
+        .await
+        .assert_status_unauthorized();
+
+    // Administrators can revoke all the signed tokens of a user.
+    let admin_token = login(&server, &admin_privkey, &admin_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .token();
+    let alice_token = login(&server, &alice_privkey, &alice_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .token();
+    server
+        .post("/admin/revoke_tokens")
+        .authorization_bearer(&admin_token)
+        .json(&frostd::AdminRevokeTokensArgs {
+            pubkey: alice_pubkey.clone(),
+        })
+        .await
+        .assert_status_ok();
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&alice_token)
+        .await
+        .assert_status_unauthorized();
+    // Tokens issued afterwards are valid.
+    let alice_token = login(&server, &alice_privkey, &alice_pubkey)
+        .await?
+        .json::<frostd::LoginOutput>()
+        .token();
+    server
+        .post("/list_sessions")
+        .authorization_bearer(&alice_token)
+        .await
+        .assert_status_ok();
+
+    Ok(())
+}
+
+/// Log in to the given test server, returning the response to the login call.
+async fn login(
+    server: &TestServer,
+    privkey: &frost_client::cipher::PrivateKey,
+    pubkey: &frostd::PublicKey,
+) -> Result<axum_test::TestResponse, Box<dyn std::error::Error>> {
+    let res = server.post("/challenge").await;
+    res.assert_status_ok();
+    let r: frostd::ChallengeOutput = res.json();
+    let signature: [u8; 64] = privkey.sign(
+        &frostd::login_payload(TEST_SERVER_IDENTITY, &r.challenge),
+        thread_rng(),
+    )?;
+    Ok(server
+        .post("/login")
+        .json(&frostd::LoginArgs {
+            challenge: r.challenge,
+            pubkey: pubkey.clone(),
+            signature: signature.to_vec(),
+        })
+        .await)
+}
+
+/// Test if only users in the allowlist can log in, and if administrators can
+/// change the allowlist.
+#[tokio::test]
+async fn test_allowlist() -> Result<(), Box<dyn std::error::Error>> {
+    let (admin_privkey, admin_pubkey) = Cipher::generate_keypair()?;
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::with_options(AppStateOptions {
+        users: UserRegistry::new(
+            [admin_pubkey.clone()].into(),
+            Some([alice_pubkey.clone()].into()),
+        ),
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    // Alice is in the allowlist, Bob isn't.
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::UNREGISTERED_USER);
+
+    // Alice can't create a session with Bob.
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::UNREGISTERED_USER);
+
+    // Alice can't call the admin API.
+    let res = server
+        .post("/admin/add_user")
+        .authorization_bearer(alice_token)
+        .json(&frostd::AdminAddUserArgs {
+            pubkey: bob_pubkey.clone(),
+        })
+        .await;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::NOT_ADMIN);
+
+    // The admin can log in (without being in the allowlist) and add Bob.
+    let res = login(&server, &admin_privkey, &admin_pubkey).await?;
+    res.assert_status_ok();
+    let admin_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = server
+        .post("/admin/add_user")
+        .authorization_bearer(admin_token)
+        .json(&frostd::AdminAddUserArgs {
+            pubkey: bob_pubkey.clone(),
+        })
+        .await;
+    res.assert_status_ok();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+    let bob_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+
+    let res = server
+        .post("/admin/list_users")
+        .authorization_bearer(admin_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::AdminListUsersOutput = res.json();
+    let mut pubkeys = r.pubkeys.expect("allowlist is enabled");
+    pubkeys.sort_by_key(|p| p.0.clone());
+    let mut expected = vec![alice_pubkey.clone(), bob_pubkey.clone()];
+    expected.sort_by_key(|p| p.0.clone());
+    assert_eq!(pubkeys, expected);
+
+    // Removing Bob revokes his access token.
+    let res = server
+        .post("/admin/remove_user")
+        .authorization_bearer(admin_token)
+        .json(&frostd::AdminRemoveUserArgs {
+            pubkey: bob_pubkey.clone(),
+        })
+        .await;
+    res.assert_status_ok();
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(bob_token)
+        .await;
+    res.assert_status_unauthorized();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::UNAUTHORIZED);
+
+    Ok(())
+}
+
+/// Test the health endpoints and if shutting down rejects new sessions and
+/// wakes up long polling calls.
+#[tokio::test]
+async fn test_shutdown() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state.clone()))?;
+
+    server.get("/healthz").await.assert_status_ok();
+    server.get("/readyz").await.assert_status_ok();
+
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let create_session = || {
+        server
+            .post("/create_new_session")
+            .authorization_bearer(alice_token)
+            .json(&frostd::CreateNewSessionArgs {
+                pubkeys: vec![alice_pubkey.clone()],
+                message_count: 1,
+                metadata: Default::default(),
+            })
+    };
+    let res = create_session().await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+
+    // Shut down while a long polling call is waiting; it must return well
+    // before the timeout.
+    let start = std::time::Instant::now();
+    let receive = async {
+        server
+            .post("/receive")
+            .authorization_bearer(alice_token)
+            .json(&frostd::ReceiveArgs {
+                session_id,
+                as_coordinator: false,
+                wait_timeout_ms: Some(20_000),
+                after_seq: None,
+            })
+            .await
+    };
+    let shutdown = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shared_state.shutdown();
+    };
+    let (res, _) = tokio::join!(receive, shutdown);
+    res.assert_status_ok();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(shared_state.is_shutting_down());
+
+    server.get("/healthz").await.assert_status_ok();
+    server
+        .get("/readyz")
+        .await
+        .assert_status(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    let res = create_session().await;
+    res.assert_status(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::SHUTTING_DOWN);
+
+    Ok(())
+}
+
+/// Test if administrators can inspect and close sessions, and revoke access
+/// tokens.
+#[tokio::test]
+async fn test_admin_sessions() -> Result<(), Box<dyn std::error::Error>> {
+    let (admin_privkey, admin_pubkey) = Cipher::generate_keypair()?;
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::with_options(AppStateOptions {
+        users: UserRegistry::new([admin_pubkey.clone()].into(), None),
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let res = login(&server, &admin_privkey, &admin_pubkey).await?;
+    res.assert_status_ok();
+    let admin_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+    let bob_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![bob_pubkey.clone()],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+
+    // Regular users can't list all sessions.
+    let res = server
+        .post("/admin/list_sessions")
+        .authorization_bearer(alice_token)
+        .await;
+    res.assert_status_forbidden();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::NOT_ADMIN);
+
+    let res = server
+        .post("/admin/list_sessions")
+        .authorization_bearer(admin_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::AdminListSessionsOutput = res.json();
+    assert_eq!(r.sessions.len(), 1);
+    let session = &r.sessions[0];
+    assert_eq!(session.session_id, session_id);
+    assert_eq!(session.coordinator_pubkey, alice_pubkey);
+    assert_eq!(
+        session.pubkeys,
+        vec![alice_pubkey.clone(), bob_pubkey.clone()]
+    );
+    assert_eq!(session.queued_messages, 1);
+    assert_eq!(session.queued_bytes, 5);
+    assert!(session.expires_at > session.last_activity);
+
+    // The admin can close the session without being its coordinator.
+    let res = server
+        .post("/admin/close_session")
+        .authorization_bearer(admin_token)
+        .json(&frostd::AdminCloseSessionArgs { session_id })
+        .await;
+    res.assert_status_ok();
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(bob_token)
+        .await;
+    res.assert_status_ok();
+    let r: frostd::ListSessionsOutput = res.json();
+    assert!(r.session_ids.is_empty());
+    let res = server
+        .post("/admin/close_session")
+        .authorization_bearer(admin_token)
+        .json(&frostd::AdminCloseSessionArgs { session_id })
+        .await;
+    res.assert_status_not_found();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::SESSION_NOT_FOUND);
+
+    // Revoking Bob's tokens logs him out.
+    let res = server
+        .post("/admin/revoke_tokens")
+        .authorization_bearer(admin_token)
+        .json(&frostd::AdminRevokeTokensArgs {
+            pubkey: bob_pubkey.clone(),
+        })
+        .await;
+    res.assert_status_ok();
+    let r: frostd::AdminRevokeTokensOutput = res.json();
+    assert_eq!(r.revoked, 1);
+    let res = server
+        .post("/list_sessions")
+        .authorization_bearer(bob_token)
+        .await;
+    res.assert_status_unauthorized();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::UNAUTHORIZED);
+
+    Ok(())
+}
+
+/// Test if the rate limits and other limits are enforced.
+#[tokio::test]
+async fn test_limits() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (_bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::with_options(AppStateOptions {
+        limits: Limits {
+            logins_per_pubkey: 2,
+            sessions_per_coordinator: 1,
+            queued_messages_per_recipient: 2,
+            queued_bytes_per_recipient: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    // Only 2 logins per minute are allowed.
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    login(&server, &alice_privkey, &alice_pubkey)
+        .await?
+        .assert_status_ok();
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_too_many_requests();
+    let retry_after: u64 = res.header("retry-after").to_str()?.parse()?;
+    assert!(retry_after > 0 && retry_after <= 60);
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::LIMIT_EXCEEDED);
+    assert_eq!(r.details.retry_after_secs, Some(retry_after));
+
+    // Only 1 session can be coordinated at a time.
+    let create_session = || {
+        server
+            .post("/create_new_session")
+            .authorization_bearer(alice_token)
+            .json(&frostd::CreateNewSessionArgs {
+                pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+                message_count: 1,
+                metadata: Default::default(),
+            })
+    };
+    let res = create_session().await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = create_session().await;
+    res.assert_status_too_many_requests();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::LIMIT_EXCEEDED);
+
+    // Closing the session allows creating a new one.
+    let res = server
+        .post("/close_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CloseSessionArgs { session_id })
+        .await;
+    res.assert_status_ok();
+    let res = create_session().await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+
+    // The queue for Bob is limited to 2 messages and 10 bytes.
+    let send = |msg: &[u8]| {
+        server
+            .post("/send")
+            .authorization_bearer(alice_token)
+            .json(&frostd::SendArgs {
+                session_id,
+                recipients: vec![bob_pubkey.clone()],
+                msg: msg.to_vec(),
+                msg_id: None,
+            })
+    };
+    send(b"hello").await.assert_status_ok();
+    let res = send(b"world!").await;
+    res.assert_status_too_many_requests();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::LIMIT_EXCEEDED);
+    send(b"hi").await.assert_status_ok();
+    let res = send(b"").await;
+    res.assert_status_too_many_requests();
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::LIMIT_EXCEEDED);
+
+    Ok(())
+}
+
+/// Test if the metrics reflect the server activity.
+#[tokio::test]
+async fn test_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+    let (bob_privkey, bob_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::new().await?;
+    let server = TestServer::new(router(shared_state.clone()))?;
+    let metrics_server = TestServer::new(metrics_router(shared_state))?;
+
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    // Bob signs with the wrong key.
+    let res = login(&server, &alice_privkey, &bob_pubkey).await?;
+    res.assert_status_unauthorized();
+    let res = login(&server, &bob_privkey, &bob_pubkey).await?;
+    res.assert_status_ok();
+
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![alice_pubkey.clone(), bob_pubkey.clone()],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status_ok();
+    let res = server
+        .post("/receive")
+        .authorization_bearer(alice_token)
+        .json(&frostd::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await;
+    res.assert_status_ok();
+
+    let res = metrics_server.get("/metrics").await;
+    res.assert_status_ok();
+    let metrics = res.text();
+    for line in [
+        "frostd_logins_succeeded_total 2",
+        "frostd_logins_failed_total 1",
+        "frostd_sessions_created_total 1",
+        "frostd_active_sessions 1",
+        "frostd_messages_sent_total 2",
+        "frostd_messages_received_total 1",
+        "frostd_queued_messages 1",
+        "frostd_queued_bytes 5",
+        "frostd_access_tokens 2",
+        "frostd_http_request_duration_seconds_count{route=\"/send\",status=\"200\"} 1",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "missing {line} in metrics:\n{metrics}"
+        );
+    }
+
+    Ok(())
+}
+
+/// Test if the configuration file is read and overridden by the arguments.
+#[test]
+fn test_config() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("frostd.toml");
+    std::fs::write(
+        &path,
+        r#"
+        ip = "127.0.0.1"
+        port = 1234
+        log_level = "debug"
+        server_names = ["frost.example.com"]
+
+        [timeouts]
+        session_secs = 60
+
+        [limits]
+        max_msg_size = 1024
+        ip_denylist = ["10.0.0.0/8"]
+        "#,
+    )?;
+
+    let args = Args::try_parse_from([
+        "frostd",
+        "--config",
+        path.to_str().unwrap(),
+        "--port",
+        "4321",
+        "--challenge-timeout-secs",
+        "5",
+    ])?;
+    let config = Config::load(&args)?;
+    assert_eq!(config.ip, "127.0.0.1");
+    assert_eq!(config.port, 4321);
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.timeouts.session, Duration::from_secs(60));
+    assert_eq!(config.timeouts.challenge, Duration::from_secs(5));
+    assert_eq!(
+        config.timeouts.access_token,
+        Timeouts::default().access_token
+    );
+    assert_eq!(config.limits.max_msg_size, 1024);
+    assert_eq!(
+        config.limits.ip_denylist,
+        vec!["10.0.0.0/8".parse::<IpRange>()?]
+    );
+
+    // Invalid values are rejected.
+    let args = Args::try_parse_from(["frostd", "--max-msg-size", "100000"])?;
+    assert!(Config::load(&args).is_err());
+    // The server names are required, except for development.
+    let args = Args::try_parse_from(["frostd"])?;
+    assert!(Config::load(&args).is_err());
+    let args = Args::try_parse_from(["frostd", "--dev-tls"])?;
+    assert!(Config::load(&args).is_ok());
+    std::fs::write(&path, "unknown_option = 1")?;
+    let args = Args::try_parse_from(["frostd", "--config", path.to_str().unwrap()])?;
+    assert!(Config::load(&args).is_err());
+
+    // The admin command connects either with a client certificate and its
+    // key, or through a Unix domain socket.
+    for (options, ok) in [
+        ("--tls-client-cert cert.pem --tls-client-key key.pem", true),
+        ("--unix-socket frostd.sock", true),
+        ("--tls-client-cert cert.pem", false),
+        ("--unix-socket frostd.sock --tls-client-key key.pem", false),
+    ] {
+        let args = format!("frostd admin -s localhost:2744 {options} list-sessions");
+        assert_eq!(Args::try_parse_from(args.split_whitespace()).is_ok(), ok);
+    }
+
+    Ok(())
+}
+
+/// Test if the info API returns the configured limits, and if they are
+/// enforced.
+#[tokio::test]
+async fn test_info() -> Result<(), Box<dyn std::error::Error>> {
+    let (alice_privkey, alice_pubkey) = Cipher::generate_keypair()?;
+
+    let shared_state = AppState::with_options(AppStateOptions {
+        limits: Limits {
+            max_msg_size: 16,
+            ..Default::default()
+        },
+        timeouts: Timeouts {
+            session: Duration::from_secs(120),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await?;
+    let server = TestServer::new(router(shared_state))?;
+
+    let res = server.post("/info").await;
+    res.assert_status_ok();
+    let r: frostd::InfoOutput = res.json();
+    assert_eq!(r.max_msg_size, 16);
+    assert_eq!(r.session_timeout_secs, 120);
+    assert_eq!(r.max_wait_timeout_ms, frostd::MAX_WAIT_TIMEOUT_MS);
+    assert_eq!(r.api_versions, frostd::API_VERSIONS);
+    assert!(r
+        .features
+        .iter()
+        .any(|f| f == frostd::features::ACKNOWLEDGEMENTS));
+
+    let res = login(&server, &alice_privkey, &alice_pubkey).await?;
+    res.assert_status_ok();
+    let alice_token = res.json::<frostd::LoginOutput>().access_token.unwrap();
+    let res = server
+        .post("/create_new_session")
+        .authorization_bearer(alice_token)
+        .json(&frostd::CreateNewSessionArgs {
+            pubkeys: vec![alice_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await;
+    res.assert_status_ok();
+    let session_id = res.json::<frostd::CreateNewSessionOutput>().session_id;
+    let res = server
+        .post("/send")
+        .authorization_bearer(alice_token)
+        .json(&frostd::SendArgs {
+            session_id,
+            recipients: vec![alice_pubkey.clone()],
+            msg: [0; 17].to_vec(),
+            msg_id: None,
+        })
+        .await;
+    res.assert_status(axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    let r: frostd::LowError = res.json();
+    assert_eq!(r.code, frostd::TOO_LARGE);
+    assert_eq!(r.details.field.as_deref(), Some("msg"));
+
+    Ok(())
+}
+
+#[test]
+fn test_ip_range() -> Result<(), Box<dyn Error>> {
+    let range: IpRange = "10.1.0.0/16".parse()?;
+    assert!(range.contains("10.1.2.3".parse()?));
+    assert!(!range.contains("10.2.0.1".parse()?));
+    assert!(!range.contains("::1".parse()?));
+
+    let range: IpRange = "0.0.0.0/0".parse()?;
+    assert!(range.contains("192.168.0.1".parse()?));
+
+    let range: IpRange = "fd00::/8".parse()?;
+    assert!(range.contains("fd12::1".parse()?));
+    assert!(!range.contains("fe80::1".parse()?));
+
+    let range: IpRange = "127.0.0.1".parse()?;
+    assert!(range.contains("127.0.0.1".parse()?));
+    assert!(!range.contains("127.0.0.2".parse()?));
+
+    assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    assert!("not an ip".parse::<IpRange>().is_err());
+
+    Ok(())
+}
+
 #[test]
 fn test_snow() -> Result<(), Box<dyn Error>> {
     let builder = snow::Builder::new("Noise_K_25519_ChaChaPoly_BLAKE2s".parse().unwrap());
@@ -201,3 +201,76 @@ async fn trusted_dealer_journey() {
         .is_ok();
     assert!(is_signature_valid);
 }
+
+#[tokio::test]
+async fn frostd_test_server() -> Result<(), Box<dyn std::error::Error>> {
+    use frost_client::{api, cipher::Cipher, client::Client};
+
+    let server = frostd::testing::Server::spawn().await?;
+
+    async fn login(client: &mut Client) -> Result<api::PublicKey, Box<dyn std::error::Error>> {
+        let (privkey, pubkey) = Cipher::generate_keypair()?;
+        let challenge = client.challenge().await?.challenge;
+        let payload = client.login_payload(&challenge).await?;
+        let signature: [u8; 64] = privkey.sign(&payload, &mut thread_rng())?;
+        client
+            .login(&api::LoginArgs {
+                challenge,
+                pubkey: pubkey.clone(),
+                signature: signature.to_vec(),
+            })
+            .await?;
+        Ok(pubkey)
+    }
+
+    let mut coordinator = server.client()?;
+    login(&mut coordinator).await?;
+    let mut participant = server.client()?;
+    let participant_pubkey = login(&mut participant).await?;
+
+    let session_id = coordinator
+        .create_new_session(&api::CreateNewSessionArgs {
+            pubkeys: vec![participant_pubkey.clone()],
+            message_count: 1,
+            metadata: Default::default(),
+        })
+        .await?
+        .session_id;
+    assert_eq!(
+        participant.list_sessions().await?.session_ids,
+        vec![session_id]
+    );
+
+    coordinator
+        .send(&api::SendArgs {
+            session_id,
+            recipients: vec![participant_pubkey],
+            msg: b"hello".to_vec(),
+            msg_id: None,
+        })
+        .await?;
+    let r = participant
+        .receive(&api::ReceiveArgs {
+            session_id,
+            as_coordinator: false,
+            wait_timeout_ms: None,
+            after_seq: None,
+        })
+        .await?;
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].msg, b"hello");
+
+    // The server stops when dropped.
+    drop(server);
+    let mut stopped = false;
+    for _ in 0..20 {
+        if coordinator.challenge().await.is_err() {
+            stopped = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(stopped);
+
+    Ok(())
+}